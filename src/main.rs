@@ -3,11 +3,20 @@
 // WITH AUTO API KEY SETUP + ADVANCED PROJECT TRANSPILER
 // ==========================================================
 
-use std::collections::HashMap;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
 use std::env;
+use std::fmt;
 use std::fs;
-use std::io::{self, Write};
+use std::io::{self, IsTerminal, Write};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+use clap::{Parser, Subcommand};
+use log::{debug, error, info, warn, LevelFilter};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
 use serde_json::json;
 
 // ----------------------------------------------------------
@@ -15,14 +24,14 @@ use serde_json::json;
 // ----------------------------------------------------------
 fn install_panic_hook() {
     std::panic::set_hook(Box::new(|info| {
-        println!("\n======================================");
-        println!("           PANIC OCCURRED");
-        println!("======================================");
-        println!("{:?}", info);
+        eprintln!("\n======================================");
+        eprintln!("           PANIC OCCURRED");
+        eprintln!("======================================");
+        eprintln!("{:?}", info);
 
         #[cfg(target_os = "windows")]
         {
-            println!("\nPress any key to exit...");
+            eprintln!("\nPress any key to exit...");
             let _ = std::process::Command::new("cmd")
                 .args(&["/C", "pause"])
                 .status();
@@ -30,13 +39,70 @@ fn install_panic_hook() {
 
         #[cfg(not(target_os = "windows"))]
         {
-            println!("\nPress ENTER to exit...");
+            eprintln!("\nPress ENTER to exit...");
             let mut s = String::new();
             let _ = io::stdin().read_line(&mut s);
         }
     }));
 }
 
+// ----------------------------------------------------------
+// LOGGING ([CONVERT] 같은 태그는 그대로, error/warn 은 stderr)
+// ----------------------------------------------------------
+struct ConsoleLogger;
+
+impl log::Log for ConsoleLogger {
+    fn enabled(&self, m: &log::Metadata) -> bool {
+        // 의존 crate (reqwest 등) 의 info/debug 는 섞지 않음
+        m.level() <= log::max_level() && (m.level() <= log::Level::Warn || m.target().starts_with(module_path!()))
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        // 진행 막대가 떠 있으면 지우고 찍은 뒤 다시 그림 (로그는 막대 위로 쌓임)
+        let bar = PROGRESS_LINE.lock().ok();
+        let drawn = bar.as_ref().and_then(|b| b.as_deref());
+        if drawn.is_some() {
+            eprint!("\r\x1b[2K");
+        }
+        if record.level() <= log::Level::Warn {
+            eprintln!("{}", record.args());
+        } else {
+            println!("{}", record.args());
+        }
+        if let Some(line) = drawn {
+            let _ = io::stdout().flush();
+            eprint!("{}", line);
+        }
+    }
+
+    fn flush(&self) {
+        let _ = io::stdout().flush();
+        let _ = io::stderr().flush();
+    }
+}
+
+static LOGGER: ConsoleLogger = ConsoleLogger;
+
+// --quiet / --verbose 가 우선, 없으면 RUST_LOG (error, warn, info, debug, trace, off), 기본 info
+fn log_level(quiet: bool, verbose: u8) -> LevelFilter {
+    if quiet {
+        return LevelFilter::Warn;
+    }
+    match verbose {
+        0 => env::var("RUST_LOG").ok().and_then(|v| v.trim().parse().ok()).unwrap_or(LevelFilter::Info),
+        1 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
+
+fn init_logging(level: LevelFilter) {
+    let _ = log::set_logger(&LOGGER);
+    log::set_max_level(level);
+}
+
 // ==========================================================
 // 2) AUTO LOAD OR CREATE API KEY
 // ==========================================================
@@ -60,7 +126,15 @@ fn load_or_create_api_key() -> String {
         }
     }
 
-    // 3) 없으면 사용자 입력
+    // 3) 없으면 사용자 입력. stdin 이 터미널이 아니면 (CI, 파이프, 하위 프로세스) 기다리지 않음.
+    //    파이프면 (file -) 소스를 key 로 읽어버리기도 함. 빈 key 는 요청 시 MissingApiKey 로 실패
+    if !io::stdin().is_terminal() {
+        error!(
+            "No API key and no interactive terminal: set OPENAI_API_KEY in the environment or .env \
+             (or use --offline)"
+        );
+        return "".into();
+    }
     println!("=================================================");
     println!(" OPENAI_API_KEY not found.");
     println!(" Please enter your OpenAI API Key:");
@@ -70,28 +144,69 @@ fn load_or_create_api_key() -> String {
     io::stdout().flush().unwrap();
 
     let mut key = String::new();
-    io::stdin().read_line(&mut key).unwrap();
+    if let Err(e) = io::stdin().read_line(&mut key) {
+        error!("[ERROR] cannot read API KEY: {}", e);
+        return "".into();
+    }
     let key = key.trim().to_string();
 
     if key.is_empty() {
-        println!("ERROR: API KEY cannot be empty. Exiting...");
+        error!("[ERROR] API KEY cannot be empty. Exiting...");
         return "".into();
     }
 
-    // 저장
-    let env_file = format!("OPENAI_API_KEY={}", key);
-    fs::write(".env", env_file).unwrap();
-
-    println!("API KEY saved to .env.");
+    // 저장 (.env 의 다른 변수 / 주석은 그대로)
+    let existing = fs::read_to_string(".env").unwrap_or_default();
+    match fs::write(".env", upsert_env_line(&existing, "OPENAI_API_KEY", &key)) {
+        Ok(()) => println!("API KEY saved to .env."),
+        Err(e) => warn!("[WARN] cannot save API KEY to .env: {}", e),
+    }
     key
 }
 
+// .env 내용에서 KEY= 줄만 바꾸거나 끝에 추가. 줄바꿈 방식 (\n / \r\n) 과 마지막 줄바꿈 유무는 유지
+fn upsert_env_line(content: &str, name: &str, value: &str) -> String {
+    let newline = if content.contains("\r\n") { "\r\n" } else { "\n" };
+    let trailing = content.is_empty() || content.ends_with('\n');
+    let entry = format!("{}={}", name, value);
+    let is_entry = |line: &str| {
+        let line = line.trim_start();
+        let line = line.strip_prefix("export ").map(str::trim_start).unwrap_or(line);
+        line.strip_prefix(name).is_some_and(|rest| rest.trim_start().starts_with('='))
+    };
+
+    let mut lines = Vec::new();
+    let mut replaced = false;
+    for line in content.lines() {
+        if is_entry(line) {
+            // 중복된 키 줄은 첫 번째만 남김
+            if !replaced {
+                lines.push(entry.clone());
+                replaced = true;
+            }
+        } else {
+            lines.push(line.to_string());
+        }
+    }
+    if !replaced {
+        lines.push(entry);
+    }
+    let mut out = lines.join(newline);
+    if trailing {
+        out.push_str(newline);
+    }
+    out
+}
+
 // ==========================================================
 // AST STRUCTURES
 // ==========================================================
 #[derive(Debug, Clone)]
 pub enum NodeKind {
     Identifier(String),
+    /// 소수점 없이 쓴 숫자 (42)
+    Integer(i64),
+    /// 소수점이 있는 숫자 (42.0, 0.5)
     Number(f64),
     BinaryOp { op: String, left: Box<Node>, right: Box<Node> },
     Function { name: String, args: Vec<String>, body: Vec<Node> },
@@ -108,329 +223,8952 @@ impl Node {
     pub fn new(kind: NodeKind) -> Self {
         Self { kind, meta: HashMap::new() }
     }
+
+    // 트리 구조만 비교 (meta 는 분석 결과라 무시)
+    pub fn structurally_eq(&self, other: &Node) -> bool {
+        match (&self.kind, &other.kind) {
+            (NodeKind::Identifier(a), NodeKind::Identifier(b)) => a == b,
+            (NodeKind::Integer(a), NodeKind::Integer(b)) => a == b,
+            (NodeKind::Number(a), NodeKind::Number(b)) => a == b,
+            (
+                NodeKind::BinaryOp { op: oa, left: la, right: ra },
+                NodeKind::BinaryOp { op: ob, left: lb, right: rb },
+            ) => oa == ob && la.structurally_eq(lb) && ra.structurally_eq(rb),
+            (
+                NodeKind::Function { name: na, args: aa, body: ba },
+                NodeKind::Function { name: nb, args: ab, body: bb },
+            ) => {
+                na == nb
+                    && aa == ab
+                    && ba.len() == bb.len()
+                    && ba.iter().zip(bb).all(|(x, y)| x.structurally_eq(y))
+            }
+            (NodeKind::Unknown, NodeKind::Unknown) => true,
+            _ => false,
+        }
+    }
+
+    // finding 등에 붙이는 한 줄 요약 (자식은 생략)
+    pub fn summary(&self) -> String {
+        match &self.kind {
+            NodeKind::Identifier(name) => format!("identifier {}", name),
+            NodeKind::Integer(n) => format!("integer {}", n),
+            NodeKind::Number(n) => format!("number {}", n),
+            NodeKind::BinaryOp { op, .. } => format!("binary op {}", op),
+            NodeKind::Function { name, args, .. } => format!("fn {}({})", name, args.join(", ")),
+            NodeKind::Unknown => "unknown node".into(),
+        }
+    }
 }
 
-// ==========================================================
-// LLM BASE TRAIT
-// ==========================================================
-pub trait LLM {
-    fn predict(&self, prompt: &str) -> String;
+// 연산자 우선순위 (AstParser 와 같음. 파서가 모르는 연산자는 가장 낮게)
+fn op_precedence(op: &str) -> u8 {
+    match op {
+        "*" | "/" | "%" => 2,
+        "+" | "-" => 1,
+        _ => 0,
+    }
+}
+
+// AstParser 문법으로 출력. 다시 파싱하면 structurally_eq 인 트리 (괄호는 필요한 곳에만)
+impl fmt::Display for Node {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            NodeKind::Identifier(x) => write!(f, "{}", x),
+            NodeKind::Integer(n) => write!(f, "{}", n),
+            // 소수점을 항상 남겨야 Integer 로 다시 읽히지 않음
+            NodeKind::Number(n) if n.fract() == 0.0 => write!(f, "{:.1}", n),
+            NodeKind::Number(n) => write!(f, "{}", n),
+            NodeKind::BinaryOp { op, left, right } => {
+                let prec = op_precedence(op);
+                // 왼쪽 결합이므로 오른쪽은 같은 우선순위도 괄호
+                let wrap = |child: &Node, strict: bool| match &child.kind {
+                    NodeKind::BinaryOp { op: inner, .. } => {
+                        let p = op_precedence(inner);
+                        p < prec || (strict && p == prec)
+                    }
+                    // 음수 리터럴은 "a - -1" 처럼 그대로 읽힘
+                    _ => false,
+                };
+                let side = |child: &Node, strict: bool| {
+                    if wrap(child, strict) { format!("({})", child) } else { child.to_string() }
+                };
+                write!(f, "{} {} {}", side(left, false), op, side(right, true))
+            }
+            NodeKind::Function { name, args, body } => {
+                write!(f, "fn {}({}) {{", name, args.join(", "))?;
+                for (i, stmt) in body.iter().enumerate() {
+                    write!(f, "{}{}", if i == 0 { " " } else { "; " }, stmt)?;
+                }
+                write!(f, "{}}}", if body.is_empty() { "" } else { " " })
+            }
+            NodeKind::Unknown => write!(f, "<unknown>"),
+        }
+    }
 }
 
 // ==========================================================
-// REAL OPENAI CLIENT
+// PARSER (작은 표현식 문법 -> Node)
 // ==========================================================
-#[derive(Clone)]
-pub struct RealLLM {
-    pub api_key: String,
+// program  := item*
+// item     := "fn" IDENT "(" params? ")" "{" (expr ";"?)* "}" | expr ";"?
+// expr     := term (("+" | "-") term)*
+// term     := unary (("*" | "/" | "%") unary)*
+// unary    := "-" unary | NUMBER | IDENT | "(" expr ")"
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub message: String,
+    /// 1 부터
+    pub line: usize,
+    pub col: usize,
 }
 
-impl RealLLM {
-    pub fn new() -> Self {
-        // AUTO API KEY SYSTEM 사용
-        let key = load_or_create_api_key();
-        Self { api_key: key }
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.col, self.message)
     }
+}
 
-    fn request(&self, prompt: &str) -> String {
-        if self.api_key.is_empty() {
-            return "(ERROR: OPENAI_API_KEY missing.)".into();
-        }
+impl std::error::Error for ParseError {}
 
-        let client = reqwest::blocking::Client::new();
+#[derive(Debug, Clone, PartialEq)]
+enum Tok {
+    Integer(i64),
+    Number(f64),
+    Ident(String),
+    Fn,
+    Punct(char),
+    Eof,
+}
 
-        let body = json!({
-            "model": "gpt-4.1",
-            "messages": [
-                { "role": "user", "content": prompt }
-            ]
-        });
+impl fmt::Display for Tok {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Tok::Integer(n) => write!(f, "number {}", n),
+            Tok::Number(n) => write!(f, "number {}", n),
+            Tok::Ident(x) => write!(f, "identifier '{}'", x),
+            Tok::Fn => write!(f, "'fn'"),
+            Tok::Punct(c) => write!(f, "'{}'", c),
+            Tok::Eof => write!(f, "end of input"),
+        }
+    }
+}
 
-        let res = client
-            .post("https://api.openai.com/v1/chat/completions")
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .json(&body)
-            .send();
+// (토큰, 줄, 열)
+fn tokenize(src: &str) -> Result<Vec<(Tok, usize, usize)>, ParseError> {
+    let mut toks = Vec::new();
+    let mut chars = src.chars().peekable();
+    let (mut line, mut col) = (1, 1);
 
-        match res {
-            Ok(r) => {
-                let v: serde_json::Value = r.json().unwrap_or(json!({}));
-                v["choices"][0]["message"]["content"]
-                    .as_str()
-                    .unwrap_or("(EMPTY)")
-                    .to_string()
+    while let Some(&c) = chars.peek() {
+        let (start_line, start_col) = (line, col);
+        let mut take = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+            let c = chars.next();
+            if c == Some('\n') {
+                line += 1;
+                col = 1;
+            } else {
+                col += 1;
+            }
+            c
+        };
+        if c.is_whitespace() {
+            take(&mut chars);
+        } else if c == '/' && chars.clone().nth(1) == Some('/') {
+            // 줄 주석
+            while chars.peek().is_some_and(|&c| c != '\n') {
+                take(&mut chars);
+            }
+        } else if c.is_ascii_digit() || c == '.' {
+            let mut text = String::new();
+            while chars.peek().is_some_and(|c| c.is_ascii_digit() || *c == '.' || *c == '_') {
+                text.extend(take(&mut chars));
             }
-            Err(e) => format!("(API ERROR: {})", e),
+            let digits = text.replace('_', "");
+            // 소수점이 없으면 정수 (i64 를 넘으면 f64 로)
+            let tok = match digits.parse::<i64>() {
+                Ok(n) if !digits.contains('.') => Tok::Integer(n),
+                _ => Tok::Number(digits.parse().map_err(|_| ParseError {
+                    message: format!("invalid number '{}'", text),
+                    line: start_line,
+                    col: start_col,
+                })?),
+            };
+            toks.push((tok, start_line, start_col));
+        } else if c.is_alphabetic() || c == '_' {
+            let mut text = String::new();
+            while chars.peek().is_some_and(|c| c.is_alphanumeric() || *c == '_') {
+                text.extend(take(&mut chars));
+            }
+            let tok = if text == "fn" { Tok::Fn } else { Tok::Ident(text) };
+            toks.push((tok, start_line, start_col));
+        } else if "+-*/%(){},;".contains(c) {
+            take(&mut chars);
+            toks.push((Tok::Punct(c), start_line, start_col));
+        } else {
+            return Err(ParseError { message: format!("unexpected character '{}'", c), line, col });
         }
     }
+    toks.push((Tok::Eof, line, col));
+    Ok(toks)
 }
 
-impl LLM for RealLLM {
-    fn predict(&self, prompt: &str) -> String {
-        self.request(prompt)
-    }
+pub struct AstParser {
+    toks: Vec<(Tok, usize, usize)>,
+    pos: usize,
 }
 
-// ==========================================================
-// VERSION AI
-// ==========================================================
-pub struct VersionAI {
-    map: HashMap<String, Vec<&'static str>>,
-}
+impl AstParser {
+    pub fn new(src: &str) -> Result<Self, ParseError> {
+        Ok(Self { toks: tokenize(src)?, pos: 0 })
+    }
 
-impl VersionAI {
-    pub fn new() -> Self {
-        let mut m = HashMap::new();
-        m.insert("go".into(), vec!["1.21"]);
-        m.insert("cpp".into(), vec!["23"]);
-        m.insert("swift".into(), vec!["6.0"]);
-        Self { map: m }
+    pub fn parse(src: &str) -> Result<Vec<Node>, ParseError> {
+        Self::new(src)?.parse_program()
     }
 
-    pub fn infer(&self, lang: &str, node: &Node) -> String {
-        if lang == "go" {
-            if node.meta.get("uses_generics") == Some(&"true".to_string()) {
-                return "1.21".into();
+    fn peek(&self) -> &Tok {
+        &self.toks[self.pos].0
+    }
+
+    fn next(&mut self) -> Tok {
+        let tok = self.toks[self.pos].0.clone();
+        if tok != Tok::Eof {
+            self.pos += 1;
+        }
+        tok
+    }
+
+    fn error(&self, message: String) -> ParseError {
+        let (_, line, col) = self.toks[self.pos];
+        ParseError { message, line, col }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), ParseError> {
+        match self.peek() {
+            Tok::Punct(p) if *p == c => {
+                self.next();
+                Ok(())
             }
+            other => Err(self.error(format!("expected '{}', found {}", c, other))),
         }
-        self.map
-            .get(lang)
-            .and_then(|v| v.last())
-            .unwrap_or(&"unknown")
-            .to_string()
     }
-}
 
-// ==========================================================
-// SEMANTIC ENGINE
-// ==========================================================
-pub struct SemanticEngine;
+    fn ident(&mut self, what: &str) -> Result<String, ParseError> {
+        match self.peek().clone() {
+            Tok::Ident(x) => {
+                self.next();
+                Ok(x)
+            }
+            other => Err(self.error(format!("expected {}, found {}", what, other))),
+        }
+    }
 
-pub struct SemanticInfo {
-    pub meaning: String,
-}
+    fn skip_semicolon(&mut self) {
+        if *self.peek() == Tok::Punct(';') {
+            self.next();
+        }
+    }
 
-impl SemanticEngine {
-    pub fn analyze(&self, node: &Node) -> SemanticInfo {
-        match &node.kind {
-            NodeKind::Identifier(x) => SemanticInfo {
-                meaning: format!("identifier '{}'", x),
-            },
-            _ => SemanticInfo { meaning: "unknown".into() }
+    pub fn parse_program(&mut self) -> Result<Vec<Node>, ParseError> {
+        let mut nodes = Vec::new();
+        while *self.peek() != Tok::Eof {
+            let node = if *self.peek() == Tok::Fn { self.function()? } else { self.expr()? };
+            nodes.push(node);
+            self.skip_semicolon();
         }
+        Ok(nodes)
     }
-}
 
-// ==========================================================
-// SECURITY AI
-// ==========================================================
-pub struct SecurityAI<L: LLM> {
-    pub llm: L,
-}
+    fn function(&mut self) -> Result<Node, ParseError> {
+        self.next();
+        let name = self.ident("function name")?;
+        self.expect('(')?;
+        let mut args = Vec::new();
+        if *self.peek() != Tok::Punct(')') {
+            loop {
+                args.push(self.ident("parameter name")?);
+                if *self.peek() != Tok::Punct(',') {
+                    break;
+                }
+                self.next();
+            }
+        }
+        self.expect(')')?;
+        self.expect('{')?;
+        let mut body = Vec::new();
+        while *self.peek() != Tok::Punct('}') {
+            if *self.peek() == Tok::Eof {
+                return Err(self.error(format!("unclosed body of function '{}'", name)));
+            }
+            body.push(self.expr()?);
+            self.skip_semicolon();
+        }
+        self.next();
+        Ok(Node::new(NodeKind::Function { name, args, body }))
+    }
 
-impl<L: LLM> SecurityAI<L> {
-    pub fn new(llm: L) -> Self { Self { llm } }
+    // 왼쪽 결합 이항 연산 한 단계
+    fn binary(&mut self, ops: &str, operand: fn(&mut Self) -> Result<Node, ParseError>) -> Result<Node, ParseError> {
+        let mut left = operand(self)?;
+        while let Tok::Punct(c) = *self.peek() {
+            if !ops.contains(c) {
+                break;
+            }
+            self.next();
+            let right = operand(self)?;
+            left = Node::new(NodeKind::BinaryOp { op: c.to_string(), left: Box::new(left), right: Box::new(right) });
+        }
+        Ok(left)
+    }
 
-    pub fn analyze(&self, node: &Node) -> Vec<String> {
-        vec![
-            self.llm.predict(&format!("Security check for node: {:?}", node))
-        ]
+    fn expr(&mut self) -> Result<Node, ParseError> {
+        self.binary("+-", Self::term)
     }
-}
 
-// ==========================================================
-// BASE CODE GENERATOR
-// ==========================================================
-pub struct BaseGenerator;
+    fn term(&mut self) -> Result<Node, ParseError> {
+        self.binary("*/%", Self::unary)
+    }
 
-impl BaseGenerator {
-    pub fn generate(&self, node: &Node, lang: &str) -> String {
-        match &node.kind {
-            NodeKind::Identifier(x) => match lang {
-                "go" => format!("var {} any", x),
-                "cpp" => format!("auto {};", x),
-                "swift" => format!("var {}: Any", x),
-                _ => format!("{}", x),
-            },
-            _ => "/* unsupported */".into(),
+    fn unary(&mut self) -> Result<Node, ParseError> {
+        match self.peek().clone() {
+            Tok::Punct('-') => {
+                self.next();
+                let operand = self.unary()?;
+                Ok(match operand.kind {
+                    NodeKind::Integer(n) => Node::new(NodeKind::Integer(-n)),
+                    NodeKind::Number(n) => Node::new(NodeKind::Number(-n)),
+                    _ => Node::new(NodeKind::BinaryOp {
+                        op: "-".into(),
+                        left: Box::new(Node::new(NodeKind::Integer(0))),
+                        right: Box::new(operand),
+                    }),
+                })
+            }
+            Tok::Integer(n) => {
+                self.next();
+                Ok(Node::new(NodeKind::Integer(n)))
+            }
+            Tok::Number(n) => {
+                self.next();
+                Ok(Node::new(NodeKind::Number(n)))
+            }
+            Tok::Ident(x) => {
+                self.next();
+                Ok(Node::new(NodeKind::Identifier(x)))
+            }
+            Tok::Punct('(') => {
+                self.next();
+                let inner = self.expr()?;
+                self.expect(')')?;
+                Ok(inner)
+            }
+            other => Err(self.error(format!("expected expression, found {}", other))),
         }
     }
 }
 
 // ==========================================================
-// LLM REFINER
+// LLM BASE TRAIT
 // ==========================================================
-pub struct LLMGenerator<L: LLM> { pub llm: L }
+#[derive(Debug, Clone, PartialEq)]
+pub enum LlmError {
+    MissingApiKey,
+    Network(String),
+    /// 본문 전체가 UTF-8 검증 실패 메시지
+    InvalidUtf8(String),
+    /// provider 에 보낼 수 없는 메시지 순서
+    InvalidMessages(String),
+    /// AbortToken 으로 취소됨
+    Cancelled,
+    /// finish_reason 이 "length" 인 채로 끝난 응답 (이어쓰기 횟수)
+    Truncated(u32),
+    /// 계정 할당량 소진 (insufficient_quota). 재시도해도 소용없음
+    QuotaExhausted(String),
+    /// 재시도 후에도 실패한 HTTP 응답, 또는 본문의 error 객체
+    Api { status: u16, message: String },
+    /// 재시도 후에도 제한 시간 안에 응답이 없음
+    Timeout(Duration),
+    /// TestLLM replay: cassette 에 없는 요청 (정규화된 프롬프트 앞부분)
+    NotRecorded(String),
+    /// --offline: 요청을 보내지 않음
+    Offline,
+}
 
-impl<L: LLM> LLMGenerator<L> {
-    pub fn refine(&self, lang: &str, version: &str, code: &str) -> String {
-        self.llm.predict(
-            &format!("Rewrite in idiomatic {} {} code:\n{}", lang, version, code)
-        )
+impl fmt::Display for LlmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LlmError::MissingApiKey => write!(f, "(ERROR: OPENAI_API_KEY missing.)"),
+            LlmError::Network(e) => write!(f, "(API ERROR: {})", e),
+            LlmError::InvalidUtf8(msg) => write!(f, "{}", msg),
+            LlmError::Cancelled => write!(f, "(ERROR: request cancelled)"),
+            LlmError::QuotaExhausted(msg) => write!(f, "{}: {})", QUOTA_MARKER, msg),
+            LlmError::Truncated(n) => write!(f, "{} after {} continuations)", TRUNCATED_MARKER, n),
+            LlmError::InvalidMessages(msg) => write!(f, "(ERROR: invalid message sequence: {})", msg),
+            LlmError::Api { status, message } => write!(f, "(API ERROR: HTTP {}: {})", status, message),
+            LlmError::Timeout(d) => write!(f, "(API ERROR: request timed out after {}s)", d.as_secs()),
+            LlmError::NotRecorded(prompt) => write!(f, "(ERROR: no cassette entry for prompt: {})", prompt),
+            LlmError::Offline => write!(f, "(ERROR: offline mode, no LLM request sent)"),
+        }
     }
 }
 
-// ==========================================================
-// FILE TRANSPILER
-// ==========================================================
-pub fn transpile_file<L: LLM>(llm: &L, src: &str, lang: &str) -> String {
-    llm.predict(&format!("Transpile to {}:\n{}", lang, src))
+impl std::error::Error for LlmError {}
+
+// predict 가 돌려준 문자열이 실제 결과가 아니라 오류 표시인지
+pub fn is_error_response(s: &str) -> bool {
+    let t = s.trim_start();
+    t.starts_with("(API ERROR") || t.starts_with("(ERROR") || t == "(EMPTY)"
 }
 
-// ==========================================================
-// 5) ADVANCED PROJECT DIRECTORY TRANSPILER
-// ==========================================================
+// ----------------------------------------------------------
+// CHAT MESSAGES
+// ----------------------------------------------------------
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    System,
+    User,
+    Assistant,
+}
 
-// 기본적으로 변환하지 않을 폴더
-fn should_skip_dir(path: &Path) -> bool {
-    let skip_list = [
-        ".git",
-        "target",
-        "build",
-        "node_modules",
-        "__pycache__",
-        ".idea",
-        ".vscode",
-    ];
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::System => "system",
+            Role::User => "user",
+            Role::Assistant => "assistant",
+        }
+    }
+}
 
-    skip_list.iter().any(|&name| path.ends_with(name))
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub role: Role,
+    pub content: String,
 }
 
-// 변환할 파일 확장자
-fn is_convertible_file(path: &Path) -> bool {
-    if let Some(ext) = path.extension() {
-        let e = ext.to_string_lossy().to_lowercase();
-        return matches!(e.as_str(), "rs" | "cpp" | "h" | "c" | "py" | "go" | "ts" | "js" | "swift");
+impl Message {
+    pub fn new(role: Role, content: impl Into<String>) -> Self {
+        Self { role, content: content.into() }
     }
-    false
 }
 
-// 언어별 변환된 확장자
-fn mapped_ext(lang: &str) -> &'static str {
-    match lang {
-        "go" => "go",
-        "cpp" => "cpp",
-        "swift" => "swift",
-        "rust" => "rs",
-        "python" => "py",
-        _ => "txt",
+// provider 가 받아주는 순서로 정리: system 은 하나로 합쳐서 맨 앞, 이후 user/assistant 교대.
+// 같은 역할이 연속되면 합쳐서 복구하고, 복구할 수 없는 순서는 오류
+pub fn assemble_messages(messages: &[Message]) -> Result<Vec<Message>, LlmError> {
+    let system: Vec<&str> = messages
+        .iter()
+        .filter(|m| m.role == Role::System)
+        .map(|m| m.content.as_str())
+        .collect();
+
+    let mut out: Vec<Message> = Vec::new();
+    if !system.is_empty() {
+        out.push(Message::new(Role::System, system.join("\n\n")));
+    }
+
+    for m in messages.iter().filter(|m| m.role != Role::System) {
+        match out.last_mut() {
+            Some(last) if last.role == m.role => {
+                last.content.push_str("\n\n");
+                last.content.push_str(&m.content);
+            }
+            _ => out.push(m.clone()),
+        }
+    }
+
+    let first_turn = out.iter().find(|m| m.role != Role::System);
+    match first_turn {
+        None => return Err(LlmError::InvalidMessages("no user message".into())),
+        Some(m) if m.role != Role::User => {
+            return Err(LlmError::InvalidMessages("conversation must start with a user message".into()))
+        }
+        _ => {}
+    }
+    if out.last().map(|m| m.role) != Some(Role::User) {
+        return Err(LlmError::InvalidMessages("conversation must end with a user message".into()));
     }
+    Ok(out)
 }
 
-pub fn transpile_project<L: LLM>(
-    llm: &L,
-    src_dir: &str,
-    out_dir: &str,
-    lang: &str,
-) {
-    println!("\n--- PROJECT TRANSPILER START ---");
-    fs::create_dir_all(out_dir).unwrap();
+pub trait LLM {
+    fn predict(&self, prompt: &str) -> String;
 
-    fn walk<L: LLM>(llm: &L, src: &Path, out: &Path, lang: &str) {
-        for entry in fs::read_dir(src).unwrap() {
-            let entry = entry.unwrap();
-            let path = entry.path();
+    // system 메시지를 따로 받는 provider 는 override
+    fn predict_with_system(&self, system: &str, prompt: &str) -> String {
+        self.predict(&format!("{}\n\n{}", system, prompt))
+    }
 
-            if path.is_dir() {
-                if should_skip_dir(&path) {
-                    println!("[SKIP] directory: {}", path.display());
-                    continue;
-                }
+    // n 개의 후보 응답. 기본 구현은 predict 를 n 번 호출
+    fn predict_n(&self, prompt: &str, n: u32) -> Result<Vec<String>, LlmError> {
+        Ok((0..n.max(1)).map(|_| self.predict(prompt)).collect())
+    }
 
-                let next = out.join(entry.file_name());
-                fs::create_dir_all(&next).unwrap_or(());
-                walk(llm, &path, &next, lang);
-            } else if path.is_file() {
-                if !is_convertible_file(&path) {
-                    println!("[IGNORE] {}", path.display());
-                    continue;
-                }
+    fn predict_n_with_system(&self, system: &str, prompt: &str, n: u32) -> Result<Vec<String>, LlmError> {
+        Ok((0..n.max(1)).map(|_| self.predict_with_system(system, prompt)).collect())
+    }
 
-                println!("[CONVERT] {}", path.display());
-                let content = fs::read_to_string(&path).unwrap_or_default();
+    // 요청마다 모델을 바꿀 수 있는 provider 는 override (기본 구현은 model 무시)
+    fn predict_with_model(&self, _model: &str, system: &str, prompt: &str) -> String {
+        self.predict_with_system(system, prompt)
+    }
 
-                let code = llm.predict(
-                    &format!("Transpile fully into {} code:\n{}", lang, content)
-                );
+    // 응답을 받는 대로 on_token 에 넘기고 전체 응답을 돌려줌.
+    // 스트리밍을 지원하지 않는 provider 는 기본 구현대로 전체를 한 번에 넘김
+    fn predict_stream(&self, prompt: &str, on_token: &mut dyn FnMut(&str)) -> String {
+        let response = self.predict(prompt);
+        on_token(&response);
+        response
+    }
 
-                let newname = format!(
-                    "{}.{}",
-                    path.file_name().unwrap().to_string_lossy(),
-                    mapped_ext(lang)
-                );
-                fs::write(out.join(newname), code).unwrap_or(());
-            }
-        }
+    fn predict_stream_with_system(&self, system: &str, prompt: &str, on_token: &mut dyn FnMut(&str)) -> String {
+        let response = self.predict_with_system(system, prompt);
+        on_token(&response);
+        response
+    }
+
+    // provider(endpoint + model) 별 누적 사용량. 집계하지 않는 구현은 빈 map
+    fn provider_stats(&self) -> HashMap<String, ProviderStats> {
+        HashMap::new()
     }
 
-    walk(llm, Path::new(src_dir), Path::new(out_dir), lang);
-    println!("--- PROJECT TRANSPILER DONE ---");
+    // 응답 캐시 key 에 넣을 provider / model / 샘플링 파라미터. 같은 프롬프트라도 이게 다르면 다른 응답
+    fn cache_identity(&self) -> String {
+        String::new()
+    }
+
+    // 대화 모드. 기본 구현은 대화 내용을 한 프롬프트로 펼쳐서 보냄
+    fn predict_chat(&self, messages: &[Message]) -> Result<String, LlmError> {
+        let messages = assemble_messages(messages)?;
+        let (system, turns): (Vec<&Message>, Vec<&Message>) =
+            messages.iter().partition(|m| m.role == Role::System);
+        let transcript = turns
+            .iter()
+            .map(|m| format!("{}: {}", m.role.as_str(), m.content))
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        Ok(match system.first() {
+            Some(sys) => self.predict_with_system(&sys.content, &transcript),
+            None => self.predict(&transcript),
+        })
+    }
 }
 
 // ==========================================================
-// FULL INTELLIGENT COMPILER
+// PERSONA / SYSTEM PROMPT
 // ==========================================================
-pub struct Compiler<L: LLM + Clone> {
-    pub llm: L,
-    pub version_ai: VersionAI,
-    pub semantic: SemanticEngine,
-    pub security: SecurityAI<L>,
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Task {
+    Transpile,
+    Refine,
+    Security,
 }
 
-impl<L: LLM + Clone> Compiler<L> {
-    pub fn new(llm: L) -> Self {
-        Self {
-            version_ai: VersionAI::new(),
-            semantic: SemanticEngine,
-            security: SecurityAI::new(llm.clone()),
-            llm
+impl Task {
+    // 작업별 기본 페르소나
+    pub fn default_persona(&self, lang: &str) -> String {
+        match self {
+            Task::Transpile => format!(
+                "You are an expert {} engineer migrating existing code bases into {}.",
+                lang, lang
+            ),
+            Task::Refine => format!(
+                "You are a senior {} reviewer who rewrites code into clean, idiomatic {}.",
+                lang, lang
+            ),
+            Task::Security => {
+                "You are an application security auditor reviewing code for vulnerabilities.".into()
+            }
         }
     }
+}
 
-    pub fn compile_node(&self, node: &Node, lang: &str) -> String {
-        let ver = self.version_ai.infer(lang, node);
-        let sem = self.semantic.analyze(node);
-        let base = BaseGenerator.generate(node, lang);
-        let refined = LLMGenerator { llm: self.llm.clone() }.refine(lang, &ver, &base);
-        let sec = self.security.analyze(node);
-
-        format!(
-            "=== Intelligent Compiler ===\n\
-             Language: {}\nVersion: {}\nMeaning: {}\n\n\
-             Base:\n{}\n\nAI Refined:\n{}\n\nSecurity:\n{:?}",
-            lang, ver, sem.meaning, base, refined, sec
-        )
+// 사용자 페르소나가 있으면 우선, 없으면 작업별 기본값
+pub fn system_prompt(task: Task, persona: Option<&str>, lang: &str) -> String {
+    match persona {
+        Some(p) if !p.trim().is_empty() => p.trim().to_string(),
+        _ => task.default_persona(lang),
     }
 }
 
+// LLM_PERSONA 환경변수 (선택)
+pub fn persona_from_env() -> Option<String> {
+    env::var("LLM_PERSONA").ok().filter(|p| !p.trim().is_empty())
+}
+
 // ==========================================================
-// MAIN
+// CANCELLATION (GUI / 서버에서 진행 중인 작업 취소)
 // ==========================================================
-fn main() {
-    install_panic_hook();
+#[derive(Debug, Clone, Default)]
+pub struct AbortToken(Arc<AtomicBool>);
 
-    println!("==============================================");
-    println!("        INTELLIGENT COMPILER AI ENGINE");
-    println!("==============================================");
-
-    let llm = RealLLM::new();
-    let compiler = Compiler::new(llm.clone());
+impl AbortToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
 
-    // Test Node
-    let mut node = Node::new(NodeKind::Identifier("x".into()));
-    node.meta.insert("uses_generics".into(), "true".into());
-    println!("{}", compiler.compile_node(&node, "go"));
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
 
-    // Test File
-    println!("\n=== FILE TRANSPILER ===");
-    let sample = "fn add(a: i32, b: i32) -> i32 { a + b }";
-    println!("{}", transpile_file(&llm, sample, "go"));
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
 
-    // Test Project
-    println!("\n=== PROJECT TRANSPILER ===");
-    transpile_project(&llm, "src", "output_go", "go");
+    // 취소되면 바로 깨어나는 sleep. 끝까지 잤으면 true
+    pub fn sleep(&self, dur: Duration) -> bool {
+        let deadline = Instant::now() + dur;
+        while !self.is_cancelled() {
+            let now = Instant::now();
+            if now >= deadline {
+                return true;
+            }
+            std::thread::sleep((deadline - now).min(Duration::from_millis(50)));
+        }
+        false
+    }
+}
 
-    println!("\nFINISHED.");
+// CLI Ctrl-C: 첫 번째는 새 파일을 시작하지 않고 진행 중인 변환만 끝낸 뒤 부분 report,
+// 두 번째는 바로 종료
+static INTERRUPTS: AtomicUsize = AtomicUsize::new(0);
+
+#[cfg(unix)]
+extern "C" fn on_sigint(_: libc::c_int) {
+    // signal handler 안에서는 atomic 과 _exit 만 씀
+    if INTERRUPTS.fetch_add(1, Ordering::SeqCst) > 0 {
+        unsafe { libc::_exit(130) };
+    }
+}
+
+// 첫 Ctrl-C 에 취소되는 token. unix 가 아니면 기본 동작 (바로 종료) 그대로
+pub fn install_interrupt_handler() -> AbortToken {
+    let token = AbortToken::new();
+    #[cfg(unix)]
+    {
+        unsafe { libc::signal(libc::SIGINT, on_sigint as *const () as libc::sighandler_t) };
+        let watched = token.clone();
+        std::thread::spawn(move || {
+            while INTERRUPTS.load(Ordering::SeqCst) == 0 {
+                std::thread::sleep(Duration::from_millis(100));
+            }
+            warn!("[INTERRUPT] no new files will start; waiting for in-flight conversions (Ctrl-C again to quit now)");
+            watched.cancel();
+        });
+    }
+    token
+}
+
+// ==========================================================
+// PROVIDER USAGE (provider / model 별 요청, 토큰, 오류, 지연)
+// ==========================================================
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ProviderStats {
+    /// HTTP 요청 수 (재시도 포함)
+    pub requests: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    /// completion_tokens 중 reasoning 에 쓰인 토큰 (o-series 등)
+    pub reasoning_tokens: u64,
+    /// 네트워크 오류 + API 오류 응답
+    pub errors: u64,
+    pub total_latency: Duration,
+}
+
+impl ProviderStats {
+    pub fn avg_latency(&self) -> Duration {
+        if self.requests == 0 {
+            Duration::ZERO
+        } else {
+            self.total_latency / self.requests as u32
+        }
+    }
+
+    // 누적값에서 earlier 시점 이후 증가분만
+    pub fn since(&self, earlier: &ProviderStats) -> ProviderStats {
+        ProviderStats {
+            requests: self.requests - earlier.requests,
+            prompt_tokens: self.prompt_tokens - earlier.prompt_tokens,
+            completion_tokens: self.completion_tokens - earlier.completion_tokens,
+            reasoning_tokens: self.reasoning_tokens - earlier.reasoning_tokens,
+            errors: self.errors - earlier.errors,
+            total_latency: self.total_latency.saturating_sub(earlier.total_latency),
+        }
+    }
+}
+
+// 두 스냅샷 사이의 provider 별 사용량 (요청이 없던 provider 는 제외)
+pub fn provider_stats_since(
+    now: &HashMap<String, ProviderStats>,
+    before: &HashMap<String, ProviderStats>,
+) -> HashMap<String, ProviderStats> {
+    now.iter()
+        .map(|(k, v)| (k.clone(), v.since(&before.get(k).copied().unwrap_or_default())))
+        .filter(|(_, v)| v.requests > 0)
+        .collect()
+}
+
+// 사용량 key ("url (model)") 의 모델 이름
+fn stats_model(key: &str) -> Option<&str> {
+    key.rsplit_once(" (")?.1.strip_suffix(')')
+}
+
+// 응답의 usage 기준 실제 비용 (USD). 모델 표에 없는 모델은 보수적 가격
+pub fn usage_cost(stats: &HashMap<String, ProviderStats>) -> f64 {
+    stats
+        .iter()
+        .map(|(key, s)| {
+            let info = stats_model(key).map_or(CONSERVATIVE_MODEL_INFO, model_info_or_default);
+            (s.prompt_tokens as f64 * info.input_per_1k + s.completion_tokens as f64 * info.output_per_1k) / 1000.0
+        })
+        .sum()
+}
+
+pub fn print_provider_stats(stats: &HashMap<String, ProviderStats>) {
+    let mut keys: Vec<&String> = stats.keys().collect();
+    keys.sort();
+    for k in keys {
+        let s = &stats[k];
+        println!(
+            "provider {}: {} requests, {} prompt + {} completion tokens ({} reasoning), {} errors, avg {:?}",
+            k,
+            s.requests,
+            s.prompt_tokens,
+            s.completion_tokens,
+            s.reasoning_tokens,
+            s.errors,
+            s.avg_latency()
+        );
+    }
+}
+
+// ==========================================================
+// REAL OPENAI CLIENT
+// ==========================================================
+// 응답 본문이 올바른 UTF-8 이 아닐 때의 처리 방식
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Utf8Policy {
+    /// 해당 응답(파일)을 실패로 처리
+    Reject,
+    /// 깨진 바이트를 U+FFFD 로 치환하고 경고 출력
+    #[default]
+    Lossy,
+}
+
+impl Utf8Policy {
+    pub fn from_env() -> Self {
+        match env::var("OPENAI_UTF8_POLICY").as_deref() {
+            Ok("reject") => Utf8Policy::Reject,
+            _ => Utf8Policy::Lossy,
+        }
+    }
+}
+
+// 잘못된 UTF-8 응답 표시 (walk 에서 파일을 쓰지 않고 실패 처리)
+const UTF8_ERROR_MARKER: &str = "(ERROR: invalid UTF-8 in response";
+// 할당량 소진: 남은 파일도 전부 같은 이유로 실패하므로 run 전체를 멈춤
+const QUOTA_MARKER: &str = "(API ERROR: quota exhausted";
+
+// max_tokens 에 걸려 코드가 중간에 잘린 응답
+const TRUNCATED_MARKER: &str = "(ERROR: response truncated (finish_reason=length)";
+
+// finish_reason == "length" 일 때의 처리
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TruncationPolicy {
+    /// "continue" 후속 요청으로 이어 받아서 붙임 (최대 횟수까지)
+    Continue { max_continuations: u32 },
+    /// 바로 실패 처리
+    Fail,
+}
+
+impl Default for TruncationPolicy {
+    fn default() -> Self {
+        TruncationPolicy::Continue { max_continuations: 2 }
+    }
+}
+
+impl TruncationPolicy {
+    // OPENAI_ON_TRUNCATION=fail|continue, OPENAI_MAX_CONTINUATIONS
+    pub fn from_env() -> Self {
+        match env::var("OPENAI_ON_TRUNCATION").as_deref() {
+            Ok("fail") => TruncationPolicy::Fail,
+            _ => TruncationPolicy::Continue {
+                max_continuations: env::var("OPENAI_MAX_CONTINUATIONS")
+                    .ok()
+                    .and_then(|v| v.trim().parse().ok())
+                    .unwrap_or(2),
+            },
+        }
+    }
+}
+
+const CONTINUE_PROMPT: &str = "Continue exactly where you stopped. Do not repeat anything already written and do not add commentary.";
+
+// choices[i] 하나
+#[derive(Debug, Clone)]
+struct Choice {
+    content: String,
+    /// reasoning 모델의 사고 과정 (출력 파일에는 쓰지 않음)
+    reasoning: Option<String>,
+    finish_reason: Option<String>,
+}
+
+// 본문에 섞여 오는 <think>...</think> / <thinking>...</thinking> 을 답과 분리
+pub fn split_reasoning(content: &str) -> (String, Option<String>) {
+    // 응답마다 불리므로 한 번만 컴파일
+    static RE: std::sync::OnceLock<Regex> = std::sync::OnceLock::new();
+    let re = RE.get_or_init(|| Regex::new(r"(?s)<(think|thinking|reasoning)>(.*?)</(think|thinking|reasoning)>").unwrap());
+    let reasoning: Vec<String> = re
+        .captures_iter(content)
+        .map(|c| c[2].trim().to_string())
+        .filter(|r| !r.is_empty())
+        .collect();
+    if !re.is_match(content) {
+        return (content.to_string(), None);
+    }
+    let answer = re.replace_all(content, "").trim_start().to_string();
+    (answer, (!reasoning.is_empty()).then(|| reasoning.join("\n\n")))
+}
+
+const REASONING_OPEN_TAGS: [&str; 3] = ["<think>", "<thinking>", "<reasoning>"];
+const REASONING_CLOSE_TAGS: [&str; 3] = ["</think>", "</thinking>", "</reasoning>"];
+
+// 스트리밍 응답용 split_reasoning. chunk 경계에 걸친 태그는 다음 chunk 가 올 때까지 붙잡아 둠
+#[derive(Debug, Default)]
+struct ReasoningFilter {
+    pending: String,
+    /// Some 이면 reasoning 구간 안 (연 태그)
+    open: Option<&'static str>,
+    /// 답 본문을 하나라도 넘겼는지 (reasoning 뒤 앞 공백은 split_reasoning 처럼 버림)
+    emitted: bool,
+    closed_any: bool,
+}
+
+impl ReasoningFilter {
+    // 지금까지 받은 것 중 화면에 보여도 되는 부분
+    fn feed(&mut self, chunk: &str) -> String {
+        self.pending.push_str(chunk);
+        let mut out = String::new();
+        loop {
+            if self.open.is_some() {
+                let close = REASONING_CLOSE_TAGS
+                    .iter()
+                    .filter_map(|t| self.pending.find(t).map(|i| (i, t.len())))
+                    .min();
+                let Some((i, len)) = close else { break };
+                self.pending.drain(..i + len);
+                self.open = None;
+                self.closed_any = true;
+                continue;
+            }
+            let Some(i) = self.pending.find('<') else {
+                out.push_str(&self.pending);
+                self.pending.clear();
+                break;
+            };
+            out.push_str(&self.pending[..i]);
+            self.pending.drain(..i);
+            if let Some(tag) = REASONING_OPEN_TAGS.iter().find(|t| self.pending.starts_with(*t)) {
+                self.pending.drain(..tag.len());
+                self.open = Some(tag);
+            } else if REASONING_OPEN_TAGS.iter().any(|t| t.starts_with(self.pending.as_str())) {
+                // "<thi" 처럼 아직 태그인지 모름
+                break;
+            } else {
+                out.push('<');
+                self.pending.drain(..1);
+            }
+        }
+        self.visible(out)
+    }
+
+    // 스트림 끝. 닫히지 않은 태그는 split_reasoning 처럼 본문으로 취급
+    fn finish(&mut self) -> String {
+        let mut out = self.open.take().unwrap_or_default().to_string();
+        out.push_str(&std::mem::take(&mut self.pending));
+        self.visible(out)
+    }
+
+    fn visible(&mut self, mut out: String) -> String {
+        if self.closed_any && !self.emitted {
+            out = out.trim_start().to_string();
+        }
+        self.emitted |= !out.is_empty();
+        out
+    }
+}
+
+// provider 마다 다른 필드 (reasoning_content: DeepSeek 등, reasoning: OpenRouter 등) + 본문 태그
+fn parse_choice(c: &serde_json::Value) -> Choice {
+    let message = &c["message"];
+    let raw = message["content"].as_str().unwrap_or("(EMPTY)");
+    let (content, inline) = split_reasoning(raw);
+    let field = message["reasoning_content"]
+        .as_str()
+        .or_else(|| message["reasoning"].as_str())
+        .map(str::to_string)
+        .filter(|r| !r.trim().is_empty());
+    let reasoning = match (field, inline) {
+        (Some(a), Some(b)) => Some(format!("{}\n\n{}", a, b)),
+        (a, b) => a.or(b),
+    };
+    Choice { content, reasoning, finish_reason: c["finish_reason"].as_str().map(str::to_string) }
+}
+
+impl Choice {
+    fn truncated(&self) -> bool {
+        self.finish_reason.as_deref() == Some("length")
+    }
+}
+
+fn decode_utf8(bytes: &[u8], policy: Utf8Policy) -> Result<String, String> {
+    match std::str::from_utf8(bytes) {
+        Ok(s) => Ok(s.to_string()),
+        Err(e) => match policy {
+            Utf8Policy::Reject => Err(format!("{} at byte {})", UTF8_ERROR_MARKER, e.valid_up_to())),
+            Utf8Policy::Lossy => {
+                warn!(
+                    "[WARN] invalid UTF-8 in response at byte {}, writing lossy-converted output",
+                    e.valid_up_to()
+                );
+                Ok(String::from_utf8_lossy(bytes).into_owned())
+            }
+        },
+    }
+}
+
+// ==========================================================
+// RETRY POLICY
+// ==========================================================
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackoffStrategy {
+    /// base * 2^n
+    Exponential,
+    /// 0 ~ base * 2^n 사이 랜덤 (여러 클라이언트가 동시에 몰리는 것 방지)
+    ExponentialWithJitter,
+    /// 항상 base
+    Fixed,
+    /// base * fib(n+1) : 1, 1, 2, 3, 5, ...
+    Fibonacci,
+}
+
+impl BackoffStrategy {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_lowercase().replace(['-', '_'], "").as_str() {
+            "exponential" | "exp" => Some(BackoffStrategy::Exponential),
+            "exponentialwithjitter" | "jitter" => Some(BackoffStrategy::ExponentialWithJitter),
+            "fixed" => Some(BackoffStrategy::Fixed),
+            "fibonacci" | "fib" => Some(BackoffStrategy::Fibonacci),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub strategy: BackoffStrategy,
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            strategy: BackoffStrategy::ExponentialWithJitter,
+            max_retries: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+// 외부 crate 없이 쓰는 0.0 ~ 1.0 난수 (jitter 용도로만)
+fn jitter_fraction() -> f64 {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.subsec_nanos() as u64)
+        .unwrap_or(0);
+    let mut x = nanos ^ 0x9E37_79B9_7F4A_7C15;
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    (x % 10_000) as f64 / 10_000.0
+}
+
+impl RetryPolicy {
+    // OPENAI_RETRY_STRATEGY / OPENAI_MAX_RETRIES / OPENAI_RETRY_BASE_MS
+    pub fn from_env() -> Self {
+        let mut p = Self::default();
+        if let Some(s) = env::var("OPENAI_RETRY_STRATEGY").ok().and_then(|v| BackoffStrategy::parse(&v)) {
+            p.strategy = s;
+        }
+        if let Some(n) = env::var("OPENAI_MAX_RETRIES").ok().and_then(|v| v.trim().parse().ok()) {
+            p.max_retries = n;
+        }
+        if let Some(ms) = env::var("OPENAI_RETRY_BASE_MS").ok().and_then(|v| v.trim().parse().ok()) {
+            p.base_delay = Duration::from_millis(ms);
+        }
+        p
+    }
+
+    // jitter 를 적용하기 전의 대기 시간 (attempt 는 0 부터)
+    pub fn base_delay_for(&self, attempt: u32) -> Duration {
+        let factor: u64 = match self.strategy {
+            BackoffStrategy::Fixed => 1,
+            BackoffStrategy::Exponential | BackoffStrategy::ExponentialWithJitter => {
+                1u64.checked_shl(attempt).unwrap_or(u64::MAX)
+            }
+            BackoffStrategy::Fibonacci => {
+                let (mut a, mut b) = (1u64, 1u64);
+                for _ in 0..attempt {
+                    let next = a.saturating_add(b);
+                    a = b;
+                    b = next;
+                }
+                a
+            }
+        };
+        let ms = (self.base_delay.as_millis() as u64).saturating_mul(factor);
+        Duration::from_millis(ms).min(self.max_delay)
+    }
+
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let d = self.base_delay_for(attempt);
+        match self.strategy {
+            BackoffStrategy::ExponentialWithJitter => d.mul_f64(jitter_fraction()),
+            _ => d,
+        }
+    }
+}
+
+// 429 (rate limit) 와 일시적인 5xx 만 재시도. 나머지 4xx 는 다시 보내도 같은 결과
+pub fn is_retryable_status(status: u16) -> bool {
+    matches!(status, 408 | 429 | 500 | 502 | 503 | 504)
+}
+
+pub const DEFAULT_MODEL: &str = "gpt-4.1";
+/// OPENAI_BASE_URL 이 없을 때. 요청은 <base>/chat/completions 로
+pub const OPENAI_DEFAULT_BASE_URL: &str = "https://api.openai.com/v1";
+
+// OPENAI_BASE_URL 환경변수 (Azure / 사내 gateway / proxy. 예: https://<resource>.openai.azure.com/openai/deployments/<name>)
+pub fn base_url_from_env() -> String {
+    env_or("OPENAI_BASE_URL", OPENAI_DEFAULT_BASE_URL)
+}
+
+// Azure 는 OPENAI_API_TYPE=azure 또는 *.openai.azure.com 주소로 판단
+pub fn is_azure_endpoint(base_url: &str) -> bool {
+    let host = base_url.split("://").nth(1).unwrap_or(base_url).split(['/', ':']).next().unwrap_or("");
+    env::var("OPENAI_API_TYPE").is_ok_and(|t| t.trim().eq_ignore_ascii_case("azure")) || host.ends_with(".openai.azure.com")
+}
+
+/// OPENAI_API_VERSION 이 없을 때 Azure 에 붙이는 api-version
+pub const AZURE_DEFAULT_API_VERSION: &str = "2024-10-21";
+
+// OPENAI_N 환경변수: 파일당 후보 개수 (기본 1)
+pub fn n_from_env() -> u32 {
+    env::var("OPENAI_N").ok().and_then(|v| v.trim().parse().ok()).unwrap_or(1)
+}
+
+// 변환은 결정적일수록 좋으므로 API 기본값 (1.0) 보다 낮게
+pub const DEFAULT_TEMPERATURE: f32 = 0.2;
+
+// OPENAI_TEMPERATURE 환경변수 (0.0 ~ 2.0, 기본 DEFAULT_TEMPERATURE)
+pub fn temperature_from_env() -> f32 {
+    env::var("OPENAI_TEMPERATURE")
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .filter(|t: &f32| (0.0..=2.0).contains(t))
+        .unwrap_or(DEFAULT_TEMPERATURE)
+}
+
+// OPENAI_MAX_TOKENS 환경변수 (선택, 없으면 모델 기본값)
+pub fn max_tokens_from_env() -> Option<u32> {
+    env::var("OPENAI_MAX_TOKENS").ok().and_then(|v| v.trim().parse().ok()).filter(|&n| n > 0)
+}
+
+// OPENAI_MODEL 환경변수 (선택)
+pub fn model_from_env() -> Option<String> {
+    env::var("OPENAI_MODEL").ok().map(|m| m.trim().to_string()).filter(|m| !m.is_empty())
+}
+
+// ==========================================================
+// MODEL TABLE (context window / cost)
+// ==========================================================
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ModelInfo {
+    pub context_window: usize,
+    /// USD / 1K prompt tokens
+    pub input_per_1k: f64,
+    /// USD / 1K completion tokens
+    pub output_per_1k: f64,
+}
+
+// 모르는 모델: 작은 context, 비싼 가격으로 보수적으로 가정
+pub const CONSERVATIVE_MODEL_INFO: ModelInfo = ModelInfo {
+    context_window: 8_192,
+    input_per_1k: 0.015,
+    output_per_1k: 0.06,
+};
+
+const KNOWN_MODELS: &[(&str, ModelInfo)] = &[
+    ("gpt-4.1", ModelInfo { context_window: 1_047_576, input_per_1k: 0.002, output_per_1k: 0.008 }),
+    ("gpt-4.1-mini", ModelInfo { context_window: 1_047_576, input_per_1k: 0.0004, output_per_1k: 0.0016 }),
+    ("gpt-4.1-nano", ModelInfo { context_window: 1_047_576, input_per_1k: 0.0001, output_per_1k: 0.0004 }),
+    ("gpt-4o", ModelInfo { context_window: 128_000, input_per_1k: 0.0025, output_per_1k: 0.01 }),
+    ("gpt-4o-mini", ModelInfo { context_window: 128_000, input_per_1k: 0.00015, output_per_1k: 0.0006 }),
+    ("o3-mini", ModelInfo { context_window: 200_000, input_per_1k: 0.0011, output_per_1k: 0.0044 }),
+    ("o1", ModelInfo { context_window: 200_000, input_per_1k: 0.015, output_per_1k: 0.06 }),
+];
+
+// MODEL_INFO="name=context,input_per_1k,output_per_1k;..." 로 추가 등록
+fn registered_models() -> Vec<(String, ModelInfo)> {
+    let Ok(v) = env::var("MODEL_INFO") else { return Vec::new() };
+    v.split(';')
+        .filter_map(|entry| {
+            let (name, rest) = entry.split_once('=')?;
+            let parts: Vec<&str> = rest.split(',').map(str::trim).collect();
+            match parts.as_slice() {
+                [ctx, inp, out] => Some((
+                    name.trim().to_string(),
+                    ModelInfo {
+                        context_window: ctx.parse().ok()?,
+                        input_per_1k: inp.parse().ok()?,
+                        output_per_1k: out.parse().ok()?,
+                    },
+                )),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+pub fn model_info(model: &str) -> Option<ModelInfo> {
+    registered_models()
+        .into_iter()
+        .find(|(n, _)| n == model)
+        .map(|(_, i)| i)
+        .or_else(|| KNOWN_MODELS.iter().find(|(n, _)| *n == model).map(|(_, i)| *i))
+}
+
+// 모르는 모델이면 이름마다 한 번만 경고하고 보수적 기본값 사용
+pub fn model_info_or_default(model: &str) -> ModelInfo {
+    static WARNED: std::sync::Mutex<Vec<String>> = std::sync::Mutex::new(Vec::new());
+
+    if let Some(info) = model_info(model) {
+        return info;
+    }
+    let mut warned = WARNED.lock().unwrap_or_else(|e| e.into_inner());
+    if !warned.iter().any(|w| w == model) {
+        warned.push(model.to_string());
+        warn!(
+            "[WARN] model '{}' is not in the known model table; assuming context window {} tokens \
+             and ${}/${} per 1K prompt/completion tokens. Register it with \
+             MODEL_INFO=\"{}=<context>,<input_per_1k>,<output_per_1k>\"",
+            model,
+            CONSERVATIVE_MODEL_INFO.context_window,
+            CONSERVATIVE_MODEL_INFO.input_per_1k,
+            CONSERVATIVE_MODEL_INFO.output_per_1k,
+            model
+        );
+    }
+    CONSERVATIVE_MODEL_INFO
+}
+
+#[derive(Clone)]
+pub struct RealLLM {
+    pub api_key: String,
+    /// 요청 body 의 model (라우팅 규칙이 고른 모델이 있으면 그쪽)
+    pub model: String,
+    pub utf8_policy: Utf8Policy,
+    /// 요청당 후보(choice) 개수. completion 토큰은 n 배로 과금되므로 주의
+    pub n: u32,
+    /// 샘플링 온도. 낮을수록 같은 입력에 같은 코드
+    pub temperature: f32,
+    /// 응답 하나의 completion 토큰 상한. None 이면 API 기본값인데, 큰 파일은 거기서 잘림
+    /// (finish_reason=length). 잘린 응답은 truncation 정책대로 이어 받거나 실패하지만
+    /// 이어 붙인 코드는 경계가 어긋나기 쉬우므로 큰 파일은 처음부터 넉넉히 잡는 편이 나음
+    pub max_tokens: Option<u32>,
+    pub retry: RetryPolicy,
+    pub truncation: TruncationPolicy,
+    /// Some 이면 응답에서 분리한 reasoning 을 이 파일에 JSONL 로 추가 (OPENAI_REASONING_LOG)
+    pub reasoning_log: Option<PathBuf>,
+    /// 취소되면 새 요청/재시도를 보내지 않음 (이미 전송 중인 요청은 응답까지 기다림)
+    pub abort: Option<AbortToken>,
+    /// 요청 하나 전체의 제한 시간 (연결 + 응답 본문까지)
+    pub timeout: Duration,
+    /// chat/completions 앞까지의 주소 (OPENAI_BASE_URL)
+    pub base_url: String,
+    /// Some 이면 ?api-version= 으로 붙임 (Azure. OPENAI_API_VERSION)
+    pub api_version: Option<String>,
+    /// true 면 Authorization: Bearer 대신 api-key 헤더 (Azure)
+    pub api_key_header: bool,
+    /// timeout 으로 한 번 만들어서 재사용 (connection pooling). clone 끼리 공유
+    client: reqwest::blocking::Client,
+    /// clone 끼리 공유하는 provider 별 사용량
+    stats: Arc<Mutex<HashMap<String, ProviderStats>>>,
+}
+
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+// 연결 자체는 이보다 오래 걸리면 거의 확실히 네트워크 문제
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// OPENAI_TIMEOUT_SECS 환경변수 (선택)
+pub fn timeout_from_env() -> Duration {
+    env::var("OPENAI_TIMEOUT_SECS")
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .filter(|&s: &u64| s > 0)
+        .map_or(DEFAULT_REQUEST_TIMEOUT, Duration::from_secs)
+}
+
+fn http_client(timeout: Duration) -> reqwest::blocking::Client {
+    reqwest::blocking::Client::builder()
+        .connect_timeout(CONNECT_TIMEOUT.min(timeout))
+        .timeout(timeout)
+        .build()
+        .unwrap_or_else(|_| reqwest::blocking::Client::new())
+}
+
+impl Default for RealLLM {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RealLLM {
+    pub fn new() -> Self {
+        // AUTO API KEY SYSTEM 사용
+        let key = load_or_create_api_key();
+        Self::with_model(key, model_from_env().unwrap_or_else(|| DEFAULT_MODEL.into()))
+    }
+
+    // 키와 모델을 직접 지정 (OPENAI_MODEL 보다 우선)
+    pub fn with_model(key: impl Into<String>, model: impl Into<String>) -> Self {
+        Self {
+            api_key: key.into(),
+            model: model.into(),
+            utf8_policy: Utf8Policy::from_env(),
+            n: n_from_env(),
+            temperature: temperature_from_env(),
+            max_tokens: max_tokens_from_env(),
+            retry: RetryPolicy::from_env(),
+            truncation: TruncationPolicy::from_env(),
+            reasoning_log: env::var("OPENAI_REASONING_LOG").ok().filter(|p| !p.trim().is_empty()).map(PathBuf::from),
+            abort: None,
+            timeout: timeout_from_env(),
+            base_url: String::new(),
+            api_version: None,
+            api_key_header: false,
+            client: http_client(timeout_from_env()),
+            stats: Arc::default(),
+        }
+        .with_base_url(base_url_from_env())
+    }
+
+    // Azure 주소면 api-key 헤더와 api-version (OPENAI_API_VERSION, 없으면 기본값) 도 같이 설정
+    pub fn with_base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = base_url.into();
+        let version = env::var("OPENAI_API_VERSION").ok().map(|v| v.trim().to_string()).filter(|v| !v.is_empty());
+        if is_azure_endpoint(&self.base_url) {
+            self.api_key_header = true;
+            self.api_version = Some(version.unwrap_or_else(|| AZURE_DEFAULT_API_VERSION.into()));
+        } else {
+            self.api_version = version;
+        }
+        self
+    }
+
+    pub fn with_api_version(mut self, version: Option<String>) -> Self {
+        self.api_version = version;
+        self
+    }
+
+    pub fn with_api_key_header(mut self, enabled: bool) -> Self {
+        self.api_key_header = enabled;
+        self
+    }
+
+    // 전체 주소를 넣었으면 (…/chat/completions) 그대로
+    pub fn chat_url(&self) -> String {
+        let base = self.base_url.trim_end_matches('/');
+        let mut url = if base.ends_with("/chat/completions") { base.to_string() } else { format!("{}/chat/completions", base) };
+        if let Some(v) = &self.api_version {
+            url.push_str(if url.contains('?') { "&" } else { "?" });
+            url.push_str("api-version=");
+            url.push_str(v);
+        }
+        url
+    }
+
+    fn post_request(&self) -> reqwest::blocking::RequestBuilder {
+        let req = self.client.post(self.chat_url());
+        if self.api_key_header {
+            req.header("api-key", &self.api_key)
+        } else {
+            req.header("Authorization", format!("Bearer {}", self.api_key))
+        }
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self.client = http_client(timeout);
+        self
+    }
+
+    pub fn with_truncation(mut self, policy: TruncationPolicy) -> Self {
+        self.truncation = policy;
+        self
+    }
+
+    pub fn with_reasoning_log(mut self, path: impl Into<PathBuf>) -> Self {
+        self.reasoning_log = Some(path.into());
+        self
+    }
+
+    // reasoning 은 출력 코드 대신 로그로만
+    fn log_reasoning(&self, model: &str, choices: &[Choice]) {
+        let Some(path) = &self.reasoning_log else { return };
+        let lines: String = choices
+            .iter()
+            .filter_map(|c| c.reasoning.as_ref())
+            .map(|r| json!({ "model": model, "reasoning": r }).to_string() + "\n")
+            .collect();
+        if lines.is_empty() {
+            return;
+        }
+        let written = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut f| f.write_all(lines.as_bytes()));
+        if let Err(e) = written {
+            debug!("[REASONING] cannot write {}: {}", path.display(), e);
+        }
+    }
+
+    pub fn with_abort(mut self, token: AbortToken) -> Self {
+        self.abort = Some(token);
+        self
+    }
+
+    fn cancelled(&self) -> bool {
+        self.abort.as_ref().is_some_and(|t| t.is_cancelled())
+    }
+
+    fn record(&self, model: &str, update: impl FnOnce(&mut ProviderStats)) {
+        let url = self.chat_url();
+        let key = format!("{} ({})", url.split('?').next().unwrap_or(&url), model);
+        if let Ok(mut stats) = self.stats.lock() {
+            update(stats.entry(key).or_default());
+        }
+    }
+
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn with_n(mut self, n: u32) -> Self {
+        self.n = n.max(1);
+        self
+    }
+
+    pub fn with_temperature(mut self, temperature: f32) -> Self {
+        self.temperature = temperature.clamp(0.0, 2.0);
+        self
+    }
+
+    pub fn with_max_tokens(mut self, max_tokens: u32) -> Self {
+        self.max_tokens = Some(max_tokens).filter(|&n| n > 0);
+        self
+    }
+
+    pub fn with_utf8_policy(mut self, policy: Utf8Policy) -> Self {
+        self.utf8_policy = policy;
+        self
+    }
+
+    fn request(&self, model: Option<&str>, system: Option<&str>, prompt: &str) -> String {
+        match self.request_n(model, system, prompt, 1) {
+            Ok(choices) => choices.into_iter().next().unwrap_or_else(|| "(EMPTY)".into()),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    // choices 배열 전체를 파싱
+    fn request_n(
+        &self,
+        model: Option<&str>,
+        system: Option<&str>,
+        prompt: &str,
+        n: u32,
+    ) -> Result<Vec<String>, LlmError> {
+        let mut messages = Vec::new();
+        if let Some(sys) = system {
+            messages.push(Message::new(Role::System, sys));
+        }
+        messages.push(Message::new(Role::User, prompt));
+        self.send(model, &messages, n)
+    }
+
+    fn send(&self, model: Option<&str>, messages: &[Message], n: u32) -> Result<Vec<String>, LlmError> {
+        if self.api_key.is_empty() {
+            return Err(LlmError::MissingApiKey);
+        }
+
+        let mut messages = assemble_messages(messages)?;
+        let choices = self.post(model, &messages, n)?;
+
+        // 후보가 여러 개면 잘린 후보만 버림
+        if n > 1 {
+            let complete: Vec<String> = choices.into_iter().filter(|c| !c.truncated()).map(|c| c.content).collect();
+            return if complete.is_empty() { Err(LlmError::Truncated(0)) } else { Ok(complete) };
+        }
+
+        let Some(first) = choices.into_iter().next() else {
+            return Ok(Vec::new());
+        };
+        let mut truncated = first.truncated();
+        let mut content = first.content;
+        let max = match self.truncation {
+            TruncationPolicy::Continue { max_continuations } => max_continuations,
+            TruncationPolicy::Fail => 0,
+        };
+        let mut continuations = 0;
+        while truncated {
+            if continuations >= max {
+                return Err(LlmError::Truncated(continuations));
+            }
+            continuations += 1;
+            warn!("[CONTINUE {}/{}] response hit max_tokens, requesting the rest", continuations, max);
+            // 대화 끝에 지금까지 받은 부분과 "continue" 를 붙여서 다시 요청
+            if messages.last().is_some_and(|m| m.role == Role::User && m.content == CONTINUE_PROMPT) {
+                messages.truncate(messages.len() - 2);
+            }
+            messages.push(Message::new(Role::Assistant, content.clone()));
+            messages.push(Message::new(Role::User, CONTINUE_PROMPT));
+            let Some(next) = self.post(model, &messages, 1)?.into_iter().next() else {
+                return Err(LlmError::Truncated(continuations));
+            };
+            truncated = next.truncated();
+            content.push_str(&next.content);
+        }
+        Ok(vec![content])
+    }
+
+    fn body(&self, model_name: &str, messages: &[Message], n: u32) -> serde_json::Value {
+        let messages: Vec<serde_json::Value> = messages
+            .iter()
+            .map(|m| json!({ "role": m.role.as_str(), "content": m.content }))
+            .collect();
+        let mut body = json!({
+            "model": model_name,
+            "messages": messages,
+            // f32 를 그대로 넣으면 0.20000000298.. 로 직렬화됨
+            "temperature": self.temperature.to_string().parse::<f64>().unwrap_or(0.0)
+        });
+        if let Some(max_tokens) = self.max_tokens {
+            body["max_tokens"] = json!(max_tokens);
+        }
+        if n > 1 {
+            body["n"] = json!(n);
+        }
+        body
+    }
+
+    // 요청 한 번 (네트워크 오류는 RetryPolicy 로 재시도)
+    fn post(&self, model: Option<&str>, messages: &[Message], n: u32) -> Result<Vec<Choice>, LlmError> {
+        let model_name = model.unwrap_or(&self.model);
+        let body = self.body(model_name, messages, n);
+
+        let mut attempt = 0;
+        let (status, bytes) = loop {
+            if self.cancelled() {
+                return Err(LlmError::Cancelled);
+            }
+            let started = Instant::now();
+            let res = self
+                .post_request()
+                .json(&body)
+                .send()
+                .and_then(|r| {
+                    let status = r.status();
+                    r.bytes().map(|b| (status, b))
+                });
+            let failed = !matches!(&res, Ok((status, _)) if status.is_success());
+            self.record(model_name, |s| {
+                s.requests += 1;
+                s.total_latency += started.elapsed();
+                s.errors += failed as u64;
+            });
+
+            let reason = match res {
+                // quota 소진도 429 로 오지만 기다려도 풀리지 않으므로 재시도하지 않음
+                Ok((status, bytes))
+                    if attempt < self.retry.max_retries
+                        && is_retryable_status(status.as_u16())
+                        && !String::from_utf8_lossy(&bytes).contains("insufficient_quota") =>
+                {
+                    format!("HTTP {}", status)
+                }
+                Ok(r) => break r,
+                Err(e) if attempt < self.retry.max_retries => e.to_string(),
+                Err(e) if e.is_timeout() => return Err(LlmError::Timeout(self.timeout)),
+                Err(e) => return Err(LlmError::Network(e.to_string())),
+            };
+            let wait = self.retry.delay(attempt);
+            warn!("[RETRY {}/{}] {} (waiting {:?})", attempt + 1, self.retry.max_retries, reason, wait);
+            match &self.abort {
+                Some(token) if !token.sleep(wait) => return Err(LlmError::Cancelled),
+                Some(_) => {}
+                None => std::thread::sleep(wait),
+            }
+            attempt += 1;
+        };
+
+        // 바이트로 받아서 직접 UTF-8 검증 (json() 은 깨진 본문을 조용히 버림)
+        let text = decode_utf8(&bytes, self.utf8_policy).map_err(LlmError::InvalidUtf8)?;
+        let v: serde_json::Value = serde_json::from_str(&text).unwrap_or(json!({}));
+        self.record(model_name, |s| {
+            s.prompt_tokens += v["usage"]["prompt_tokens"].as_u64().unwrap_or(0);
+            s.completion_tokens += v["usage"]["completion_tokens"].as_u64().unwrap_or(0);
+            s.reasoning_tokens += v["usage"]["completion_tokens_details"]["reasoning_tokens"].as_u64().unwrap_or(0);
+            // HTTP 오류는 위에서 이미 셈
+            s.errors += (status.is_success() && v.get("error").is_some()) as u64;
+        });
+        // 일시적인 429 와 달리 quota 소진은 기다려도 풀리지 않음
+        let error = &v["error"];
+        if error["code"].as_str() == Some("insufficient_quota") || error["type"].as_str() == Some("insufficient_quota") {
+            return Err(LlmError::QuotaExhausted(
+                error["message"].as_str().unwrap_or("insufficient_quota").to_string(),
+            ));
+        }
+        // 재시도가 끝났거나 재시도할 수 없는 오류: 빈 응답이 아니라 오류로
+        if !status.is_success() || error.is_object() {
+            let message = error["message"].as_str().map(str::to_string).unwrap_or_else(|| text.trim().to_string());
+            return Err(LlmError::Api { status: status.as_u16(), message });
+        }
+
+        let choices: Vec<Choice> = v["choices"]
+            .as_array()
+            .map(|arr| arr.iter().map(parse_choice).collect())
+            .unwrap_or_default();
+        self.log_reasoning(model_name, &choices);
+        Ok(choices)
+    }
+}
+
+impl RealLLM {
+    // stream: true 로 보내고 SSE chunk 의 delta 를 받는 대로 on_token 에 넘김.
+    // 스트림을 시작하지 못하면 (연결 실패 / HTTP 오류) 재시도가 있는 일반 요청으로 대신 받음
+    fn request_stream(&self, system: Option<&str>, prompt: &str, on_token: &mut dyn FnMut(&str)) -> String {
+        match self.stream(system, prompt, on_token) {
+            Ok(content) => content,
+            Err(e) => e.to_string(),
+        }
+    }
+
+    fn stream(&self, system: Option<&str>, prompt: &str, on_token: &mut dyn FnMut(&str)) -> Result<String, LlmError> {
+        if self.api_key.is_empty() {
+            return Err(LlmError::MissingApiKey);
+        }
+        if self.cancelled() {
+            return Err(LlmError::Cancelled);
+        }
+        let mut messages = Vec::new();
+        if let Some(sys) = system {
+            messages.push(Message::new(Role::System, sys));
+        }
+        messages.push(Message::new(Role::User, prompt));
+        let mut messages = assemble_messages(&messages)?;
+
+        let mut body = self.body(&self.model, &messages, 1);
+        body["stream"] = json!(true);
+        body["stream_options"] = json!({ "include_usage": true });
+        let started = Instant::now();
+        let res = self
+            .post_request()
+            .json(&body)
+            .send();
+        let res = match res {
+            Ok(r) if r.status().is_success() => r,
+            other => {
+                let reason = match other {
+                    Ok(r) => format!("HTTP {}", r.status()),
+                    Err(e) => e.to_string(),
+                };
+                self.record(&self.model, |s| {
+                    s.requests += 1;
+                    s.total_latency += started.elapsed();
+                    s.errors += 1;
+                });
+                warn!("[STREAM] {}, falling back to a regular request", reason);
+                let response = self.request(None, system, prompt);
+                on_token(&response);
+                return Ok(response);
+            }
+        };
+        self.record(&self.model, |s| s.requests += 1);
+
+        let mut reader = io::BufReader::new(res);
+        let mut line = Vec::new();
+        let mut content = String::new();
+        // <think> 구간은 on_token 에 넘기지 않음 (최종 응답에서도 split_reasoning 으로 빠짐)
+        let mut reasoning = ReasoningFilter::default();
+        let mut finish_reason = None;
+        loop {
+            line.clear();
+            match io::BufRead::read_until(&mut reader, b'\n', &mut line) {
+                Ok(0) => break,
+                Ok(_) => {}
+                Err(e) => {
+                    self.record(&self.model, |s| s.errors += 1);
+                    return Err(match e.kind() {
+                        io::ErrorKind::TimedOut => LlmError::Timeout(self.timeout),
+                        _ => LlmError::Network(e.to_string()),
+                    });
+                }
+            }
+            // 중간에 취소되면 응답을 버리고 연결을 끊음
+            if self.cancelled() {
+                return Err(LlmError::Cancelled);
+            }
+            let text = decode_utf8(&line, self.utf8_policy).map_err(LlmError::InvalidUtf8)?;
+            let Some(data) = text.trim().strip_prefix("data:").map(str::trim) else { continue };
+            if data == "[DONE]" {
+                break;
+            }
+            let Ok(v) = serde_json::from_str::<serde_json::Value>(data) else { continue };
+            if let Some(message) = v["error"]["message"].as_str() {
+                self.record(&self.model, |s| s.errors += 1);
+                return Err(LlmError::Api { status: 200, message: message.to_string() });
+            }
+            // include_usage 면 마지막 chunk 에만 usage 가 옴
+            let usage = &v["usage"];
+            if usage.is_object() {
+                self.record(&self.model, |s| {
+                    s.prompt_tokens += usage["prompt_tokens"].as_u64().unwrap_or(0);
+                    s.completion_tokens += usage["completion_tokens"].as_u64().unwrap_or(0);
+                    s.reasoning_tokens += usage["completion_tokens_details"]["reasoning_tokens"].as_u64().unwrap_or(0);
+                });
+            }
+            let choice = &v["choices"][0];
+            if let Some(delta) = choice["delta"]["content"].as_str().filter(|d| !d.is_empty()) {
+                let visible = reasoning.feed(delta);
+                if !visible.is_empty() {
+                    on_token(&visible);
+                }
+                content.push_str(delta);
+            }
+            if let Some(reason) = choice["finish_reason"].as_str() {
+                finish_reason = Some(reason.to_string());
+            }
+        }
+        self.record(&self.model, |s| s.total_latency += started.elapsed());
+
+        // 잘린 나머지는 일반 요청으로 이어 받아서 한 번에 넘김
+        if finish_reason.as_deref() == Some("length") {
+            match self.truncation {
+                TruncationPolicy::Continue { max_continuations } if max_continuations > 0 => {
+                    warn!("[CONTINUE] streamed response hit max_tokens, requesting the rest");
+                    messages.push(Message::new(Role::Assistant, content.clone()));
+                    messages.push(Message::new(Role::User, CONTINUE_PROMPT));
+                    let rest = self.send(None, &messages, 1)?.into_iter().next().unwrap_or_default();
+                    on_token(&reasoning.feed(&rest));
+                    content.push_str(&rest);
+                }
+                _ => return Err(LlmError::Truncated(0)),
+            }
+        }
+        let tail = reasoning.finish();
+        if !tail.is_empty() {
+            on_token(&tail);
+        }
+        if content.is_empty() {
+            return Ok("(EMPTY)".into());
+        }
+        Ok(split_reasoning(&content).0)
+    }
+}
+
+impl LLM for RealLLM {
+    fn provider_stats(&self) -> HashMap<String, ProviderStats> {
+        self.stats.lock().map(|s| s.clone()).unwrap_or_default()
+    }
+
+    fn cache_identity(&self) -> String {
+        format!(
+            "openai|{}|{}|{}|{}|{:?}|{}",
+            self.base_url,
+            self.api_version.as_deref().unwrap_or(""),
+            self.model,
+            self.temperature,
+            self.max_tokens,
+            self.n
+        )
+    }
+
+    fn predict(&self, prompt: &str) -> String {
+        self.request(None, None, prompt)
+    }
+
+    fn predict_with_system(&self, system: &str, prompt: &str) -> String {
+        self.request(None, Some(system), prompt)
+    }
+
+    fn predict_n(&self, prompt: &str, n: u32) -> Result<Vec<String>, LlmError> {
+        self.request_n(None, None, prompt, n.max(1))
+    }
+
+    fn predict_n_with_system(&self, system: &str, prompt: &str, n: u32) -> Result<Vec<String>, LlmError> {
+        self.request_n(None, Some(system), prompt, n.max(1))
+    }
+
+    fn predict_with_model(&self, model: &str, system: &str, prompt: &str) -> String {
+        self.request(Some(model), Some(system), prompt)
+    }
+
+    fn predict_stream(&self, prompt: &str, on_token: &mut dyn FnMut(&str)) -> String {
+        self.request_stream(None, prompt, on_token)
+    }
+
+    fn predict_stream_with_system(&self, system: &str, prompt: &str, on_token: &mut dyn FnMut(&str)) -> String {
+        self.request_stream(Some(system), prompt, on_token)
+    }
+
+    fn predict_chat(&self, messages: &[Message]) -> Result<String, LlmError> {
+        Ok(self.send(None, messages, 1)?.into_iter().next().unwrap_or_else(|| "(EMPTY)".into()))
+    }
+}
+
+// ==========================================================
+// MOCK LLM (API 호출 없는 고정 응답, 테스트용)
+// ==========================================================
+#[derive(Debug, Clone)]
+pub struct MockLLM {
+    /// 프롬프트에 포함된 문자열 -> 응답. 여러 개가 맞으면 가장 긴 키
+    pub responses: HashMap<String, String>,
+    /// 맞는 키가 없을 때의 응답
+    pub fallback: String,
+    /// 받은 프롬프트 (system 은 predict_with_system 기본 구현대로 앞에 붙음). clone 끼리 공유
+    calls: Arc<Mutex<Vec<String>>>,
+}
+
+impl MockLLM {
+    pub fn with_responses<K: Into<String>, V: Into<String>>(responses: impl IntoIterator<Item = (K, V)>) -> Self {
+        Self {
+            responses: responses.into_iter().map(|(k, v)| (k.into(), v.into())).collect(),
+            fallback: "(EMPTY)".into(),
+            calls: Arc::default(),
+        }
+    }
+
+    pub fn with_fallback(mut self, fallback: impl Into<String>) -> Self {
+        self.fallback = fallback.into();
+        self
+    }
+
+    /// 지금까지 받은 프롬프트 (순서대로)
+    pub fn calls(&self) -> Vec<String> {
+        self.calls.lock().map(|c| c.clone()).unwrap_or_default()
+    }
+}
+
+impl LLM for MockLLM {
+    fn predict(&self, prompt: &str) -> String {
+        if let Ok(mut calls) = self.calls.lock() {
+            calls.push(prompt.to_string());
+        }
+        // HashMap 순서와 관계없이 같은 응답이 나오도록 가장 구체적인 (긴) 키 우선
+        self.responses
+            .iter()
+            .filter(|(k, _)| prompt.contains(k.as_str()))
+            .max_by(|a, b| a.0.len().cmp(&b.0.len()).then_with(|| b.0.cmp(a.0)))
+            .map(|(_, v)| v.clone())
+            .unwrap_or_else(|| self.fallback.clone())
+    }
+}
+
+// ==========================================================
+// OFFLINE LLM (--offline, 네트워크 / API 키 없이)
+// ==========================================================
+// 어떤 요청도 보내지 않음. Compiler / SecurityAI 는 offline 이면 아예 부르지 않고,
+// 그래도 호출되면 오류 응답이라 결과 코드로 쓰이지 않음
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OfflineLLM;
+
+impl LLM for OfflineLLM {
+    fn predict(&self, _prompt: &str) -> String {
+        LlmError::Offline.to_string()
+    }
+
+    fn predict_n(&self, _prompt: &str, _n: u32) -> Result<Vec<String>, LlmError> {
+        Err(LlmError::Offline)
+    }
+
+    fn predict_n_with_system(&self, _system: &str, _prompt: &str, _n: u32) -> Result<Vec<String>, LlmError> {
+        Err(LlmError::Offline)
+    }
+
+    fn predict_chat(&self, _messages: &[Message]) -> Result<String, LlmError> {
+        Err(LlmError::Offline)
+    }
+}
+
+// ==========================================================
+// RESPONSE CACHE (같은 요청은 디스크에서, 과금 없이)
+// ==========================================================
+pub const DEFAULT_CACHE_DIR: &str = ".intelligent-compiler-cache";
+
+// 외부 crate 없이 쓰는 SHA-256 (캐시 키 용도)
+pub fn sha256_hex(data: &[u8]) -> String {
+    const K: [u32; 64] = [
+        0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+        0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+        0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+        0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+        0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+        0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+        0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+        0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+    ];
+    let mut h: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+    ];
+
+    let mut msg = data.to_vec();
+    msg.push(0x80);
+    while msg.len() % 64 != 56 {
+        msg.push(0);
+    }
+    msg.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for block in msg.chunks(64) {
+        let mut w = [0u32; 64];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut hh] = h;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = hh.wrapping_add(s1).wrapping_add(ch).wrapping_add(K[i]).wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            hh = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (x, v) in h.iter_mut().zip([a, b, c, d, e, f, g, hh]) {
+            *x = x.wrapping_add(v);
+        }
+    }
+    h.iter().map(|x| format!("{:08x}", x)).collect()
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+// 어떤 LLM 이든 감싸서 응답을 prompt 해시별 파일로 저장. 오류 응답은 저장하지 않음
+#[derive(Debug, Clone)]
+pub struct CachingLLM<L: LLM> {
+    pub inner: L,
+    pub dir: PathBuf,
+    /// clone 끼리 공유 (worker 마다 따로 세지 않음)
+    hits: Arc<AtomicU64>,
+    misses: Arc<AtomicU64>,
+}
+
+impl<L: LLM> CachingLLM<L> {
+    pub fn new(inner: L) -> Self {
+        Self::with_dir(inner, DEFAULT_CACHE_DIR)
+    }
+
+    pub fn with_dir(inner: L, dir: impl Into<PathBuf>) -> Self {
+        Self { inner, dir: dir.into(), hits: Arc::default(), misses: Arc::default() }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats { hits: self.hits.load(Ordering::SeqCst), misses: self.misses.load(Ordering::SeqCst) }
+    }
+
+    /// 저장된 응답을 모두 지움. 지운 파일 수
+    pub fn clear(&self) -> io::Result<usize> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(e) => e,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e),
+        };
+        let mut removed = 0;
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_some_and(|e| e == "txt") {
+                fs::remove_file(&path)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    // provider 설정과 요청 구성 요소를 구분자로 이어서 해시 (provider / model / system 이 다르면 다른 키)
+    fn path_for(&self, parts: &[&str]) -> PathBuf {
+        let identity = self.inner.cache_identity();
+        let key = std::iter::once(identity.as_str()).chain(parts.iter().copied()).collect::<Vec<_>>().join("\u{0}");
+        self.dir.join(format!("{}.txt", sha256_hex(key.as_bytes())))
+    }
+
+    fn cached(&self, parts: &[&str], call: impl FnOnce() -> String) -> String {
+        let path = self.path_for(parts);
+        if let Ok(hit) = fs::read_to_string(&path) {
+            self.hits.fetch_add(1, Ordering::SeqCst);
+            return hit;
+        }
+        self.misses.fetch_add(1, Ordering::SeqCst);
+        let response = call();
+        if !is_error_response(&response) {
+            // worker 끼리 같은 파일을 쓰더라도 반쯤 쓴 파일을 읽지 않도록 임시 파일 후 rename
+            let tmp = path.with_extension(format!("tmp{:?}", std::thread::current().id()).replace(['(', ')'], ""));
+            let stored = fs::create_dir_all(&self.dir)
+                .and_then(|_| fs::write(&tmp, &response))
+                .and_then(|_| fs::rename(&tmp, &path));
+            if let Err(e) = stored {
+                warn!("[CACHE] cannot write {}: {}", path.display(), e);
+            }
+        }
+        response
+    }
+}
+
+impl<L: LLM> LLM for CachingLLM<L> {
+    fn predict(&self, prompt: &str) -> String {
+        self.cached(&["", "", prompt], || self.inner.predict(prompt))
+    }
+
+    fn predict_with_system(&self, system: &str, prompt: &str) -> String {
+        self.cached(&["", system, prompt], || self.inner.predict_with_system(system, prompt))
+    }
+
+    fn predict_with_model(&self, model: &str, system: &str, prompt: &str) -> String {
+        self.cached(&[model, system, prompt], || self.inner.predict_with_model(model, system, prompt))
+    }
+
+    // predict 와 같은 key. cache hit 면 저장된 응답을 한 번에 넘김
+    fn predict_stream(&self, prompt: &str, on_token: &mut dyn FnMut(&str)) -> String {
+        let mut streamed = false;
+        let response = self.cached(&["", "", prompt], || {
+            streamed = true;
+            self.inner.predict_stream(prompt, on_token)
+        });
+        if !streamed {
+            on_token(&response);
+        }
+        response
+    }
+
+    fn predict_stream_with_system(&self, system: &str, prompt: &str, on_token: &mut dyn FnMut(&str)) -> String {
+        let mut streamed = false;
+        let response = self.cached(&["", system, prompt], || {
+            streamed = true;
+            self.inner.predict_stream_with_system(system, prompt, on_token)
+        });
+        if !streamed {
+            on_token(&response);
+        }
+        response
+    }
+
+    // 후보 여러 개는 매번 달라야 의미가 있으므로 캐시하지 않음
+    fn predict_n(&self, prompt: &str, n: u32) -> Result<Vec<String>, LlmError> {
+        self.inner.predict_n(prompt, n)
+    }
+
+    fn predict_n_with_system(&self, system: &str, prompt: &str, n: u32) -> Result<Vec<String>, LlmError> {
+        self.inner.predict_n_with_system(system, prompt, n)
+    }
+
+    fn provider_stats(&self) -> HashMap<String, ProviderStats> {
+        self.inner.provider_stats()
+    }
+
+    fn cache_identity(&self) -> String {
+        self.inner.cache_identity()
+    }
+
+    fn predict_chat(&self, messages: &[Message]) -> Result<String, LlmError> {
+        let transcript: Vec<String> = messages.iter().map(|m| format!("{}: {}", m.role.as_str(), m.content)).collect();
+        let key = transcript.join("\n");
+        let mut failure = None;
+        let response = self.cached(&["chat", &key], || match self.inner.predict_chat(messages) {
+            Ok(r) => r,
+            Err(e) => {
+                let shown = e.to_string();
+                failure = Some(e);
+                shown
+            }
+        });
+        match failure {
+            Some(e) => Err(e),
+            None => Ok(response),
+        }
+    }
+}
+
+// 어떤 LLM 이든 감싸서 분당 요청 수 / 토큰 수 한도 안에서만 보냄 (429 예방)
+// 캐시와 함께 쓸 때는 CachingLLM::new(RateLimitedLLM::new(..)) 로 감싸야 cache hit 를 세지 않음
+#[derive(Debug, Clone)]
+pub struct RateLimitedLLM<L: LLM> {
+    pub inner: L,
+    /// clone 끼리 공유 (worker 전체 합으로 제한)
+    requests: Option<Arc<Mutex<TokenBudget>>>,
+    tokens: Option<Arc<Mutex<TokenBudget>>>,
+    /// 동시에 진행 중인 요청 수 한도 (Ctrl-C 때 기다려야 할 요청 수도 이만큼)
+    slots: Option<Arc<Slots>>,
+}
+
+// 동시 요청 수 세마포어. 요청이 끝나면 SlotGuard 가 돌려줌
+#[derive(Debug)]
+struct Slots {
+    limit: usize,
+    in_flight: Mutex<usize>,
+    freed: Condvar,
+}
+
+impl Slots {
+    fn enter(&self) -> SlotGuard<'_> {
+        let mut n = self.in_flight.lock().unwrap_or_else(|e| e.into_inner());
+        while *n >= self.limit {
+            n = self.freed.wait(n).unwrap_or_else(|e| e.into_inner());
+        }
+        *n += 1;
+        SlotGuard(self)
+    }
+}
+
+struct SlotGuard<'a>(&'a Slots);
+
+impl Drop for SlotGuard<'_> {
+    fn drop(&mut self) {
+        let mut n = self.0.in_flight.lock().unwrap_or_else(|e| e.into_inner());
+        *n -= 1;
+        self.0.freed.notify_one();
+    }
+}
+
+impl<L: LLM> RateLimitedLLM<L> {
+    pub fn new(inner: L) -> Self {
+        Self { inner, requests: None, tokens: None, slots: None }
+    }
+
+    pub fn with_max_concurrency(mut self, max_in_flight: usize) -> Self {
+        self.slots = Some(Arc::new(Slots {
+            limit: max_in_flight.max(1),
+            in_flight: Mutex::new(0),
+            freed: Condvar::new(),
+        }));
+        self
+    }
+
+    pub fn with_rpm(mut self, requests_per_minute: usize) -> Self {
+        self.requests = Some(Arc::new(Mutex::new(TokenBudget::requests_per_minute(requests_per_minute.max(1)))));
+        self
+    }
+
+    pub fn with_tpm(mut self, tokens_per_minute: usize) -> Self {
+        self.tokens = Some(Arc::new(Mutex::new(TokenBudget::new(tokens_per_minute.max(1)))));
+        self
+    }
+
+    // 보내기 전에 모든 한도에 여유가 생길 때까지 블록. 돌려받은 guard 는 요청이 끝날 때까지 잡고 있음
+    fn acquire(&self, request: &str) -> Option<SlotGuard<'_>> {
+        let slot = self.slots.as_ref().map(|s| s.enter());
+        if let Some(b) = &self.requests {
+            TokenBudget::acquire_shared(b, 1, None);
+        }
+        if let Some(b) = &self.tokens {
+            TokenBudget::acquire_shared(b, estimate_request_tokens(request), None);
+        }
+        slot
+    }
+}
+
+impl<L: LLM> LLM for RateLimitedLLM<L> {
+    fn predict(&self, prompt: &str) -> String {
+        let _slot = self.acquire(prompt);
+        self.inner.predict(prompt)
+    }
+
+    fn predict_with_system(&self, system: &str, prompt: &str) -> String {
+        let _slot = self.acquire(&format!("{}{}", system, prompt));
+        self.inner.predict_with_system(system, prompt)
+    }
+
+    fn predict_n(&self, prompt: &str, n: u32) -> Result<Vec<String>, LlmError> {
+        let _slot = self.acquire(prompt);
+        self.inner.predict_n(prompt, n)
+    }
+
+    fn predict_n_with_system(&self, system: &str, prompt: &str, n: u32) -> Result<Vec<String>, LlmError> {
+        let _slot = self.acquire(&format!("{}{}", system, prompt));
+        self.inner.predict_n_with_system(system, prompt, n)
+    }
+
+    fn predict_with_model(&self, model: &str, system: &str, prompt: &str) -> String {
+        let _slot = self.acquire(&format!("{}{}", system, prompt));
+        self.inner.predict_with_model(model, system, prompt)
+    }
+
+    fn predict_stream(&self, prompt: &str, on_token: &mut dyn FnMut(&str)) -> String {
+        let _slot = self.acquire(prompt);
+        self.inner.predict_stream(prompt, on_token)
+    }
+
+    fn predict_stream_with_system(&self, system: &str, prompt: &str, on_token: &mut dyn FnMut(&str)) -> String {
+        let _slot = self.acquire(&format!("{}{}", system, prompt));
+        self.inner.predict_stream_with_system(system, prompt, on_token)
+    }
+
+    fn provider_stats(&self) -> HashMap<String, ProviderStats> {
+        self.inner.provider_stats()
+    }
+
+    fn cache_identity(&self) -> String {
+        self.inner.cache_identity()
+    }
+
+    fn predict_chat(&self, messages: &[Message]) -> Result<String, LlmError> {
+        let transcript: String = messages.iter().map(|m| m.content.as_str()).collect();
+        let _slot = self.acquire(&transcript);
+        self.inner.predict_chat(messages)
+    }
+}
+
+// ==========================================================
+// RECORD / REPLAY LLM (VCR 방식 cassette, 테스트용)
+// ==========================================================
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CassetteMode {
+    /// 항상 inner 로 호출하고 (요청, 응답) 을 cassette 에 기록
+    Record,
+    /// cassette 에서만 응답. 기록에 없는 요청은 LlmError::NotRecorded
+    Replay,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CassetteEntry {
+    /// "predict" / "predict_n" / "chat"
+    pub kind: String,
+    #[serde(default)]
+    pub model: String,
+    #[serde(default)]
+    pub system: String,
+    pub prompt: String,
+    /// predict_n 은 후보 전부, 그 외는 하나
+    pub responses: Vec<String>,
+}
+
+// 줄바꿈 / 들여쓰기 / 끝 공백 차이는 같은 요청으로 봄
+pub fn normalize_prompt(s: &str) -> String {
+    s.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+pub struct TestLLM {
+    /// Replay 에서는 None
+    inner: Option<Box<dyn LLM + Send + Sync>>,
+    pub mode: CassetteMode,
+    pub path: PathBuf,
+    entries: Mutex<Vec<CassetteEntry>>,
+}
+
+impl TestLLM {
+    // cassette 가 이미 있으면 이어서 기록 (같은 요청은 새 응답으로 교체)
+    pub fn record(inner: impl LLM + Send + Sync + 'static, path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let entries = match fs::read_to_string(&path) {
+            Ok(text) => serde_json::from_str(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Self { inner: Some(Box::new(inner)), mode: CassetteMode::Record, path, entries: Mutex::new(entries) })
+    }
+
+    pub fn replay(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let text = fs::read_to_string(&path)?;
+        let entries = serde_json::from_str(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(Self { inner: None, mode: CassetteMode::Replay, path, entries: Mutex::new(entries) })
+    }
+
+    pub fn entries(&self) -> Vec<CassetteEntry> {
+        self.entries.lock().map(|e| e.clone()).unwrap_or_default()
+    }
+
+    fn matches(e: &CassetteEntry, kind: &str, model: &str, system: &str, prompt: &str) -> bool {
+        e.kind == kind
+            && e.model == model
+            && normalize_prompt(&e.system) == normalize_prompt(system)
+            && normalize_prompt(&e.prompt) == normalize_prompt(prompt)
+    }
+
+    // worker 끼리 같은 파일을 쓰더라도 반쯤 쓴 cassette 를 남기지 않도록 임시 파일 후 rename
+    fn save(&self, entries: &[CassetteEntry]) -> io::Result<()> {
+        let text = serde_json::to_string_pretty(entries).map_err(io::Error::other)?;
+        if let Some(dir) = self.path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let tmp = self.path.with_extension("json.tmp");
+        fs::write(&tmp, format!("{}\n", text))?;
+        fs::rename(&tmp, &self.path)
+    }
+
+    fn call(
+        &self,
+        kind: &str,
+        model: &str,
+        system: &str,
+        prompt: &str,
+        live: impl FnOnce(&dyn LLM) -> Result<Vec<String>, LlmError>,
+    ) -> Result<Vec<String>, LlmError> {
+        let Some(inner) = &self.inner else {
+            let entries = self.entries.lock().map_err(|_| LlmError::NotRecorded(prompt.into()))?;
+            return entries
+                .iter()
+                .find(|e| Self::matches(e, kind, model, system, prompt))
+                .map(|e| e.responses.clone())
+                .ok_or_else(|| LlmError::NotRecorded(normalize_prompt(prompt).chars().take(80).collect()));
+        };
+        let responses = live(inner.as_ref())?;
+        // 오류 응답은 기록하지 않음 (replay 에서 실패를 재현할 이유가 없음)
+        if responses.iter().any(|r| is_error_response(r)) {
+            return Ok(responses);
+        }
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.retain(|e| !Self::matches(e, kind, model, system, prompt));
+            entries.push(CassetteEntry {
+                kind: kind.into(),
+                model: model.into(),
+                system: system.into(),
+                prompt: prompt.into(),
+                responses: responses.clone(),
+            });
+            if let Err(e) = self.save(&entries) {
+                warn!("[CASSETTE] cannot write {}: {}", self.path.display(), e);
+            }
+        }
+        Ok(responses)
+    }
+
+    fn call_one(&self, model: &str, system: &str, prompt: &str, live: impl FnOnce(&dyn LLM) -> String) -> String {
+        match self.call("predict", model, system, prompt, |l| Ok(vec![live(l)])) {
+            Ok(r) => r.into_iter().next().unwrap_or_else(|| "(EMPTY)".into()),
+            Err(e) => e.to_string(),
+        }
+    }
+}
+
+impl LLM for TestLLM {
+    fn predict(&self, prompt: &str) -> String {
+        self.call_one("", "", prompt, |l| l.predict(prompt))
+    }
+
+    fn predict_with_system(&self, system: &str, prompt: &str) -> String {
+        self.call_one("", system, prompt, |l| l.predict_with_system(system, prompt))
+    }
+
+    fn predict_with_model(&self, model: &str, system: &str, prompt: &str) -> String {
+        self.call_one(model, system, prompt, |l| l.predict_with_model(model, system, prompt))
+    }
+
+    fn predict_n(&self, prompt: &str, n: u32) -> Result<Vec<String>, LlmError> {
+        self.call("predict_n", "", "", prompt, |l| l.predict_n(prompt, n))
+    }
+
+    fn predict_n_with_system(&self, system: &str, prompt: &str, n: u32) -> Result<Vec<String>, LlmError> {
+        self.call("predict_n", "", system, prompt, |l| l.predict_n_with_system(system, prompt, n))
+    }
+
+    fn provider_stats(&self) -> HashMap<String, ProviderStats> {
+        self.inner.as_ref().map(|l| l.provider_stats()).unwrap_or_default()
+    }
+
+    fn cache_identity(&self) -> String {
+        self.inner.as_ref().map(|l| l.cache_identity()).unwrap_or_default()
+    }
+
+    fn predict_chat(&self, messages: &[Message]) -> Result<String, LlmError> {
+        let transcript: Vec<String> = messages.iter().map(|m| format!("{}: {}", m.role.as_str(), m.content)).collect();
+        let responses = self.call("chat", "", "", &transcript.join("\n"), |l| l.predict_chat(messages).map(|r| vec![r]))?;
+        Ok(responses.into_iter().next().unwrap_or_default())
+    }
+}
+
+// ==========================================================
+// OTHER PROVIDERS (Anthropic / Ollama)
+// ==========================================================
+pub const ANTHROPIC_DEFAULT_URL: &str = "https://api.anthropic.com";
+pub const ANTHROPIC_DEFAULT_MODEL: &str = "claude-sonnet-4-5";
+pub const OLLAMA_DEFAULT_URL: &str = "http://localhost:11434";
+pub const OLLAMA_DEFAULT_MODEL: &str = "llama3.1";
+
+fn env_or(name: &str, default: &str) -> String {
+    env::var(name).ok().map(|v| v.trim().to_string()).filter(|v| !v.is_empty()).unwrap_or_else(|| default.into())
+}
+
+// 요청 하나를 보내고 JSON 응답 본문을 돌려줌 (HTTP 오류는 본문의 메시지로).
+// timeout: 요청을 만든 client 의 제한 시간 (오류 메시지용)
+fn post_json(
+    req: reqwest::blocking::RequestBuilder,
+    body: &serde_json::Value,
+    timeout: Duration,
+) -> Result<serde_json::Value, LlmError> {
+    let res = req.json(body).send().map_err(|e| match e.is_timeout() {
+        true => LlmError::Timeout(timeout),
+        false => LlmError::Network(e.to_string()),
+    })?;
+    let status = res.status();
+    // 본문을 받는 도중의 timeout 도 같은 오류로. JSON 이 아닌 본문은 빈 객체
+    let v: serde_json::Value = match res.json() {
+        Ok(v) => v,
+        Err(e) if e.is_timeout() => return Err(LlmError::Timeout(timeout)),
+        Err(_) => json!({}),
+    };
+    if !status.is_success() {
+        let message = v["error"]["message"].as_str().or_else(|| v["error"].as_str()).unwrap_or("request failed");
+        return Err(LlmError::Api { status: status.as_u16(), message: message.to_string() });
+    }
+    Ok(v)
+}
+
+// post_json 결과를 "endpoint (model)" 별 사용량에 기록. tokens: 응답 본문에서 (prompt, completion) 토큰 수
+fn record_provider_request(
+    stats: &Mutex<HashMap<String, ProviderStats>>,
+    key: String,
+    started: Instant,
+    result: &Result<serde_json::Value, LlmError>,
+    tokens: impl FnOnce(&serde_json::Value) -> (u64, u64),
+) {
+    let Ok(mut stats) = stats.lock() else { return };
+    let s = stats.entry(key).or_default();
+    s.requests += 1;
+    s.total_latency += started.elapsed();
+    match result {
+        Ok(v) => {
+            let (prompt, completion) = tokens(v);
+            s.prompt_tokens += prompt;
+            s.completion_tokens += completion;
+        }
+        Err(_) => s.errors += 1,
+    }
+}
+
+// Anthropic Messages API (ANTHROPIC_API_KEY / ANTHROPIC_MODEL / ANTHROPIC_BASE_URL)
+#[derive(Debug, Clone)]
+pub struct AnthropicLLM {
+    pub api_key: String,
+    pub model: String,
+    pub base_url: String,
+    /// Messages API 는 max_tokens 가 필수
+    pub max_tokens: u32,
+    /// 요청 하나 전체의 제한 시간 (OPENAI_TIMEOUT_SECS 와 같은 설정)
+    pub timeout: Duration,
+    client: reqwest::blocking::Client,
+    /// clone 끼리 공유하는 provider 별 사용량
+    stats: Arc<Mutex<HashMap<String, ProviderStats>>>,
+}
+
+impl Default for AnthropicLLM {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AnthropicLLM {
+    pub fn new() -> Self {
+        Self {
+            api_key: env_or("ANTHROPIC_API_KEY", ""),
+            model: env_or("ANTHROPIC_MODEL", ANTHROPIC_DEFAULT_MODEL),
+            base_url: env_or("ANTHROPIC_BASE_URL", ANTHROPIC_DEFAULT_URL),
+            max_tokens: 8192,
+            timeout: timeout_from_env(),
+            client: http_client(timeout_from_env()),
+            stats: Arc::default(),
+        }
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self.client = http_client(timeout);
+        self
+    }
+
+    fn request(&self, model: &str, system: Option<&str>, prompt: &str) -> String {
+        if self.api_key.is_empty() {
+            return "(ERROR: ANTHROPIC_API_KEY missing.)".into();
+        }
+        let mut body = json!({
+            "model": model,
+            "max_tokens": self.max_tokens,
+            "messages": [{ "role": "user", "content": prompt }]
+        });
+        if let Some(system) = system {
+            body["system"] = json!(system);
+        }
+        let url = format!("{}/v1/messages", self.base_url.trim_end_matches('/'));
+        let req = self
+            .client
+            .post(&url)
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", "2023-06-01");
+        let started = Instant::now();
+        let result = post_json(req, &body, self.timeout);
+        record_provider_request(&self.stats, format!("{} ({})", url, model), started, &result, |v| {
+            (v["usage"]["input_tokens"].as_u64().unwrap_or(0), v["usage"]["output_tokens"].as_u64().unwrap_or(0))
+        });
+        match result {
+            // content 는 블록 배열. thinking 블록은 빼고 text 만
+            Ok(v) => {
+                let text: String = v["content"]
+                    .as_array()
+                    .map(|blocks| {
+                        blocks.iter().filter(|b| b["type"] == "text").filter_map(|b| b["text"].as_str()).collect()
+                    })
+                    .unwrap_or_default();
+                // max_tokens 에서 멈춘 응답은 반쪽 파일이므로 walk 가 쓰지 않도록 표시
+                if v["stop_reason"] == "max_tokens" {
+                    return LlmError::Truncated(0).to_string();
+                }
+                if text.is_empty() { "(EMPTY)".into() } else { text }
+            }
+            Err(e) => e.to_string(),
+        }
+    }
+}
+
+impl LLM for AnthropicLLM {
+    fn cache_identity(&self) -> String {
+        format!("anthropic|{}|{}|{}", self.base_url, self.model, self.max_tokens)
+    }
+
+    fn provider_stats(&self) -> HashMap<String, ProviderStats> {
+        self.stats.lock().map(|s| s.clone()).unwrap_or_default()
+    }
+
+    fn predict(&self, prompt: &str) -> String {
+        self.request(&self.model, None, prompt)
+    }
+
+    fn predict_with_system(&self, system: &str, prompt: &str) -> String {
+        self.request(&self.model, Some(system), prompt)
+    }
+
+    fn predict_with_model(&self, model: &str, system: &str, prompt: &str) -> String {
+        self.request(model, Some(system), prompt)
+    }
+}
+
+// 로컬 Ollama 서버의 /api/chat (OLLAMA_HOST / OLLAMA_MODEL)
+#[derive(Debug, Clone)]
+pub struct OllamaLLM {
+    pub model: String,
+    pub base_url: String,
+    /// 요청 하나 전체의 제한 시간. 로컬 서버가 멈춰도 CLI 가 같이 멈추지 않게
+    pub timeout: Duration,
+    client: reqwest::blocking::Client,
+    stats: Arc<Mutex<HashMap<String, ProviderStats>>>,
+}
+
+impl Default for OllamaLLM {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OllamaLLM {
+    pub fn new() -> Self {
+        // ollama 자체도 OLLAMA_HOST 를 "127.0.0.1:11434" 처럼 scheme 없이 받음
+        let host = env_or("OLLAMA_HOST", OLLAMA_DEFAULT_URL);
+        let base_url = if host.contains("://") { host } else { format!("http://{}", host) };
+        Self::with_model(base_url, env_or("OLLAMA_MODEL", OLLAMA_DEFAULT_MODEL))
+    }
+
+    pub fn with_model(base_url: impl Into<String>, model: impl Into<String>) -> Self {
+        Self {
+            model: model.into(),
+            base_url: base_url.into(),
+            timeout: timeout_from_env(),
+            client: http_client(timeout_from_env()),
+            stats: Arc::default(),
+        }
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self.client = http_client(timeout);
+        self
+    }
+
+    fn request(&self, model: &str, system: Option<&str>, prompt: &str) -> String {
+        let mut messages = Vec::new();
+        if let Some(system) = system {
+            messages.push(json!({ "role": "system", "content": system }));
+        }
+        messages.push(json!({ "role": "user", "content": prompt }));
+        let body = json!({ "model": model, "messages": messages, "stream": false });
+        let url = format!("{}/api/chat", self.base_url.trim_end_matches('/'));
+        let started = Instant::now();
+        let result = post_json(self.client.post(&url), &body, self.timeout);
+        record_provider_request(&self.stats, format!("{} ({})", url, model), started, &result, |v| {
+            (v["prompt_eval_count"].as_u64().unwrap_or(0), v["eval_count"].as_u64().unwrap_or(0))
+        });
+        match result {
+            // 로컬 reasoning 모델은 <think> 를 본문에 섞어 보냄
+            Ok(v) => match v["message"]["content"].as_str() {
+                // num_predict 에 걸려 멈춘 응답
+                Some(_) if v["done_reason"] == "length" => LlmError::Truncated(0).to_string(),
+                Some(content) => split_reasoning(content).0,
+                None => "(EMPTY)".into(),
+            },
+            Err(e) => e.to_string(),
+        }
+    }
+}
+
+impl LLM for OllamaLLM {
+    fn cache_identity(&self) -> String {
+        format!("ollama|{}|{}", self.base_url, self.model)
+    }
+
+    fn provider_stats(&self) -> HashMap<String, ProviderStats> {
+        self.stats.lock().map(|s| s.clone()).unwrap_or_default()
+    }
+
+    fn predict(&self, prompt: &str) -> String {
+        self.request(&self.model, None, prompt)
+    }
+
+    fn predict_with_system(&self, system: &str, prompt: &str) -> String {
+        self.request(&self.model, Some(system), prompt)
+    }
+
+    fn predict_with_model(&self, model: &str, system: &str, prompt: &str) -> String {
+        self.request(model, Some(system), prompt)
+    }
+}
+
+// LLM_PROVIDER 환경변수 (기본 openai)
+pub fn provider_from_env() -> String {
+    env_or("LLM_PROVIDER", "openai").to_lowercase()
+}
+
+pub fn make_llm(provider: &str) -> Result<Box<dyn LLM + Send + Sync>, String> {
+    match provider.trim().to_lowercase().as_str() {
+        "openai" => Ok(Box::new(RealLLM::new())),
+        "anthropic" | "claude" => Ok(Box::new(AnthropicLLM::new())),
+        "ollama" => Ok(Box::new(OllamaLLM::new())),
+        other => Err(format!("unknown LLM provider '{}' (openai, anthropic, ollama)", other)),
+    }
+}
+
+// Box<dyn LLM> / Arc<dyn LLM> 도 제네릭 API 에 그대로 넘길 수 있게
+macro_rules! forward_llm {
+    ($ptr:ident) => {
+        impl<T: LLM + ?Sized> LLM for $ptr<T> {
+            fn predict(&self, prompt: &str) -> String {
+                (**self).predict(prompt)
+            }
+
+            fn predict_with_system(&self, system: &str, prompt: &str) -> String {
+                (**self).predict_with_system(system, prompt)
+            }
+
+            fn predict_n(&self, prompt: &str, n: u32) -> Result<Vec<String>, LlmError> {
+                (**self).predict_n(prompt, n)
+            }
+
+            fn predict_n_with_system(&self, system: &str, prompt: &str, n: u32) -> Result<Vec<String>, LlmError> {
+                (**self).predict_n_with_system(system, prompt, n)
+            }
+
+            fn predict_with_model(&self, model: &str, system: &str, prompt: &str) -> String {
+                (**self).predict_with_model(model, system, prompt)
+            }
+
+            fn predict_stream(&self, prompt: &str, on_token: &mut dyn FnMut(&str)) -> String {
+                (**self).predict_stream(prompt, on_token)
+            }
+
+            fn predict_stream_with_system(&self, system: &str, prompt: &str, on_token: &mut dyn FnMut(&str)) -> String {
+                (**self).predict_stream_with_system(system, prompt, on_token)
+            }
+
+            fn provider_stats(&self) -> HashMap<String, ProviderStats> {
+                (**self).provider_stats()
+            }
+
+            fn cache_identity(&self) -> String {
+                (**self).cache_identity()
+            }
+
+            fn predict_chat(&self, messages: &[Message]) -> Result<String, LlmError> {
+                (**self).predict_chat(messages)
+            }
+        }
+    };
+}
+
+forward_llm!(Box);
+forward_llm!(Arc);
+
+// ==========================================================
+// FEATURE ANALYSIS (파싱한 노드 -> node.meta 기능 플래그 -> VersionAI)
+// ==========================================================
+/// 타입에 묶이지 않은 파라미터 연산 (go 에서는 type parameter 필요)
+pub const FEATURE_GENERICS: &str = "uses_generics";
+/// await / async 를 쓰는 코드
+pub const FEATURE_ASYNC: &str = "uses_async";
+/// 함수 안에서 정의한 함수 (closure / lambda)
+pub const FEATURE_LAMBDAS: &str = "uses_lambdas";
+/// C++20 coroutine (co_await 등). 파서가 직접 찾지는 않고 versions.toml / 직접 지정용
+pub const FEATURE_COROUTINES: &str = "uses_coroutines";
+
+// version 추론 전에 노드마다 쓰는 기능을 찾아 meta 에 "true" 로 기록.
+// 자식에서 찾은 플래그는 감싸는 함수에도 올림. 이미 있는 플래그는 지우지 않음
+pub fn analyze_features(nodes: &mut [Node]) {
+    for node in nodes {
+        mark_features(node, false);
+    }
+}
+
+// 이 노드와 자식에서 찾은 플래그. nested: 함수 본문 안인지
+fn mark_features(node: &mut Node, nested: bool) -> Vec<&'static str> {
+    let mut found = Vec::new();
+    match &mut node.kind {
+        // 파서에 async 문법이 없으므로 키워드는 식별자로 남음
+        NodeKind::Identifier(x) if x == "await" || x == "async" => found.push(FEATURE_ASYNC),
+        NodeKind::BinaryOp { left, right, .. } => {
+            found.extend(mark_features(left, nested));
+            found.extend(mark_features(right, nested));
+        }
+        NodeKind::Function { name, args, body } => {
+            if nested {
+                found.push(FEATURE_LAMBDAS);
+            }
+            if name.starts_with("async") {
+                found.push(FEATURE_ASYNC);
+            }
+            if is_generic_function(args, body) {
+                found.push(FEATURE_GENERICS);
+            }
+            for b in body.iter_mut() {
+                found.extend(mark_features(b, true));
+            }
+        }
+        _ => {}
+    }
+    found.sort_unstable();
+    found.dedup();
+    for f in &found {
+        node.meta.insert(f.to_string(), "true".into());
+    }
+    found
+}
+
+// 파라미터끼리의 연산만 있고 숫자 리터럴이 없으면 타입을 정할 근거가 없음 (fn add(a, b) { a + b })
+fn is_generic_function(args: &[String], body: &[Node]) -> bool {
+    fn has_literal(node: &Node) -> bool {
+        match &node.kind {
+            NodeKind::Integer(_) | NodeKind::Number(_) => true,
+            NodeKind::BinaryOp { left, right, .. } => has_literal(left) || has_literal(right),
+            _ => false,
+        }
+    }
+    !args.is_empty()
+        && body.iter().any(|n| matches!(n.kind, NodeKind::BinaryOp { .. }))
+        && !body.iter().any(has_literal)
+}
+
+// ==========================================================
+// VERSION AI
+// ==========================================================
+pub struct VersionAI {
+    // 언어 -> 버전 목록 (오래된 것 -> 최신 순)
+    map: HashMap<String, Vec<String>>,
+    // 언어 -> (node.meta 기능 플래그, 최소 요구 버전)
+    features: HashMap<String, Vec<(String, String)>>,
+}
+
+impl Default for VersionAI {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 작업 디렉토리에 있으면 내장 버전 표 위에 덮어씀
+pub const VERSIONS_FILE: &str = "versions.toml";
+
+impl VersionAI {
+    pub fn new() -> Self {
+        let mut v = Self { map: HashMap::new(), features: HashMap::new() };
+        v.set_versions("go", &["1.16", "1.17", "1.18", "1.19", "1.20", "1.21"]);
+        v.set_versions("cpp", &["11", "14", "17", "20", "23"]);
+        v.set_versions("swift", &["5.5", "5.7", "5.9", "6.0"]);
+        v.set_feature("go", FEATURE_GENERICS, "1.18");
+        v.set_feature("cpp", FEATURE_COROUTINES, "20");
+        v.set_feature("cpp", FEATURE_ASYNC, "20");
+        v.set_feature("cpp", FEATURE_LAMBDAS, "11");
+        v.set_feature("swift", FEATURE_ASYNC, "5.5");
+        v
+    }
+
+    fn set_versions(&mut self, lang: &str, versions: &[&str]) {
+        self.map.insert(lang.into(), versions.iter().map(|v| v.to_string()).collect());
+    }
+
+    fn set_feature(&mut self, lang: &str, feature: &str, version: &str) {
+        let rules = self.features.entry(lang.into()).or_default();
+        match rules.iter_mut().find(|(f, _)| f == feature) {
+            Some(rule) => rule.1 = version.into(),
+            None => rules.push((feature.into(), version.into())),
+        }
+    }
+
+    // versions.toml 이 있으면 읽고, 없거나 잘못됐으면 내장 표
+    pub fn discover() -> Self {
+        let path = Path::new(VERSIONS_FILE);
+        if !path.exists() {
+            return Self::new();
+        }
+        match Self::load(path) {
+            Ok(v) => {
+                debug!("[VERSIONS] loaded {}", path.display());
+                v
+            }
+            Err(e) => {
+                warn!("[VERSIONS] {}: {} (using bundled defaults)", path.display(), e);
+                Self::new()
+            }
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Self::parse(&text)
+    }
+
+    // TOML 의 필요한 부분만 지원:
+    //   [go]
+    //   versions = ["1.16", "1.18", "1.21"]
+    //   [go.features]
+    //   uses_generics = "1.18"
+    // 파일에 있는 언어의 versions 는 교체, features 는 내장 규칙에 추가 / 덮어씀
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut v = Self::new();
+        let mut section: Option<(String, bool)> = None;
+        for (i, raw) in text.lines().enumerate() {
+            let line = strip_toml_comment(raw).trim();
+            if line.is_empty() {
+                continue;
+            }
+            let err = |msg: String| format!("line {}: {}", i + 1, msg);
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                let name = name.trim();
+                section = Some(match name.strip_suffix(".features") {
+                    Some(lang) => (lang.trim().to_string(), true),
+                    None if !name.contains('.') => (name.to_string(), false),
+                    None => return Err(err(format!("unknown table [{}]", name))),
+                });
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(err(format!("expected key = value, got '{}'", line)));
+            };
+            let Some((lang, is_features)) = &section else {
+                return Err(err("key outside of a [language] table".into()));
+            };
+            let key = key.trim();
+            let value = value.trim();
+            if *is_features {
+                let version = parse_toml_scalar(value).ok_or_else(|| err(format!("invalid version for {}", key)))?;
+                v.set_feature(lang, key, &version);
+            } else if key == "versions" {
+                let versions = parse_toml_array(value).ok_or_else(|| err("versions must be an array of strings".into()))?;
+                if versions.is_empty() {
+                    return Err(err(format!("{} has no versions", lang)));
+                }
+                v.map.insert(lang.clone(), versions);
+            } else {
+                return Err(err(format!("unknown key '{}' in [{}]", key, lang)));
+            }
+        }
+        Ok(v)
+    }
+
+    pub fn known(&self, lang: &str) -> &[String] {
+        self.map.get(lang).map(|v| v.as_slice()).unwrap_or(&[])
+    }
+
+    // --target-version 값 검증. 목록에 없는 언어는 검증할 수 없으니 통과
+    pub fn validate(&self, lang: &str, version: &str) -> Result<(), String> {
+        let known = self.known(lang);
+        if known.is_empty() || known.iter().any(|v| v == version) {
+            Ok(())
+        } else {
+            Err(format!(
+                "unknown {} version '{}' (known: {})",
+                lang,
+                version,
+                known.join(", ")
+            ))
+        }
+    }
+
+    // 노드가 사용하는 기능 기준 최소 요구 버전 (여러 개면 가장 높은 것)
+    pub fn required(&self, lang: &str, node: &Node) -> Option<&str> {
+        self.features
+            .get(lang)?
+            .iter()
+            .filter(|(feature, _)| node.meta.get(feature).is_some_and(|v| v == "true"))
+            .map(|(_, version)| version.as_str())
+            .max_by(|a, b| version_cmp(a, b))
+    }
+
+    // 명시한 target 이 있으면 추론 없이 그대로 사용. 코드가 target 보다 새 버전을 필요로 하면 경고만
+    pub fn resolve(&self, lang: &str, node: &Node, target: Option<&str>) -> String {
+        let Some(target) = target else {
+            return self.infer(lang, node);
+        };
+        if let Some(req) = self.required(lang, node) {
+            if version_cmp(req, target) == std::cmp::Ordering::Greater {
+                warn!(
+                    "[VERSION WARN] {} code likely needs {} {} but target is {}",
+                    lang, lang, req, target
+                );
+            }
+        }
+        target.to_string()
+    }
+
+    // 기능 플래그가 있으면 그 최소 요구 버전, 없으면 알려진 최신 버전
+    pub fn infer(&self, lang: &str, node: &Node) -> String {
+        self.required(lang, node)
+            .or_else(|| self.map.get(lang).and_then(|v| v.last()).map(String::as_str))
+            .unwrap_or("unknown")
+            .to_string()
+    }
+}
+
+// 따옴표 밖의 # 부터 주석
+fn strip_toml_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+// "1.18" 또는 따옴표 없는 20 / 1.18
+fn parse_toml_scalar(value: &str) -> Option<String> {
+    let value = value.trim();
+    let inner = match value.strip_prefix('"') {
+        Some(rest) => rest.strip_suffix('"')?,
+        None => value,
+    };
+    (!inner.is_empty() && !inner.contains('"')).then(|| inner.to_string())
+}
+
+fn parse_toml_array(value: &str) -> Option<Vec<String>> {
+    let inner = value.trim().strip_prefix('[')?.strip_suffix(']')?;
+    inner
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(parse_toml_scalar)
+        .collect()
+}
+
+// "1.18" / "17" 같은 점 구분 버전 숫자 비교
+pub fn version_cmp(a: &str, b: &str) -> std::cmp::Ordering {
+    let parts = |v: &str| -> Vec<u64> { v.split('.').map(|p| p.trim().parse().unwrap_or(0)).collect() };
+    let (a, b) = (parts(a), parts(b));
+    for i in 0..a.len().max(b.len()) {
+        let ord = a.get(i).unwrap_or(&0).cmp(b.get(i).unwrap_or(&0));
+        if ord != std::cmp::Ordering::Equal {
+            return ord;
+        }
+    }
+    std::cmp::Ordering::Equal
+}
+
+// 프롬프트에 붙이는 최소 지원 버전 제약
+pub fn version_constraint(lang: &str, version: &str) -> String {
+    format!(
+        "Hard constraint: the code must compile on {} {}. Do not use language or standard library features newer than that.",
+        lang, version
+    )
+}
+
+// "go=1.18" 형식의 --target-version 값. "17" 처럼 언어가 없으면 default_lang (선택한 대상 언어)
+pub fn parse_target_version(spec: &str, default_lang: &str) -> Result<(String, String), String> {
+    match spec.split_once('=') {
+        Some((lang, ver)) if !lang.trim().is_empty() && !ver.trim().is_empty() => {
+            Ok((lang.trim().to_string(), ver.trim().to_string()))
+        }
+        None if !spec.trim().is_empty() => Ok((default_lang.to_string(), spec.trim().to_string())),
+        _ => Err(format!("invalid target version '{}' (expected <lang>=<version> or <version>)", spec)),
+    }
+}
+
+// ==========================================================
+// SEMANTIC ENGINE
+// ==========================================================
+pub struct SemanticEngine;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SemanticKind {
+    Identifier,
+    Literal,
+    /// + - * / %
+    Arithmetic,
+    /// 그 외 이항 연산자
+    Operation,
+    Function,
+    Unknown,
+}
+
+pub struct SemanticInfo {
+    pub kind: SemanticKind,
+    pub meaning: String,
+}
+
+// 이항 연산자 이름 (모르는 연산자는 None)
+fn arithmetic_name(op: &str) -> Option<&'static str> {
+    match op {
+        "+" => Some("addition"),
+        "-" => Some("subtraction"),
+        "*" => Some("multiplication"),
+        "/" => Some("division"),
+        "%" => Some("remainder"),
+        _ => None,
+    }
+}
+
+fn plural(n: usize, word: &str) -> String {
+    format!("{} {}{}", n, word, if n == 1 { "" } else { "s" })
+}
+
+impl SemanticEngine {
+    pub fn analyze(&self, node: &Node) -> SemanticInfo {
+        let (kind, meaning) = match &node.kind {
+            NodeKind::Identifier(x) => (SemanticKind::Identifier, format!("identifier '{}'", x)),
+            NodeKind::Integer(n) => (SemanticKind::Literal, format!("integer literal {}", n)),
+            NodeKind::Number(n) => (SemanticKind::Literal, format!("number literal {}", n)),
+            NodeKind::BinaryOp { op, left, right } => {
+                // 피연산자도 재귀적으로 설명
+                let (l, r) = (self.analyze(left).meaning, self.analyze(right).meaning);
+                match arithmetic_name(op) {
+                    Some(name) => (SemanticKind::Arithmetic, format!("arithmetic {} of ({}) and ({})", name, l, r)),
+                    None => (SemanticKind::Operation, format!("'{}' operation on ({}) and ({})", op, l, r)),
+                }
+            }
+            NodeKind::Function { name, args, body } => (
+                SemanticKind::Function,
+                format!(
+                    "function '{}' taking {} with {}-statement body",
+                    name,
+                    plural(args.len(), "argument"),
+                    body.len()
+                ),
+            ),
+            NodeKind::Unknown => (SemanticKind::Unknown, "unknown".into()),
+        };
+        SemanticInfo { kind, meaning }
+    }
+}
+
+// ==========================================================
+// SECURITY AI
+// ==========================================================
+pub struct SecurityAI<L: LLM> {
+    pub llm: L,
+    pub persona: Option<String>,
+    /// 실행할 규칙 카테고리 (기본: RULE_CATEGORIES 전부). 오프라인 규칙과 LLM 프롬프트 모두 적용
+    pub rule_categories: HashSet<String>,
+    /// true 면 LLM 검사는 건너뛰고 오프라인 규칙만 (--offline)
+    pub offline: bool,
+}
+
+// 보안 규칙 카테고리. unsafe-math 는 오프라인 규칙 없이 LLM 만 검사
+pub const RULE_CATEGORIES: &[&str] = &["secrets", "injection", "unsafe-math", "resource-leak", "memory-safety"];
+
+pub fn all_rule_categories() -> HashSet<String> {
+    RULE_CATEGORIES.iter().map(|c| c.to_string()).collect()
+}
+
+// "secrets,injection" -> 집합. 모르는 카테고리는 오류
+pub fn parse_rule_categories(list: &[String]) -> Result<HashSet<String>, String> {
+    let mut set = HashSet::new();
+    for c in list.iter().map(|c| c.trim().to_lowercase()).filter(|c| !c.is_empty()) {
+        if !RULE_CATEGORIES.contains(&c.as_str()) {
+            return Err(format!("unknown rule category '{}' (known: {})", c, RULE_CATEGORIES.join(", ")));
+        }
+        set.insert(c);
+    }
+    Ok(set)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+impl Severity {
+    // 알 수 없는 값은 Info
+    pub fn parse(s: &str) -> Self {
+        match s.trim().to_lowercase().as_str() {
+            "low" => Severity::Low,
+            "medium" | "moderate" => Severity::Medium,
+            "high" => Severity::High,
+            "critical" => Severity::Critical,
+            _ => Severity::Info,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub severity: Severity,
+    /// RULE_CATEGORIES 중 하나 (LLM 이 분류하지 못한 응답은 빈 문자열)
+    pub category: String,
+    pub rule: String,
+    pub message: String,
+    pub line: Option<usize>,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(l) => write!(f, "[{:?}] {} (line {}): {}", self.severity, self.rule, l, self.message),
+            None => write!(f, "[{:?}] {}: {}", self.severity, self.rule, self.message),
+        }
+    }
+}
+
+// LLM 에게 요구하는 응답 형식
+const FINDINGS_SCHEMA: &str = "Respond ONLY with a JSON array, no prose. Each element: \
+{\"severity\": \"info|low|medium|high|critical\", \"category\": \"one of the requested categories\", \
+\"rule\": \"short-rule-id\", \
+\"message\": \"what is wrong and why\", \"line\": <line number or null>}. \
+Return [] if nothing is found.";
+
+#[derive(Deserialize)]
+struct RawFinding {
+    #[serde(default)]
+    severity: String,
+    #[serde(default)]
+    category: String,
+    #[serde(default)]
+    rule: String,
+    #[serde(default)]
+    message: String,
+    #[serde(default)]
+    line: Option<usize>,
+}
+
+// LLM 응답에서 JSON 배열을 찾아 파싱. 형식이 깨지면 원문을 Info 하나로 보존
+pub fn parse_findings(response: &str) -> Vec<Finding> {
+    let json_part = match (response.find('['), response.rfind(']')) {
+        (Some(a), Some(b)) if a < b => &response[a..=b],
+        _ => response,
+    };
+    match serde_json::from_str::<Vec<RawFinding>>(json_part) {
+        Ok(raw) => raw.into_iter().map(RawFinding::into_finding).collect(),
+        Err(_) if response.trim().is_empty() => Vec::new(),
+        Err(_) => vec![Finding {
+            severity: Severity::Info,
+            category: String::new(),
+            rule: "llm-unstructured".into(),
+            message: response.trim().to_string(),
+            line: None,
+        }],
+    }
+}
+
+// 노드 단위 보안 검사 결과 (SecurityAI::analyze / analyze_all)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SecurityFinding {
+    pub severity: Severity,
+    pub message: String,
+    pub node_summary: String,
+}
+
+impl fmt::Display for SecurityFinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{:?}] {} ({})", self.severity, self.message, self.node_summary)
+    }
+}
+
+// analyze_all 응답 형식. index 는 프롬프트의 #N
+const BATCH_FINDINGS_SCHEMA: &str = "Respond ONLY with a JSON object, no prose: \
+{\"nodes\": [{\"index\": <node number>, \"findings\": [{\"severity\": \"info|low|medium|high|critical\", \
+\"category\": \"one of the requested categories\", \"message\": \"what is wrong and why\"}, ...]}, ...]}. \
+Include every node exactly once; use an empty findings array for nodes without issues.";
+
+#[derive(Deserialize)]
+struct RawBatchFindings {
+    nodes: Vec<RawNodeFindings>,
+}
+
+#[derive(Deserialize)]
+struct RawNodeFindings {
+    index: usize,
+    #[serde(default)]
+    findings: Vec<RawFinding>,
+}
+
+impl RawFinding {
+    fn into_finding(self) -> Finding {
+        Finding {
+            severity: Severity::parse(&self.severity),
+            category: self.category.trim().to_lowercase(),
+            rule: if self.rule.is_empty() { "llm".into() } else { self.rule },
+            message: self.message,
+            line: self.line,
+        }
+    }
+}
+
+// 노드 수만큼의 결과로 변환. JSON 이 깨졌으면 None (범위 밖 index 는 무시)
+pub fn parse_batch_findings(response: &str, count: usize) -> Option<Vec<Vec<Finding>>> {
+    let (a, b) = (response.find('{')?, response.rfind('}')?);
+    let raw: RawBatchFindings = serde_json::from_str(response.get(a..=b)?).ok()?;
+    let mut out = vec![Vec::new(); count];
+    for entry in raw.nodes {
+        if let Some(slot) = out.get_mut(entry.index) {
+            slot.extend(entry.findings.into_iter().map(RawFinding::into_finding));
+        }
+    }
+    Some(out)
+}
+
+// 텍스트 기반 오프라인 규칙 (LLM 없이 동작)
+struct TextRule {
+    id: &'static str,
+    category: &'static str,
+    severity: Severity,
+    pattern: &'static str,
+    message: &'static str,
+}
+
+const TEXT_RULES: &[TextRule] = &[
+    TextRule {
+        id: "secret-aws-access-key",
+        category: "secrets",
+        severity: Severity::Critical,
+        pattern: r"\b(AKIA|ASIA)[0-9A-Z]{16}\b",
+        message: "hardcoded AWS access key id",
+    },
+    TextRule {
+        id: "secret-private-key",
+        category: "secrets",
+        severity: Severity::Critical,
+        pattern: r"-----BEGIN ([A-Z]+ )?PRIVATE KEY-----",
+        message: "embedded private key block",
+    },
+    TextRule {
+        id: "secret-openai-key",
+        category: "secrets",
+        severity: Severity::Critical,
+        pattern: r"\bsk-[A-Za-z0-9_-]{20,}\b",
+        message: "hardcoded OpenAI-style API key",
+    },
+    TextRule {
+        id: "secret-assignment",
+        category: "secrets",
+        severity: Severity::High,
+        pattern: r#"(?i)(api[_-]?key|secret|passwd|password|token)\s*[:=]\s*["'](?P<secret>[^"'\s]{8,})["']"#,
+        message: "credential-like literal assigned in source",
+    },
+    TextRule {
+        id: "injection-eval",
+        category: "injection",
+        severity: Severity::Medium,
+        pattern: r"\b(eval|exec)\s*\(",
+        message: "dynamic code execution",
+    },
+    TextRule {
+        id: "injection-shell",
+        category: "injection",
+        severity: Severity::High,
+        pattern: r"\bos\.system\s*\(|\bshell\s*=\s*True\b",
+        message: "command run through a shell",
+    },
+    TextRule {
+        id: "resource-leak-open",
+        category: "resource-leak",
+        severity: Severity::Low,
+        pattern: r"\bopen\s*\([^)]*\)\s*\.(read|write|readlines)\s*\(",
+        message: "file opened inline and never closed",
+    },
+];
+
+// 대상 언어별 오프라인 규칙 (LLM 을 부르기 전에 눈에 띄는 것만)
+const LANGUAGE_RULES: &[(&[Language], TextRule)] = &[
+    (
+        &[Language::Rust],
+        TextRule {
+            id: "rust-unsafe-block",
+            category: "memory-safety",
+            severity: Severity::Medium,
+            pattern: r"\bunsafe\s*(\{|fn\b|impl\b)",
+            message: "unsafe code bypasses the borrow checker",
+        },
+    ),
+    (
+        &[Language::Rust],
+        TextRule {
+            id: "rust-transmute",
+            category: "memory-safety",
+            severity: Severity::High,
+            pattern: r"\bmem::transmute\b",
+            message: "transmute reinterprets memory without any checks",
+        },
+    ),
+    (
+        &[Language::C, Language::Cpp],
+        TextRule {
+            id: "c-unbounded-copy",
+            category: "memory-safety",
+            severity: Severity::High,
+            pattern: r"\b(strcpy|strcat|sprintf|vsprintf|gets)\s*\(",
+            message: "unbounded copy into a fixed-size buffer (possible overflow)",
+        },
+    ),
+    (
+        &[Language::C, Language::Cpp],
+        TextRule {
+            id: "c-system",
+            category: "injection",
+            severity: Severity::High,
+            pattern: r"\b(system|popen)\s*\(",
+            message: "command run through a shell",
+        },
+    ),
+    (
+        &[Language::Go],
+        TextRule {
+            id: "go-unsafe-pointer",
+            category: "memory-safety",
+            severity: Severity::Medium,
+            pattern: r"\bunsafe\.Pointer\b",
+            message: "unsafe.Pointer bypasses Go type and memory safety",
+        },
+    ),
+    (
+        &[Language::Go],
+        TextRule {
+            id: "go-shell-command",
+            category: "injection",
+            severity: Severity::High,
+            pattern: r#"\bexec\.Command\(\s*"(sh|bash|cmd)""#,
+            message: "command run through a shell",
+        },
+    ),
+    (
+        &[Language::Swift],
+        TextRule {
+            id: "swift-unsafe-pointer",
+            category: "memory-safety",
+            severity: Severity::Medium,
+            pattern: r"\b(Unsafe(Mutable)?(Raw)?Pointer|unsafeBitCast)\b",
+            message: "unsafe pointer access without bounds or lifetime checks",
+        },
+    ),
+    (
+        &[Language::Python],
+        TextRule {
+            id: "python-unsafe-deserialize",
+            category: "injection",
+            severity: Severity::High,
+            pattern: r"\bpickle\.loads?\s*\(|\byaml\.load\s*\([^)]*\)$",
+            message: "deserializing untrusted data can execute code",
+        },
+    ),
+    (
+        &[Language::Java],
+        TextRule {
+            id: "java-runtime-exec",
+            category: "injection",
+            severity: Severity::High,
+            pattern: r"\bRuntime\.getRuntime\(\)\.exec\s*\(",
+            message: "external command built at runtime",
+        },
+    ),
+];
+
+// 대상 언어별로 LLM 에게 추가로 보라고 할 부분
+fn security_guidance(lang: Language) -> Option<&'static str> {
+    Some(match lang {
+        Language::Go => "nil pointer and nil map dereferences, ignored error returns, data races between goroutines, unsafe.Pointer",
+        Language::C | Language::Cpp => {
+            "buffer overflows (strcpy, sprintf, unchecked indexing), use-after-free and dangling references, \
+             integer overflow, uninitialized memory"
+        }
+        Language::Rust => "unsound unsafe blocks, unwrap / expect panics on untrusted input, integer overflow in release builds",
+        Language::Python => "eval / exec, subprocess with shell=True, unsafe deserialization (pickle, yaml.load), path traversal",
+        Language::Swift => "force unwraps on untrusted input, unsafe pointers, unchecked casts",
+        Language::Java => "unsafe deserialization, Runtime.exec, SQL built by string concatenation",
+        Language::TypeScript | Language::JavaScript => return None,
+    })
+}
+
+// --lang 값 또는 확장자 (scan 은 확장자를 넘김)
+fn rule_language(lang: &str) -> Option<Language> {
+    lang.parse::<Language>().ok().or_else(|| Language::from_extension(lang))
+}
+
+// 내장 규칙 regex 는 처음 쓸 때 한 번만 컴파일 (rule id -> regex). 잘못된 패턴은 로그만 남기고 건너뜀
+fn rule_regex(rule: &TextRule) -> Option<&'static Regex> {
+    static COMPILED: std::sync::OnceLock<HashMap<&'static str, Regex>> = std::sync::OnceLock::new();
+    COMPILED
+        .get_or_init(|| {
+            TEXT_RULES
+                .iter()
+                .chain(LANGUAGE_RULES.iter().map(|(_, r)| r))
+                .filter_map(|r| match Regex::new(r.pattern) {
+                    Ok(re) => Some((r.id, re)),
+                    Err(e) => {
+                        error!("[RULES] invalid built-in pattern {}: {}", r.id, e);
+                        None
+                    }
+                })
+                .collect()
+        })
+        .get(rule.id)
+}
+
+fn scan_rules<'a>(src: &str, rules: impl Iterator<Item = &'a TextRule>) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    for rule in rules {
+        let Some(re) = rule_regex(rule) else { continue };
+        for (i, line) in src.lines().enumerate() {
+            if re.is_match(line) {
+                findings.push(Finding {
+                    severity: rule.severity,
+                    category: rule.category.into(),
+                    rule: rule.id.into(),
+                    message: rule.message.into(),
+                    line: Some(i + 1),
+                });
+            }
+        }
+    }
+    findings
+}
+
+pub fn scan_text_rules(src: &str) -> Vec<Finding> {
+    scan_rules(src, TEXT_RULES.iter())
+}
+
+// 언어를 모르면 (확장자 / 이름 둘 다 아니면) 없음
+pub fn scan_language_rules(src: &str, lang: &str) -> Vec<Finding> {
+    let Some(lang) = rule_language(lang) else {
+        return Vec::new();
+    };
+    scan_rules(src, LANGUAGE_RULES.iter().filter(|(langs, _)| langs.contains(&lang)).map(|(_, rule)| rule))
+}
+
+impl<L: LLM> SecurityAI<L> {
+    pub fn new(llm: L) -> Self { Self { llm, persona: None, rule_categories: all_rule_categories(), offline: false } }
+
+    pub fn with_offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    pub fn with_persona(mut self, persona: Option<String>) -> Self {
+        self.persona = persona;
+        self
+    }
+
+    // 비어 있으면 전체
+    pub fn with_rule_categories(mut self, categories: HashSet<String>) -> Self {
+        self.rule_categories = if categories.is_empty() { all_rule_categories() } else { categories };
+        self
+    }
+
+    // LLM 에게 활성화된 카테고리만 보라고 알려주는 문장 (+ 대상 언어에서 흔한 문제)
+    fn category_focus(&self, lang: &str) -> String {
+        let mut cats: Vec<&str> = self.rule_categories.iter().map(String::as_str).collect();
+        cats.sort();
+        let mut focus = format!("Only report issues in these categories: {}.", cats.join(", "));
+        if let Some((lang, guidance)) = rule_language(lang).and_then(|l| Some((l, security_guidance(l)?))) {
+            focus.push_str(&format!(" For {} code, pay particular attention to: {}.", lang.display_name(), guidance));
+        }
+        focus
+    }
+
+    // 사용자가 실제로 받는 코드 (refine 결과, offline 이면 base) 에 언어별 오프라인 규칙 적용
+    fn static_findings(&self, code: &str, lang: &str) -> Vec<Finding> {
+        scan_language_rules(code, lang)
+            .into_iter()
+            .filter(|f| self.rule_categories.contains(&f.category))
+            .collect()
+    }
+
+    // 심각도 높은 순. 활성 카테고리 밖 finding 은 버리고, 분류되지 않은 응답은 Info 로 남김
+    fn node_findings(&self, findings: Vec<Finding>, node: &Node) -> Vec<SecurityFinding> {
+        let summary = node.summary();
+        let mut out: Vec<SecurityFinding> = findings
+            .into_iter()
+            .filter(|f| f.category.is_empty() || self.rule_categories.contains(&f.category))
+            .map(|f| SecurityFinding {
+                severity: f.severity,
+                message: if f.message.trim().is_empty() { f.rule } else { f.message },
+                node_summary: summary.clone(),
+            })
+            .collect();
+        out.sort_by_key(|f| std::cmp::Reverse(f.severity));
+        out
+    }
+
+    // code: 노드를 lang 으로 생성한 코드. 그 코드의 언어별 오프라인 규칙 결과와 LLM 결과를 합쳐서 반환
+    pub fn analyze(&self, node: &Node, code: &str, lang: &str) -> Vec<SecurityFinding> {
+        let mut findings = self.static_findings(code, lang);
+        if self.offline {
+            return self.node_findings(findings, node);
+        }
+        let system = system_prompt(Task::Security, self.persona.as_deref(), lang);
+        let response = self.llm.predict_with_system(
+            &system,
+            &format!("Security check for node: {:?}\n{}\n{}", node, self.category_focus(lang), FINDINGS_SCHEMA),
+        );
+        findings.extend(parse_findings(&response));
+        self.node_findings(findings, node)
+    }
+
+    // 모든 노드를 한 번의 호출로 검사 (노드와 그 노드로 생성한 코드 쌍). 응답 형식이 깨지면 노드별 analyze 로 대체
+    pub fn analyze_all(&self, nodes: &[(&Node, &str)], lang: &str) -> Vec<Vec<SecurityFinding>> {
+        if nodes.is_empty() {
+            return Vec::new();
+        }
+        if self.offline {
+            return nodes.iter().map(|&(n, code)| self.node_findings(self.static_findings(code, lang), n)).collect();
+        }
+        let system = system_prompt(Task::Security, self.persona.as_deref(), lang);
+        let listing: Vec<String> = nodes.iter().enumerate().map(|(i, (n, _))| format!("#{}: {:?}", i, n)).collect();
+        let response = self.llm.predict_with_system(
+            &system,
+            &format!(
+                "Security check for each of these nodes.\n{}\n{}\n\n{}",
+                self.category_focus(lang),
+                BATCH_FINDINGS_SCHEMA,
+                listing.join("\n")
+            ),
+        );
+        let with_static = |mut llm: Vec<Finding>, &(n, code): &(&Node, &str)| {
+            llm.extend(self.static_findings(code, lang));
+            self.node_findings(llm, n)
+        };
+        if is_error_response(&response) {
+            return nodes.iter().map(|unit| with_static(parse_findings(&response), unit)).collect();
+        }
+        match parse_batch_findings(&response, nodes.len()) {
+            Some(batch) => batch.into_iter().zip(nodes).map(|(f, unit)| with_static(f, unit)).collect(),
+            None => {
+                warn!("[SECURITY] batch response was not valid JSON, checking {} nodes one by one", nodes.len());
+                nodes.iter().map(|&(n, code)| self.analyze(n, code, lang)).collect()
+            }
+        }
+    }
+
+    // 파서가 아직 지원하지 않는 언어도 원본 텍스트 그대로 검사
+    pub fn analyze_source(&self, src: &str, lang: &str) -> Vec<Finding> {
+        let mut findings: Vec<Finding> = scan_text_rules(src)
+            .into_iter()
+            .chain(scan_language_rules(src, lang))
+            .filter(|f| self.rule_categories.contains(&f.category))
+            .collect();
+        if self.offline {
+            findings.sort_by_key(|f| std::cmp::Reverse(f.severity));
+            return findings;
+        }
+
+        let system = system_prompt(Task::Security, self.persona.as_deref(), lang);
+        let response = self.llm.predict_with_system(
+            &system,
+            &format!(
+                "Security review of this {} source file.\n{}\n{}\n\n{}",
+                lang,
+                self.category_focus(lang),
+                FINDINGS_SCHEMA,
+                src
+            ),
+        );
+        // 분류되지 않은 응답(llm-unstructured)은 버리지 않음
+        findings.extend(
+            parse_findings(&response)
+                .into_iter()
+                .filter(|f| f.category.is_empty() || self.rule_categories.contains(&f.category)),
+        );
+
+        findings.sort_by_key(|f| std::cmp::Reverse(f.severity));
+        findings
+    }
+}
+
+// ==========================================================
+// SECRET GUARD (LLM 전송 전 검사)
+// ==========================================================
+
+// 파일에서 시크릿이 발견됐을 때 처리
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SecretPolicy {
+    /// 경고 후 해당 파일은 전송하지 않음
+    #[default]
+    Block,
+    /// 경고만 하고 그대로 전송 (--allow-secrets)
+    Allow,
+    /// 시크릿을 placeholder 로 바꿔서 전송 (--redact-secrets)
+    Redact,
+}
+
+// 따옴표 안의 긴 토큰 중 엔트로피가 높은 것 (base64/hex 키 등)
+const HIGH_ENTROPY_MIN_LEN: usize = 20;
+const HIGH_ENTROPY_THRESHOLD: f64 = 4.0;
+
+fn shannon_entropy(s: &str) -> f64 {
+    let mut counts: HashMap<char, usize> = HashMap::new();
+    for c in s.chars() {
+        *counts.entry(c).or_default() += 1;
+    }
+    let len = s.chars().count() as f64;
+    counts
+        .values()
+        .map(|&n| {
+            let p = n as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+fn high_entropy_literal_re() -> Regex {
+    Regex::new(&format!(r#"["'](?P<secret>[A-Za-z0-9+/=_\-]{{{},}})["']"#, HIGH_ENTROPY_MIN_LEN))
+        .expect("invalid entropy pattern")
+}
+
+// 시크릿 관련 규칙 + 고엔트로피 문자열 검사
+pub fn detect_secrets(src: &str) -> Vec<Finding> {
+    let mut findings: Vec<Finding> = scan_text_rules(src)
+        .into_iter()
+        .filter(|f| f.category == "secrets")
+        .collect();
+
+    let re = high_entropy_literal_re();
+    for (i, line) in src.lines().enumerate() {
+        let hit = re
+            .captures_iter(line)
+            .any(|c| shannon_entropy(&c["secret"]) >= HIGH_ENTROPY_THRESHOLD);
+        if hit && !findings.iter().any(|f| f.line == Some(i + 1)) {
+            findings.push(Finding {
+                severity: Severity::Medium,
+                category: "secrets".into(),
+                rule: "secret-high-entropy".into(),
+                message: "high-entropy string literal (possible key or token)".into(),
+                line: Some(i + 1),
+            });
+        }
+    }
+    findings.sort_by_key(|f| f.line);
+    findings
+}
+
+// ==========================================================
+// REDACTION (전송 전 치환 / 결과에서 복원)
+// ==========================================================
+#[derive(Debug, Clone)]
+pub struct RedactPattern {
+    /// placeholder 에 들어가는 종류 (SECRET, EMAIL, URL, CUSTOM ...)
+    pub kind: String,
+    /// `secret` 이름의 캡처 그룹이 있으면 그 부분만 치환
+    pub regex: Regex,
+    /// 고엔트로피 문자열만 치환
+    pub entropy_check: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Redactor {
+    pub patterns: Vec<RedactPattern>,
+    /// 결과 코드에서 placeholder 를 원래 값으로 복원
+    pub restore: bool,
+}
+
+// placeholder -> 원래 값
+#[derive(Debug, Clone, Default)]
+pub struct RedactionMap {
+    entries: HashMap<(String, usize), String>,
+}
+
+impl Redactor {
+    // 시크릿 규칙 + 고엔트로피 문자열
+    pub fn secrets_only() -> Self {
+        let mut patterns: Vec<RedactPattern> = TEXT_RULES
+            .iter()
+            .filter(|r| r.id.starts_with("secret-"))
+            .map(|r| RedactPattern {
+                kind: "SECRET".into(),
+                regex: Regex::new(r.pattern).expect("invalid built-in rule pattern"),
+                entropy_check: false,
+            })
+            .collect();
+        patterns.push(RedactPattern {
+            kind: "SECRET".into(),
+            regex: high_entropy_literal_re(),
+            entropy_check: true,
+        });
+        Self { patterns, restore: false }
+    }
+
+    // 시크릿 + 이메일(PII) + 사내 URL
+    pub fn with_defaults() -> Self {
+        let mut r = Self::secrets_only();
+        r.patterns.push(RedactPattern {
+            kind: "EMAIL".into(),
+            regex: Regex::new(r"[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}").expect("invalid email pattern"),
+            entropy_check: false,
+        });
+        r.patterns.push(RedactPattern {
+            kind: "URL".into(),
+            regex: Regex::new(r#"https?://[A-Za-z0-9.-]+\.(internal|corp|local|lan|intranet)(:\d+)?[^\s"'<>)]*"#)
+                .expect("invalid url pattern"),
+            entropy_check: false,
+        });
+        r
+    }
+
+    pub fn add_pattern(&mut self, regex: &str) -> Result<(), regex::Error> {
+        self.patterns.push(RedactPattern {
+            kind: "CUSTOM".into(),
+            regex: Regex::new(regex)?,
+            entropy_check: false,
+        });
+        Ok(())
+    }
+
+    pub fn redact(&self, src: &str) -> (String, RedactionMap) {
+        let mut out = src.to_string();
+        let mut map = RedactionMap::default();
+        let mut n = 0;
+
+        for p in &self.patterns {
+            let mut spans: Vec<std::ops::Range<usize>> = p
+                .regex
+                .captures_iter(&out)
+                .filter_map(|c| c.name("secret").or_else(|| c.get(0)))
+                .filter(|m| !m.as_str().contains("REDACTED_"))
+                .filter(|m| !p.entropy_check || shannon_entropy(m.as_str()) >= HIGH_ENTROPY_THRESHOLD)
+                .map(|m| m.range())
+                .collect();
+            // 뒤에서부터 치환해야 앞쪽 offset 이 유지됨
+            spans.reverse();
+            for span in spans {
+                n += 1;
+                map.entries.insert((p.kind.clone(), n), out[span.clone()].to_string());
+                out.replace_range(span, &format!("__REDACTED_{}_{}__", p.kind, n));
+            }
+        }
+        (out, map)
+    }
+}
+
+impl RedactionMap {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // 모델이 밑줄/대소문자를 조금 바꿔도 찾도록 느슨하게 매칭.
+    // (복원된 개수, 결과에서 사라진 placeholder 목록)
+    pub fn restore(&self, text: &str) -> (String, usize, Vec<String>) {
+        let re = Regex::new(r"(?i)_*REDACTED_([A-Z]+)_(\d+)_*").expect("invalid placeholder pattern");
+        let mut restored = std::collections::HashSet::new();
+        let out = re
+            .replace_all(text, |c: &regex::Captures| {
+                let key = (c[1].to_uppercase(), c[2].parse().unwrap_or(0));
+                match self.entries.get(&key) {
+                    Some(orig) => {
+                        restored.insert(key);
+                        orig.clone()
+                    }
+                    None => c[0].to_string(),
+                }
+            })
+            .into_owned();
+
+        let mut missing: Vec<String> = self
+            .entries
+            .keys()
+            .filter(|k| !restored.contains(*k))
+            .map(|(kind, n)| format!("__REDACTED_{}_{}__", kind, n))
+            .collect();
+        missing.sort();
+        (out, restored.len(), missing)
+    }
+}
+
+// ==========================================================
+// BASE CODE GENERATOR
+// ==========================================================
+pub struct BaseGenerator;
+
+impl BaseGenerator {
+    pub fn generate(&self, node: &Node, lang: Language) -> String {
+        match &node.kind {
+            NodeKind::Identifier(x) => match lang {
+                Language::Go => format!("var {} any", x),
+                Language::Cpp => format!("auto {};", x),
+                Language::C => format!("int {};", x),
+                Language::Swift => format!("var {}: Any", x),
+                Language::Rust => format!("let {};", x),
+                Language::Python => format!("{} = None", x),
+                Language::Java => format!("Object {};", x),
+                Language::TypeScript => format!("let {}: any;", x),
+                Language::JavaScript => format!("let {};", x),
+            },
+            NodeKind::Integer(_) | NodeKind::Number(_) | NodeKind::BinaryOp { .. } => {
+                self.expr(node, lang, has_float(node))
+            }
+            NodeKind::Function { name, args, body } => self.function(name, args, body, lang),
+            NodeKind::Unknown => "/* unsupported */".into(),
+        }
+    }
+
+    // 식 위치의 코드. 이항 연산은 우선순위를 따지지 않고 항상 괄호로 감쌈.
+    // float: 실수 연산 안의 정수 리터럴인지 (rust 는 1 + 0.5 가 컴파일되지 않음)
+    fn expr(&self, node: &Node, lang: Language, float: bool) -> String {
+        match &node.kind {
+            NodeKind::Identifier(x) => x.clone(),
+            NodeKind::Integer(n) => integer_literal(*n, lang, float),
+            NodeKind::Number(n) => number_literal(*n),
+            NodeKind::BinaryOp { op, left, right } => {
+                format!("({} {} {})", self.expr(left, lang, float), op, self.expr(right, lang, float))
+            }
+            _ => "/* unsupported */".into(),
+        }
+    }
+
+    // 시그니처 + 본문 stub. 타입은 모르므로 언어별 느슨한 타입 (rust / java / c 는 f64 / double)
+    fn function(&self, name: &str, args: &[String], body: &[Node], lang: Language) -> String {
+        let params = |f: &dyn Fn(&String) -> String| args.iter().map(f).collect::<Vec<_>>().join(", ");
+        // 본문에 숫자 / 산술이 있으면 숫자 타입 (go 의 any, swift 의 Any 끼리는 + 가 컴파일되지 않음).
+        // 리터럴이 모두 정수면 정수 타입, 소수 리터럴이 하나라도 있으면 실수 타입
+        let numeric = body.iter().any(|n| !matches!(n.kind, NodeKind::Identifier(_)));
+        let float = body.iter().any(has_float);
+        let integer = numeric && !float;
+        let (ret, stmts) = match body.split_last() {
+            Some((last, rest)) => (Some(self.expr(last, lang, float)), rest),
+            None => (None, body),
+        };
+        let (discard, end) = match lang {
+            Language::Go => ("_ = ", ""),
+            Language::Rust => ("let _ = ", ";"),
+            // swift 는 쓰지 않은 식 결과를 경고함
+            Language::Swift => ("_ = ", ""),
+            Language::Python => ("", ""),
+            Language::Cpp | Language::C | Language::Java | Language::TypeScript | Language::JavaScript => ("", ";"),
+        };
+        let indent = if lang == Language::Go { "\t" } else { "    " };
+        let mut lines: Vec<String> =
+            stmts.iter().map(|n| format!("{}{}{}{}", indent, discard, self.expr(n, lang, float), end)).collect();
+        if let Some(r) = &ret {
+            lines.push(match lang {
+                Language::Rust => format!("{}{}", indent, r),
+                _ => format!("{}return {}{}", indent, r, end),
+            });
+        }
+        if lines.is_empty() && lang == Language::Python {
+            lines.push(format!("{}pass", indent));
+        }
+        let body = lines.join("\n");
+        let has_ret = ret.is_some();
+        let (go_ty, swift_ty, ts_ty) = match (integer, numeric) {
+            (true, _) => ("int", "Int", "number"),
+            (false, true) => ("float64", "Double", "number"),
+            _ => ("any", "Any", "any"),
+        };
+        let (rust_ty, java_ty, c_ty) = if integer { ("i64", "long", "long") } else { ("f64", "double", "double") };
+
+        let header = match lang {
+            Language::Go => {
+                let ret_ty = if has_ret { format!(" {}", go_ty) } else { String::new() };
+                format!("func {}({}){} {{", name, params(&|a| format!("{} {}", a, go_ty)), ret_ty)
+            }
+            Language::Cpp => format!("auto {}({}) {{", name, params(&|a| format!("auto {}", a))),
+            Language::C => {
+                let ret_ty = if has_ret { c_ty } else { "void" };
+                format!("{} {}({}) {{", ret_ty, name, params(&|a| format!("{} {}", c_ty, a)))
+            }
+            Language::Swift => {
+                let ret_ty = if has_ret { format!(" -> {}", swift_ty) } else { String::new() };
+                format!("func {}({}){} {{", name, params(&|a| format!("_ {}: {}", a, swift_ty)), ret_ty)
+            }
+            Language::Rust => {
+                let ret_ty = if has_ret { format!(" -> {}", rust_ty) } else { String::new() };
+                format!("fn {}({}){} {{", name, params(&|a| format!("{}: {}", a, rust_ty)), ret_ty)
+            }
+            Language::Java => {
+                let ret_ty = if has_ret { java_ty } else { "void" };
+                format!("static {} {}({}) {{", ret_ty, name, params(&|a| format!("{} {}", java_ty, a)))
+            }
+            Language::TypeScript => {
+                let ret_ty = if has_ret { format!(": {}", ts_ty) } else { String::new() };
+                format!("function {}({}){} {{", name, params(&|a| format!("{}: {}", a, ts_ty)), ret_ty)
+            }
+            Language::JavaScript => format!("function {}({}) {{", name, params(&|a| a.clone())),
+            Language::Python => return format!("def {}({}):\n{}", name, params(&|a| a.clone()), body),
+        };
+        if body.is_empty() {
+            format!("{}}}", header)
+        } else {
+            format!("{}\n{}\n}}", header, body)
+        }
+    }
+}
+
+// 문자열로 받은 대상 언어용 (Compiler). 모르는 언어면 코드 대신 그 이유를 주석으로
+fn base_code(node: &Node, lang: &str) -> String {
+    match lang.parse::<Language>() {
+        Ok(l) => BaseGenerator.generate(node, l),
+        Err(e) => format!("/* {} */", e),
+    }
+}
+
+// 소수 리터럴이 하나라도 있으면 실수 연산
+fn has_float(node: &Node) -> bool {
+    match &node.kind {
+        NodeKind::Number(_) => true,
+        NodeKind::BinaryOp { left, right, .. } => has_float(left) || has_float(right),
+        _ => false,
+    }
+}
+
+// 소수로 쓴 값은 값이 정수여도 소수점을 유지 (42.0 -> 42.0, go 에서 42 는 int 가 됨)
+fn number_literal(n: f64) -> String {
+    let s = if n.fract() == 0.0 && n.abs() < 1e15 { format!("{:.1}", n) } else { format!("{}", n) };
+    if n < 0.0 { format!("({})", s) } else { s }
+}
+
+// 정수 리터럴. rust 는 실수 연산 안에서 정수와 f64 를 섞을 수 없으므로 소수점을 붙임
+fn integer_literal(n: i64, lang: Language, float: bool) -> String {
+    let s = if float && lang == Language::Rust { format!("{}.0", n) } else { n.to_string() };
+    if n < 0 { format!("({})", s) } else { s }
+}
+
+// ==========================================================
+// LLM REFINER
+// ==========================================================
+pub struct LLMGenerator<L: LLM> {
+    pub llm: L,
+    pub persona: Option<String>,
+    /// Some 이면 version 을 최소 지원 버전 제약으로 프롬프트에 명시
+    pub target_version: Option<String>,
+}
+
+impl<L: LLM> LLMGenerator<L> {
+    pub fn refine(&self, lang: &str, version: &str, code: &str) -> String {
+        let mut system = system_prompt(Task::Refine, self.persona.as_deref(), lang);
+        if let Some(target) = &self.target_version {
+            system.push_str("\n\n");
+            system.push_str(&version_constraint(lang, target));
+        }
+        self.llm.predict_with_system(
+            &system,
+            &format!("Rewrite in idiomatic {} {} code:\n{}", lang, version, code)
+        )
+    }
+}
+
+// ==========================================================
+// TRANSPILE OPTIONS
+// ==========================================================
+#[derive(Debug, Clone, Default)]
+pub struct TranspileOptions {
+    /// 시스템 프롬프트 페르소나 (None 이면 Task::Transpile 기본값)
+    pub persona: Option<String>,
+    /// 언어별 최소 지원 버전 (예: go -> 1.18). 프롬프트에 hard constraint 로 들어감
+    pub target_versions: HashMap<String, String>,
+    /// 원본 구조를 얼마나 유지할지 (직역 <-> 관용적 재작성)
+    pub fidelity: Fidelity,
+    /// 모든 곳에 명시적 타입을 요구. 알 수 없는 타입은 any 대신 TYPE_PLACEHOLDER + TODO
+    pub explicit_types: bool,
+    /// 분당 토큰 한도 (OpenAI TPM). None 이면 제한 없음
+    pub tpm_limit: Option<usize>,
+    /// 파일당 후보 개수. 2 이상이면 predict_n 으로 받아서 가장 나은 후보 선택
+    pub candidates: u32,
+    /// 이 도구가 만들지 않은 출력 파일이 이미 있을 때 (manifest 에 기록된 이전 출력은 항상 갱신)
+    pub on_conflict: ConflictPolicy,
+    /// 변환 후 import/include 문만 따로 보정하는 후처리
+    pub fix_imports: bool,
+    /// 전송 전 시크릿 검사 결과 처리
+    pub secrets: SecretPolicy,
+    /// 모든 파일에 적용할 치환 규칙. None 이면 SecretPolicy::Redact 일 때만 시크릿 치환
+    pub redaction: Option<Redactor>,
+    /// 파일별 모델 선택 규칙 (처음 일치하는 규칙 사용, 없으면 기본 모델)
+    pub routes: Vec<RoutingRule>,
+    /// 검증을 통과한 변환을 OpenAI fine-tuning chat 형식 JSONL 로 저장
+    pub finetune_export: Option<PathBuf>,
+    /// 비어 있지 않으면 이 확장자만 변환 (소문자, 점 없이)
+    pub only_exts: Vec<String>,
+    /// 소스 루트 기준 include / exclude glob. include 가 없으면 확장자 기본값
+    pub path_filter: PathFilter,
+    /// 출력 파일 인코딩. None 이면 UTF-8 그대로 (변환 없음)
+    pub output_encoding: Option<&'static encoding_rs::Encoding>,
+    /// 파일 사이마다 확인. 취소되면 남은 파일은 건너뛰고 부분 report 반환
+    pub abort: Option<AbortToken>,
+    /// Some 이면 디렉토리마다 여러 원본 파일을 하나의 출력 파일로 묶어서 변환
+    pub merge: Option<MergeMode>,
+    /// Some(n) 이면 n 토큰을 넘는 파일 중 가운데가 반복적인 파일은 head + tail 만 전송 (opt-in)
+    pub window_tokens: Option<usize>,
+    /// 언어 쌍별 변환 프롬프트 템플릿 ({lang}, {src_lang}, {content}). 기본은 내장 템플릿
+    pub prompt_templates: PromptTemplates,
+    /// Some 이면 변환 후 출력 트리 전체를 대상 toolchain 으로 빌드 (이 시간 안에)
+    pub smoke_build: Option<Duration>,
+    /// true 면 출력 파일마다 대상 컴파일러로 따로 검사 (toolchain 필요. 지금은 rust 만)
+    pub validate: bool,
+    /// true 면 진행률 표시 (터미널이면 막대, 아니면 [PROGRESS] 줄)
+    pub progress: bool,
+    /// 동시에 변환할 worker 수. 0 이면 CPU 수, 1 이면 순차 처리
+    pub jobs: usize,
+    /// 작업이 이 개수 미만이면 jobs 와 관계없이 순차 처리 (None 이면 DEFAULT_PARALLEL_MIN_FILES)
+    pub parallel_min_files: Option<usize>,
+    /// true 면 소스 루트의 .gitignore 를 무시하고 전부 변환
+    pub no_gitignore: bool,
+    /// true 면 디렉토리 symlink 도 따라감 (이미 본 실제 경로는 다시 들어가지 않음)
+    pub follow_symlinks: bool,
+    /// true 면 manifest 를 보지 않고 전부 다시 변환 (출력도 덮어씀)
+    pub force: bool,
+    /// LLM 호출도, 디스크 쓰기도 없이 무엇을 변환 / 건너뛸지만 report (planned)
+    pub dry_run: bool,
+    /// Some 이면 dry run 에서 파일마다 입력 토큰을 세고 이 가격으로 비용 추정
+    pub estimate: Option<ModelInfo>,
+    /// 이보다 큰 파일은 [TOO LARGE] 로 건너뜀 (None 이면 DEFAULT_MAX_FILE_SIZE, Some(0) 이면 제한 없음)
+    pub max_file_size: Option<u64>,
+}
+
+// 작은 프로젝트는 pool 을 띄우는 비용이 더 크고 로그만 섞임
+pub const DEFAULT_PARALLEL_MIN_FILES: usize = 4;
+
+// minified 번들 같은 큰 파일은 토큰만 쓰고 의미 있게 변환되지 않음
+pub const DEFAULT_MAX_FILE_SIZE: u64 = 256 * 1024;
+
+// 앞부분에 NUL 이 있으면 코드 확장자여도 바이너리로 간주
+const BINARY_SNIFF_BYTES: usize = 8 * 1024;
+
+fn looks_binary(content: &str) -> bool {
+    content.bytes().take(BINARY_SNIFF_BYTES).any(|b| b == 0)
+}
+
+fn max_file_size(opts: &TranspileOptions) -> Option<u64> {
+    match opts.max_file_size {
+        None => Some(DEFAULT_MAX_FILE_SIZE),
+        Some(0) => None,
+        Some(n) => Some(n),
+    }
+}
+
+// 출력 경로에 이미 파일이 있을 때
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConflictPolicy {
+    /// 기존 파일은 그대로 두고 건너뜀 ([EXISTS])
+    #[default]
+    Skip,
+    /// 다시 변환해서 덮어씀
+    Overwrite,
+    /// 하나라도 있으면 아무것도 변환하지 않고 실패
+    Error,
+}
+
+impl ConflictPolicy {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s.trim().to_lowercase().as_str() {
+            "skip" => Ok(ConflictPolicy::Skip),
+            "overwrite" => Ok(ConflictPolicy::Overwrite),
+            "error" => Ok(ConflictPolicy::Error),
+            other => Err(format!("unknown conflict mode '{}' (overwrite, skip, error)", other)),
+        }
+    }
+}
+
+// 변환 충실도: 원본 구조 유지 vs 대상 언어다운 재작성
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Fidelity {
+    /// 줄 단위 직역. 함수 / 변수 / 제어 흐름 구조를 그대로
+    Literal,
+    #[default]
+    Balanced,
+    /// 대상 언어 관용구로 전면 재작성
+    Idiomatic,
+}
+
+impl Fidelity {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s.trim().to_lowercase().as_str() {
+            "literal" => Ok(Fidelity::Literal),
+            "balanced" => Ok(Fidelity::Balanced),
+            "idiomatic" => Ok(Fidelity::Idiomatic),
+            other => Err(format!("unknown fidelity '{}' (literal, balanced, idiomatic)", other)),
+        }
+    }
+
+    // 시스템 프롬프트에 붙는 지시
+    pub fn instruction(&self) -> &'static str {
+        match self {
+            Fidelity::Literal => {
+                "Translate as literally as possible: keep the same functions, names, statement order and control flow, \
+                 changing structure only where the target language requires it."
+            }
+            Fidelity::Balanced => {
+                "Preserve the overall structure and names, but use target-language idioms where a literal translation would be awkward."
+            }
+            Fidelity::Idiomatic => {
+                "Rewrite fully in idiomatic target-language style: restructure freely using the target's standard patterns, \
+                 types and library, as long as behavior is preserved."
+            }
+        }
+    }
+
+    // report 머리말용 설명 (예: python 의 for i in range(len(xs)) 를 go 로)
+    pub fn describe(&self) -> &'static str {
+        match self {
+            Fidelity::Literal => "literal - structure kept line by line (for i in range(len(xs)) -> for i := 0; i < len(xs); i++)",
+            Fidelity::Balanced => "balanced - structure kept, awkward spots made idiomatic (for i in range(len(xs)) -> for i := range xs)",
+            Fidelity::Idiomatic => "idiomatic - free rewrite in target style (index loops -> for _, x := range xs, helpers may be merged or split)",
+        }
+    }
+}
+
+// --explicit-types 에서 타입을 정할 수 없을 때 쓰게 하는 자리표시 타입
+pub const TYPE_PLACEHOLDER: &str = "TodoType";
+
+// 대상 언어의 느슨한 만능 타입 (explicit types 모드에서 금지하고, 출력에 남은 것을 셈)
+pub fn loose_types(lang: &str) -> &'static [&'static str] {
+    match lang {
+        "go" => &["interface{}", "any"],
+        "typescript" | "ts" => &["any", "unknown"],
+        "python" | "py" => &["Any"],
+        "rust" | "rs" => &["dyn Any"],
+        "java" => &["Object"],
+        "csharp" | "cs" => &["object", "dynamic"],
+        "kotlin" | "kt" | "swift" => &["Any"],
+        "cpp" | "c++" => &["std::any", "void*"],
+        "c" => &["void*"],
+        _ => &[],
+    }
+}
+
+fn explicit_types_instruction(lang: &str) -> String {
+    let loose = loose_types(lang).iter().map(|t| format!("`{}`", t)).collect::<Vec<_>>();
+    let avoid = if loose.is_empty() {
+        "catch-all types".to_string()
+    } else {
+        format!("catch-all types such as {}", loose.join(" or "))
+    };
+    format!(
+        "Add explicit type annotations everywhere the target language allows them: parameters, return values, \
+         variables, fields and collection elements. Do not use {}. Where a type cannot be determined from the source, \
+         use the placeholder type `{}` (declared once as an alias) and put a `TODO(type)` comment on that line.",
+        avoid, TYPE_PLACEHOLDER
+    )
+}
+
+// 출력에서 느슨한 타입이나 자리표시 타입이 남은 줄 수 (자리표시 alias 선언 줄은 제외)
+pub fn count_loose_types(code: &str, lang: &str) -> usize {
+    let word = |t: &str| {
+        let start = if t.starts_with(|c: char| c.is_alphanumeric()) { r"\b" } else { "" };
+        let end = if t.ends_with(|c: char| c.is_alphanumeric()) { r"\b" } else { "" };
+        format!("{}{}{}", start, regex::escape(t), end)
+    };
+    let mut alts: Vec<String> = loose_types(lang).iter().map(|t| word(t)).collect();
+    alts.push(word(TYPE_PLACEHOLDER));
+    let re = Regex::new(&alts.join("|")).unwrap();
+    let decl = Regex::new(&format!(r"\b{}\s*=", TYPE_PLACEHOLDER)).unwrap();
+    code.lines().filter(|l| re.is_match(l) && !decl.is_match(l)).count()
+}
+
+// OPENAI_TPM_LIMIT 환경변수 (선택)
+pub fn tpm_limit_from_env() -> Option<usize> {
+    env::var("OPENAI_TPM_LIMIT").ok().and_then(|v| v.trim().parse().ok()).filter(|&n| n > 0)
+}
+
+// OPENAI_RPM_LIMIT 환경변수 (선택)
+pub fn rpm_limit_from_env() -> Option<usize> {
+    env::var("OPENAI_RPM_LIMIT").ok().and_then(|v| v.trim().parse().ok()).filter(|&n| n > 0)
+}
+
+// ==========================================================
+// CANDIDATE SELECTION (n > 1)
+// ==========================================================
+
+// 괄호 짝이 맞는지만 보는 가벼운 구문 검사 (문자열/문자 리터럴 내부는 무시)
+pub fn has_balanced_delimiters(code: &str) -> bool {
+    let mut stack = Vec::new();
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+
+    for c in code.chars() {
+        if let Some(q) = quote {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == q {
+                quote = None;
+            }
+            continue;
+        }
+        match c {
+            '"' | '`' => quote = Some(c),
+            '(' | '[' | '{' => stack.push(c),
+            ')' | ']' | '}' => {
+                let open = match c { ')' => '(', ']' => '[', _ => '{' };
+                if stack.pop() != Some(open) {
+                    return false;
+                }
+            }
+            _ => {}
+        }
+    }
+    stack.is_empty() && quote.is_none()
+}
+
+// 구문 검사를 통과한 첫 후보, 없으면 첫 번째 후보
+pub fn pick_candidate(candidates: Vec<String>) -> Option<String> {
+    let best = candidates.iter().position(|c| has_balanced_delimiters(c));
+    let idx = best.unwrap_or(0);
+    candidates.into_iter().nth(idx)
+}
+
+// ==========================================================
+// MODEL ROUTING (파일 크기 / 언어별 모델 선택)
+// ==========================================================
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RouteCondition {
+    /// 추정 토큰 수 > N
+    TokensAbove(usize),
+    /// 추정 토큰 수 <= N
+    TokensAtMost(usize),
+    /// 소스 확장자 일치
+    SourceExt(String),
+    /// 항상 (기본 규칙)
+    Any,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoutingRule {
+    pub when: RouteCondition,
+    pub model: String,
+}
+
+impl RoutingRule {
+    // "<조건>:<모델>" 형식. 조건: tokens>N, tokens<=N, ext=py, *
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let (cond, model) = s
+            .rsplit_once(':')
+            .ok_or_else(|| format!("route '{}' must look like <condition>:<model>", s))?;
+        let (cond, model) = (cond.trim(), model.trim());
+        if model.is_empty() {
+            return Err(format!("route '{}' has no model", s));
+        }
+
+        let when = if cond == "*" {
+            RouteCondition::Any
+        } else if let Some(n) = cond.strip_prefix("tokens<=") {
+            RouteCondition::TokensAtMost(n.trim().parse().map_err(|_| format!("bad token count in '{}'", s))?)
+        } else if let Some(n) = cond.strip_prefix("tokens>") {
+            RouteCondition::TokensAbove(n.trim().parse().map_err(|_| format!("bad token count in '{}'", s))?)
+        } else if let Some(ext) = cond.strip_prefix("ext=") {
+            RouteCondition::SourceExt(ext.trim().trim_start_matches('.').to_lowercase())
+        } else {
+            return Err(format!("unknown route condition '{}'", cond));
+        };
+        Ok(Self { when, model: model.to_string() })
+    }
+
+    pub fn matches(&self, tokens: usize, ext: &str) -> bool {
+        match &self.when {
+            RouteCondition::TokensAbove(n) => tokens > *n,
+            RouteCondition::TokensAtMost(n) => tokens <= *n,
+            RouteCondition::SourceExt(e) => e.eq_ignore_ascii_case(ext),
+            RouteCondition::Any => true,
+        }
+    }
+}
+
+// 처음 일치하는 규칙의 모델
+pub fn route_model<'a>(rules: &'a [RoutingRule], tokens: usize, ext: &str) -> Option<&'a str> {
+    rules.iter().find(|r| r.matches(tokens, ext)).map(|r| r.model.as_str())
+}
+
+// MODEL_ROUTES 환경변수: 쉼표로 구분한 규칙 목록
+pub fn routes_from_env() -> Result<Vec<RoutingRule>, String> {
+    match env::var("MODEL_ROUTES") {
+        Ok(v) => v.split(',').filter(|r| !r.trim().is_empty()).map(RoutingRule::parse).collect(),
+        Err(_) => Ok(Vec::new()),
+    }
+}
+
+// ==========================================================
+// TOKEN BUDGET (TPM-AWARE SCHEDULING)
+// ==========================================================
+
+// 대략적인 토큰 추정 (영문 코드 기준 4 chars ≈ 1 token)
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+// prompt + completion 추정치. 트랜스파일 결과는 보통 입력과 비슷한 길이
+pub fn estimate_request_tokens(prompt: &str) -> usize {
+    estimate_tokens(prompt) * 2
+}
+
+// 입력 토큰 기준 예상 비용 (USD). completion 도 입력과 비슷한 길이로 가정
+pub fn estimate_cost(info: &ModelInfo, input_tokens: usize) -> f64 {
+    input_tokens as f64 / 1000.0 * (info.input_per_1k + info.output_per_1k)
+}
+
+// 최근 60초 동안 보낸 토큰을 추적해서 TPM 한도를 넘기지 않도록 대기
+// (요청마다 1 씩 세면 RPM 한도)
+#[derive(Debug)]
+pub struct TokenBudget {
+    pub tokens_per_minute: usize,
+    /// 로그 태그 (TPM / RPM)
+    label: &'static str,
+    window: VecDeque<(Instant, usize)>,
+}
+
+impl TokenBudget {
+    const WINDOW: Duration = Duration::from_secs(60);
+
+    pub fn new(tokens_per_minute: usize) -> Self {
+        Self { tokens_per_minute, label: "TPM", window: VecDeque::new() }
+    }
+
+    pub fn requests_per_minute(requests: usize) -> Self {
+        Self { tokens_per_minute: requests, label: "RPM", window: VecDeque::new() }
+    }
+
+    fn used(&mut self, now: Instant) -> usize {
+        while let Some(&(t, _)) = self.window.front() {
+            if now.duration_since(t) >= Self::WINDOW {
+                self.window.pop_front();
+            } else {
+                break;
+            }
+        }
+        self.window.iter().map(|&(_, n)| n).sum()
+    }
+
+    // 예산이 있으면 기록하고 None, 없으면 기다려야 하는 시간.
+    // 한도보다 큰 요청은 창이 빌 때까지 기다린 뒤 단독으로 보냄
+    pub fn try_acquire(&mut self, tokens: usize) -> Option<Duration> {
+        let now = Instant::now();
+        let used = self.used(now);
+        if used + tokens <= self.tokens_per_minute || self.window.is_empty() {
+            if tokens > self.tokens_per_minute {
+                warn!("[WARN] request (~{} tokens) exceeds TPM limit {}", tokens, self.tokens_per_minute);
+            }
+            self.window.push_back((now, tokens));
+            return None;
+        }
+
+        let oldest = self.window.front().map(|&(t, _)| t).unwrap_or(now);
+        let wait = Self::WINDOW.saturating_sub(now.duration_since(oldest));
+        info!("[{} WAIT] {:.1}s ({}/{} used in the last minute)", self.label, wait.as_secs_f32(), used, self.tokens_per_minute);
+        Some(wait)
+    }
+
+    // worker 끼리 공유하는 예산에서 받을 때까지 블록. 기다리는 동안은 lock 을 놓아서 다른 worker 를 막지 않고,
+    // abort 가 취소되면 바로 false (예산은 쓰지 않음)
+    pub fn acquire_shared(budget: &Mutex<TokenBudget>, tokens: usize, abort: Option<&AbortToken>) -> bool {
+        loop {
+            let wait = match budget.lock() {
+                Ok(mut b) => b.try_acquire(tokens),
+                Err(_) => return true,
+            };
+            let Some(wait) = wait else { return true };
+            match abort {
+                Some(token) if !token.sleep(wait) => return false,
+                Some(_) => {}
+                None => std::thread::sleep(wait),
+            }
+        }
+    }
+}
+
+// ==========================================================
+// FILE TRANSPILER
+// ==========================================================
+// 변환용 시스템 프롬프트 (target version 이 있으면 제약 추가. version 이 opts 보다 우선)
+fn transpile_system(lang: &str, opts: &TranspileOptions, version: Option<&str>) -> String {
+    let mut system = system_prompt(Task::Transpile, opts.persona.as_deref(), lang);
+    system.push_str("\n\n");
+    system.push_str(opts.fidelity.instruction());
+    if opts.explicit_types {
+        system.push_str("\n\n");
+        system.push_str(&explicit_types_instruction(lang));
+    }
+    if let Some(target) = version.or(opts.target_versions.get(lang).map(String::as_str)) {
+        system.push_str("\n\n");
+        system.push_str(&version_constraint(lang, target));
+    }
+    system
+}
+
+// 응답이 ```lang ... ``` 로 감싸져 있으면 안쪽 코드만. (코드, 벗겼는지)
+pub fn strip_code_fences(response: &str) -> (String, bool) {
+    static RE: std::sync::OnceLock<Regex> = std::sync::OnceLock::new();
+    let re = RE.get_or_init(|| Regex::new(r"(?s)```[A-Za-z0-9_+.#-]*[ \t]*\r?\n(.*?)```").unwrap());
+    let blocks: Vec<&str> = re.captures_iter(response).map(|c| c.get(1).unwrap().as_str()).collect();
+    match blocks.as_slice() {
+        [] => (response.to_string(), false),
+        // 여러 블록이면 가장 긴 것을 코드로 봄 (나머지는 보통 사용 예시)
+        _ => (blocks.iter().max_by_key(|b| b.len()).unwrap().to_string(), true),
+    }
+}
+
+// transpile_str 결과
+#[derive(Debug, Clone, PartialEq)]
+pub struct TranspileOutput {
+    pub code: String,
+    /// 전송 전 시크릿 검사 결과 (Allow / Redact 정책에서 통과한 것)
+    pub secrets: Vec<Finding>,
+    /// 치환해서 보낸 값 수 / 결과에서 되돌린 값 수
+    pub redacted: usize,
+    pub restored: usize,
+    /// 라우팅 규칙으로 고른 모델 (없으면 기본 모델)
+    pub model: Option<String>,
+    pub fences_stripped: bool,
+    /// 괄호 짝 검사 통과 여부
+    pub balanced: bool,
+    pub indent_issues: Vec<usize>,
+    /// explicit_types 일 때 느슨한 타입 / 자리표시 타입이 남은 줄 수
+    pub loose_types: usize,
+    /// validate 옵션일 때 대상 컴파일러 검사 결과
+    pub validation: Option<SmokeResult>,
+}
+
+// 코드 조각 하나를 프로젝트 변환과 같은 파이프라인으로 변환
+// (시크릿 검사 -> 치환 -> 프롬프트 -> 호출 -> fence 제거 -> 복원 -> 정규화 -> 검사).
+// path: 원본 파일 경로를 알면 원본 언어를 확장자로 먼저 판단 (없으면 내용만 보고 추정)
+pub fn transpile_str<L: LLM>(
+    options: &TranspileOptions,
+    llm: &L,
+    src: &str,
+    lang: &str,
+    path: Option<&Path>,
+) -> Result<TranspileOutput, TranspileError> {
+    transpile_str_streaming(options, llm, src, lang, path, None)
+}
+
+// on_token 이 Some 이면 응답을 받는 대로 넘김 (큰 파일도 진행이 보이게). 넘기는 건 fence 제거 / 복원 전 원본 응답.
+// 후보 여러 개나 라우팅한 모델은 스트리밍하지 않으므로 응답 전체를 한 번에 넘김
+pub fn transpile_str_streaming<L: LLM>(
+    options: &TranspileOptions,
+    llm: &L,
+    src: &str,
+    lang: &str,
+    path: Option<&Path>,
+    on_token: Option<&mut dyn FnMut(&str)>,
+) -> Result<TranspileOutput, TranspileError> {
+    let secrets = detect_secrets(src);
+    let redactor = options
+        .redaction
+        .clone()
+        .or_else(|| (options.secrets == SecretPolicy::Redact).then(Redactor::secrets_only));
+    if !secrets.is_empty() && options.secrets == SecretPolicy::Block && redactor.is_none() {
+        return Err(TranspileError::SecretsBlocked(secrets));
+    }
+
+    let (shebang, body) = split_shebang(src);
+    let mut content = body.to_string();
+    let (mut redaction, mut redacted) = (None, 0);
+    if let Some(r) = &redactor {
+        let (text, map) = r.redact(&content);
+        if !map.is_empty() {
+            content = text;
+            redacted = map.len();
+            redaction = r.restore.then_some(map);
+        }
+    }
+
+    let system = transpile_system(lang, options, None);
+    let source_lang = detect_language(path.unwrap_or(Path::new("")), src);
+    let prompt = options.render_prompt(source_lang.as_deref(), lang, &content);
+    let model = route_model(&options.routes, estimate_tokens(&prompt), "").map(str::to_string);
+    let mut on_token = on_token;
+    let response = if options.candidates > 1 {
+        let candidates = llm
+            .predict_n_with_system(&system, &prompt, options.candidates)
+            .map_err(|e| TranspileError::ApiFailure(e.to_string()))?;
+        pick_candidate(candidates).unwrap_or_else(|| "(EMPTY)".into())
+    } else if let Some(m) = &model {
+        llm.predict_with_model(m, &system, &prompt)
+    } else if let Some(on_token) = on_token.take() {
+        llm.predict_stream_with_system(&system, &prompt, on_token)
+    } else {
+        llm.predict_with_system(&system, &prompt)
+    };
+    if is_error_response(&response) {
+        return Err(TranspileError::ApiFailure(response));
+    }
+    if let Some(on_token) = on_token {
+        on_token(&response);
+    }
+
+    let (mut code, fences_stripped) = strip_code_fences(&response);
+    let mut restored = 0;
+    if let Some(map) = &redaction {
+        let (text, n, _missing) = map.restore(&code);
+        code = text;
+        restored = n;
+    }
+
+    let mut code = normalize_output(&code);
+    if let (Some(_), rest) = split_shebang(&code) {
+        code = rest.to_string();
+    }
+    if let (Some(_), Some(line)) = (shebang, target_shebang(lang)) {
+        code = format!("{}\n{}", line, code);
+    }
+
+    Ok(TranspileOutput {
+        balanced: has_balanced_delimiters(&code),
+        indent_issues: if is_indentation_sensitive(lang) { indentation_issues(&code) } else { Vec::new() },
+        loose_types: if options.explicit_types { count_loose_types(&code, lang) } else { 0 },
+        validation: options.validate.then(|| validate_output(&code, lang)),
+        code,
+        secrets,
+        redacted,
+        restored,
+        model,
+        fences_stripped,
+    })
+}
+
+// ==========================================================
+// ROUND TRIP (A -> B -> A 로 되돌려서 원본과 비교)
+// ==========================================================
+// 완벽한 검사는 아님: 되돌린 코드가 원본과 많이 다르면 사람이 볼 만한 위험한 변환
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoundtripReport {
+    pub from: String,
+    pub to: String,
+    /// A -> B 결과
+    pub forward: String,
+    /// B -> A 결과
+    pub back: String,
+    /// 원본과 back 의 unified diff (줄 끝 공백 / 줄바꿈 차이는 무시)
+    pub diff: String,
+    pub lines_changed: usize,
+    /// 0.0 ~ 1.0, 공백을 뺀 줄 단위 유사도
+    pub similarity: f64,
+    /// 어느 단계든 실패하면 그 오류 (similarity 는 0)
+    pub error: Option<String>,
+}
+
+// 이 유사도보다 낮으면 roundtrip 명령이 실패
+pub const DEFAULT_ROUNDTRIP_THRESHOLD: f64 = 0.6;
+
+impl RoundtripReport {
+    pub fn passed(&self, threshold: f64) -> bool {
+        self.error.is_none() && self.similarity >= threshold
+    }
+}
+
+// 줄마다 공백을 모두 지우고 빈 줄은 버림
+fn compact_lines(code: &str) -> String {
+    code.lines()
+        .map(|l| l.split_whitespace().collect::<String>())
+        .filter(|l| !l.is_empty())
+        .map(|l| l + "\n")
+        .collect()
+}
+
+pub fn roundtrip_check<L: LLM>(llm: &L, src: &str, from: &str, to: &str) -> RoundtripReport {
+    roundtrip_check_with(llm, &TranspileOptions::default(), src, from, to)
+}
+
+// 두 번 모두 transpile_str 파이프라인 (시크릿 정책 / 치환 등은 options 그대로)
+pub fn roundtrip_check_with<L: LLM>(
+    llm: &L,
+    options: &TranspileOptions,
+    src: &str,
+    from: &str,
+    to: &str,
+) -> RoundtripReport {
+    let options = TranspileOptions { validate: false, ..options.clone() };
+    let mut report = RoundtripReport {
+        from: from.to_string(),
+        to: to.to_string(),
+        forward: String::new(),
+        back: String::new(),
+        diff: String::new(),
+        lines_changed: 0,
+        similarity: 0.0,
+        error: None,
+    };
+    let back = transpile_str(&options, llm, src, to, None).and_then(|out| {
+        report.forward = out.code;
+        transpile_str(&options, llm, &report.forward, from, None)
+    });
+    match back {
+        Ok(out) => report.back = out.code,
+        Err(e) => {
+            report.error = Some(e.to_string());
+            return report;
+        }
+    }
+
+    // 점수는 공백 / 빈 줄을 빼고 비교 (a+b 와 a + b 는 같은 줄). diff 는 보여주기용으로 그대로
+    let original = normalize_output(src);
+    let (a, b) = (compact_lines(&original), compact_lines(&report.back));
+    let diff = similar::TextDiff::from_lines(&a, &b);
+    report.similarity = diff.ratio() as f64;
+    report.lines_changed = diff.iter_all_changes().filter(|c| c.tag() != similar::ChangeTag::Equal).count();
+    report.diff = unified_diff(&original, &report.back, "original", &format!("roundtrip via {}", to));
+    report
+}
+
+// ==========================================================
+// SLIDING WINDOW (큰 파일의 반복적인 가운데 생략)
+// ==========================================================
+// chunking 보다 싸지만 거칠다: 가운데 줄은 모델이 보지 못하고 앞뒤 패턴에서 추측한다.
+// 가운데가 반복적(테이블, 생성된 상수 목록 등)일 때만 적용하고, 아니면 전체를 그대로 보냄
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WindowedSource {
+    pub text: String,
+    pub omitted_lines: usize,
+}
+
+// 숫자를 지우고 공백을 합친 "모양" 이 대부분 같으면 boilerplate
+const BOILERPLATE_UNIQUE_RATIO: f64 = 0.3;
+
+fn is_boilerplate(lines: &[&str]) -> bool {
+    let shapes: HashSet<String> = lines
+        .iter()
+        .map(|l| {
+            l.chars()
+                .filter(|c| !c.is_ascii_digit())
+                .collect::<String>()
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .collect();
+    !lines.is_empty() && (shapes.len() as f64 / lines.len() as f64) <= BOILERPLATE_UNIQUE_RATIO
+}
+
+// max_tokens 를 넘으면 앞/뒤 절반씩만 남긴 버전. 작거나 가운데가 반복적이지 않으면 None
+pub fn sliding_window(src: &str, max_tokens: usize) -> Option<WindowedSource> {
+    if estimate_tokens(src) <= max_tokens {
+        return None;
+    }
+    let lines: Vec<&str> = src.lines().collect();
+    // estimate_tokens 와 같은 기준 (4 chars / token)
+    let side_chars = max_tokens * 4 / 2;
+
+    let take = |it: &mut dyn Iterator<Item = &&str>| {
+        let mut chars = 0;
+        it.take_while(|l| {
+            chars += l.len() + 1;
+            chars <= side_chars
+        })
+        .count()
+    };
+    let head = take(&mut lines.iter());
+    let tail = take(&mut lines.iter().rev()).min(lines.len() - head);
+    let middle = &lines[head..lines.len() - tail];
+    if middle.is_empty() || !is_boilerplate(middle) {
+        return None;
+    }
+
+    let mut text = lines[..head].join("\n");
+    text.push_str(&format!(
+        "\n<<< {} lines omitted: repetitive content following the same pattern as the lines around it >>>\n",
+        middle.len()
+    ));
+    text.push_str(&lines[lines.len() - tail..].join("\n"));
+    Some(WindowedSource { text, omitted_lines: middle.len() })
+}
+
+// ==========================================================
+// IMPORT FIX POST-PASS
+// ==========================================================
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImportFix {
+    /// 외부 도구 (goimports 등) 로 보정
+    Tool { name: String, changed: bool },
+    /// LLM follow-up 프롬프트로 보정
+    Llm { changed: bool },
+}
+
+impl ImportFix {
+    pub fn changed(&self) -> bool {
+        match self {
+            ImportFix::Tool { changed, .. } | ImportFix::Llm { changed } => *changed,
+        }
+    }
+}
+
+// 표준입력으로 코드를 넘기고 표준출력을 받는 외부 필터 실행. 도구가 없거나 실패하면 None
+fn run_filter(cmd: &str, args: &[&str], input: &str) -> Option<String> {
+    use std::process::{Command, Stdio};
+
+    let mut child = Command::new(cmd)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .ok()?;
+    child.stdin.take()?.write_all(input.as_bytes()).ok()?;
+    let out = child.wait_with_output().ok()?;
+    if !out.status.success() {
+        return None;
+    }
+    String::from_utf8(out.stdout).ok()
+}
+
+// 언어별 import 문 명칭
+fn import_keyword(lang: &str) -> &'static str {
+    match lang {
+        "cpp" => "#include",
+        "rust" => "use",
+        _ => "import",
+    }
+}
+
+pub fn fix_imports<L: LLM>(llm: &L, code: &str, lang: &str) -> (String, ImportFix) {
+    if lang == "go" {
+        if let Some(fixed) = run_filter("goimports", &[], code) {
+            let changed = fixed != code;
+            return (fixed, ImportFix::Tool { name: "goimports".into(), changed });
+        }
+    }
+
+    let keyword = import_keyword(lang);
+    let fixed = llm.predict(&format!(
+        "The following {} code may be missing or have incorrect `{}` statements.\n\
+         Add or correct ONLY the {} statements so the file compiles, change nothing else, \
+         and return the complete file without explanation:\n{}",
+        lang, keyword, keyword, code
+    ));
+    // 오류 / 빈 응답은 멀쩡한 변환 결과를 덮어쓰지 않음
+    if is_error_response(&fixed) || fixed.trim().is_empty() {
+        warn!("[IMPORTS] import fix failed, keeping the transpiled code: {}", fixed.trim());
+        return (code.to_string(), ImportFix::Llm { changed: false });
+    }
+    let (fixed, _) = strip_code_fences(&fixed);
+    let changed = fixed != code;
+    (fixed, ImportFix::Llm { changed })
+}
+
+// ==========================================================
+// FILESYSTEM ABSTRACTION (프로젝트 변환의 디스크 접근)
+// ==========================================================
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FsMetadata {
+    /// is_dir / is_file / len 은 symlink 를 따라간 대상 기준
+    pub is_dir: bool,
+    pub is_file: bool,
+    pub len: u64,
+    /// 경로 자체가 symlink 인지
+    pub is_symlink: bool,
+}
+
+pub trait Fs: Sync {
+    /// 디렉토리 바로 아래 항목들의 전체 경로
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>>;
+    fn read_to_string(&self, path: &Path) -> io::Result<String>;
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()>;
+    fn create_dir_all(&self, path: &Path) -> io::Result<()>;
+    fn metadata(&self, path: &Path) -> io::Result<FsMetadata>;
+    fn remove_file(&self, path: &Path) -> io::Result<()>;
+    /// to 가 있으면 교체
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+
+    /// 옆의 <이름>.tmp 에 다 쓴 뒤 rename. 중간에 죽어도 path 는 없거나 완전한 파일 (실패하면 tmp 삭제)
+    fn write_atomic(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        let tmp = temp_sibling(path);
+        let written = self.write(&tmp, contents).and_then(|_| self.rename(&tmp, path));
+        if written.is_err() {
+            let _ = self.remove_file(&tmp);
+        }
+        written
+    }
+
+    /// 실행 권한 추가 (지원하지 않는 환경에서는 아무것도 안 함)
+    fn set_executable(&self, _path: &Path) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// from 의 실행 권한 비트를 to 에도 추가. 바꿨으면 true (지원하지 않는 환경에서는 아무것도 안 함)
+    fn copy_executable_bits(&self, _from: &Path, _to: &Path) -> io::Result<bool> {
+        Ok(false)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.metadata(path).is_ok()
+    }
+
+    /// symlink 를 모두 푼 절대 경로 (symlink 가 없는 환경에서는 그대로)
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        Ok(path.to_path_buf())
+    }
+}
+
+// name.go -> name.go.tmp (같은 디렉토리라 rename 이 원자적)
+pub fn temp_sibling(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    path.with_file_name(name)
+}
+
+// Windows 의 MAX_PATH. 이보다 긴 경로는 \\?\ 접두사가 없으면 Win32 API 가 거부함
+pub const WINDOWS_MAX_PATH: usize = 260;
+
+// 긴 절대 경로를 \\?\ 확장 경로로 (짧거나, 이미 확장 경로거나, 상대 경로면 None)
+pub fn extended_length_path(path: &Path) -> Option<PathBuf> {
+    let s = path.to_str()?;
+    if s.len() < WINDOWS_MAX_PATH || s.starts_with(r"\\?\") {
+        return None;
+    }
+    // 확장 경로에서는 '/' 가 구분자로 인정되지 않음
+    let s = s.replace('/', r"\");
+    if let Some(unc) = s.strip_prefix(r"\\") {
+        return Some(PathBuf::from(format!(r"\\?\UNC\{}", unc)));
+    }
+    let b = s.as_bytes();
+    let drive = b.len() >= 3 && b[0].is_ascii_alphabetic() && b[1] == b':' && b[2] == b'\\';
+    drive.then(|| PathBuf::from(format!(r"\\?\{}", s)))
+}
+
+// io 실패 메시지. Windows 에서 경로가 MAX_PATH 를 넘으면 해결 방법도 같이
+pub fn describe_io_failure(path: &Path, e: &io::Error) -> String {
+    if cfg!(windows) && path.as_os_str().len() >= WINDOWS_MAX_PATH {
+        format!(
+            "{} (path is {} chars, over MAX_PATH; use a shorter --out directory or enable LongPathsEnabled)",
+            e,
+            path.as_os_str().len()
+        )
+    } else {
+        e.to_string()
+    }
+}
+
+// std::fs 그대로. Windows 에서는 MAX_PATH 를 넘는 경로를 확장 경로로 바꿔서 호출
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RealFs;
+
+impl RealFs {
+    fn os_path(path: &Path) -> std::borrow::Cow<'_, Path> {
+        if cfg!(windows) {
+            if let Some(long) = std::path::absolute(path).ok().and_then(|p| extended_length_path(&p)) {
+                return long.into();
+            }
+        }
+        path.into()
+    }
+}
+
+impl Fs for RealFs {
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        // 호출한 쪽 경로 기준으로 돌려줌 (확장 경로가 report 에 섞이지 않게)
+        fs::read_dir(Self::os_path(path))?.map(|e| e.map(|e| path.join(e.file_name()))).collect()
+    }
+
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        fs::read_to_string(Self::os_path(path))
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        fs::write(Self::os_path(path), contents)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir_all(Self::os_path(path))
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<FsMetadata> {
+        let path = Self::os_path(path);
+        let is_symlink = fs::symlink_metadata(&path)?.file_type().is_symlink();
+        let m = fs::metadata(&path)?;
+        Ok(FsMetadata { is_dir: m.is_dir(), is_file: m.is_file(), len: m.len(), is_symlink })
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        fs::canonicalize(Self::os_path(path))
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(Self::os_path(path))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(Self::os_path(from), Self::os_path(to))
+    }
+
+    #[cfg(unix)]
+    fn set_executable(&self, path: &Path) -> io::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mut perms = fs::metadata(path)?.permissions();
+        perms.set_mode(perms.mode() | 0o111);
+        fs::set_permissions(path, perms)
+    }
+
+    // 나머지 권한은 출력 쪽 그대로 (읽기 전용 원본 때문에 다음 실행에서 덮어쓰지 못하는 일이 없게)
+    #[cfg(unix)]
+    fn copy_executable_bits(&self, from: &Path, to: &Path) -> io::Result<bool> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let bits = fs::metadata(from)?.permissions().mode() & 0o111;
+        let mut perms = fs::metadata(to)?.permissions();
+        if bits == 0 || perms.mode() & bits == bits {
+            return Ok(false);
+        }
+        perms.set_mode(perms.mode() | bits);
+        fs::set_permissions(to, perms)?;
+        Ok(true)
+    }
+}
+
+// 디스크를 건드리지 않는 가상 파일 트리 (테스트용). 디렉토리는 상위까지 자동 생성
+#[derive(Debug, Default)]
+pub struct MemoryFs {
+    files: Mutex<BTreeMap<PathBuf, Vec<u8>>>,
+    dirs: Mutex<BTreeSet<PathBuf>>,
+}
+
+impl MemoryFs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_file(self, path: impl AsRef<Path>, contents: &str) -> Self {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            let _ = self.create_dir_all(parent);
+        }
+        self.files.lock().unwrap().insert(path.to_path_buf(), contents.as_bytes().to_vec());
+        self
+    }
+
+    /// 현재 파일 트리 (경로 순)
+    pub fn files(&self) -> BTreeMap<PathBuf, Vec<u8>> {
+        self.files.lock().unwrap().clone()
+    }
+
+    fn not_found(path: &Path) -> io::Error {
+        io::Error::new(io::ErrorKind::NotFound, format!("{}: not found", path.display()))
+    }
+}
+
+impl Fs for MemoryFs {
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        if !self.dirs.lock().unwrap().contains(path) {
+            return Err(Self::not_found(path));
+        }
+        let is_child = |p: &&PathBuf| p.parent() == Some(path);
+        let mut entries: Vec<PathBuf> = self.dirs.lock().unwrap().iter().filter(is_child).cloned().collect();
+        entries.extend(self.files.lock().unwrap().keys().filter(is_child).cloned());
+        entries.sort();
+        Ok(entries)
+    }
+
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        let files = self.files.lock().unwrap();
+        let bytes = files.get(path).ok_or_else(|| Self::not_found(path))?;
+        String::from_utf8(bytes.clone()).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() && !self.dirs.lock().unwrap().contains(parent) => {
+                return Err(Self::not_found(parent));
+            }
+            _ => {}
+        }
+        if self.dirs.lock().unwrap().contains(path) {
+            return Err(io::Error::new(io::ErrorKind::IsADirectory, format!("{}: is a directory", path.display())));
+        }
+        self.files.lock().unwrap().insert(path.to_path_buf(), contents.to_vec());
+        Ok(())
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        let mut dirs = self.dirs.lock().unwrap();
+        for a in path.ancestors().filter(|a| !a.as_os_str().is_empty()) {
+            if self.files.lock().unwrap().contains_key(a) {
+                return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{}: is a file", a.display())));
+            }
+            dirs.insert(a.to_path_buf());
+        }
+        Ok(())
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<FsMetadata> {
+        if self.dirs.lock().unwrap().contains(path) {
+            return Ok(FsMetadata { is_dir: true, is_file: false, len: 0, is_symlink: false });
+        }
+        let files = self.files.lock().unwrap();
+        let bytes = files.get(path).ok_or_else(|| Self::not_found(path))?;
+        Ok(FsMetadata { is_dir: false, is_file: true, len: bytes.len() as u64, is_symlink: false })
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        self.files.lock().unwrap().remove(path).map(|_| ()).ok_or_else(|| Self::not_found(path))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut files = self.files.lock().unwrap();
+        let contents = files.remove(from).ok_or_else(|| Self::not_found(from))?;
+        files.insert(to.to_path_buf(), contents);
+        Ok(())
+    }
+}
+
+// ==========================================================
+// TRANSPILE REPORT
+// ==========================================================
+#[derive(Debug, Clone)]
+pub struct FileRecord {
+    /// merge 된 묶음이면 해당 디렉토리
+    pub source: PathBuf,
+    /// merge 로 하나의 출력이 된 원본 파일들 (일반 변환이면 비어 있음)
+    pub merged_from: Vec<PathBuf>,
+    pub output: PathBuf,
+    pub imports: Option<ImportFix>,
+    /// 들여쓰기 탭/공백 혼용 줄 (들여쓰기가 문법인 언어만 검사)
+    pub indent_issues: Vec<usize>,
+    /// 출력 경로에 맞게 고친 package 선언
+    pub package_fix: Option<PackageFix>,
+    /// explicit_types 일 때 느슨한 타입 / 자리표시 타입이 남은 줄 수
+    pub loose_types: usize,
+    /// --validate 결과 (요청한 경우만)
+    pub validation: Option<SmokeResult>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RunStatus {
+    #[default]
+    Completed,
+    /// AbortToken 으로 중간에 멈춤 (files 는 그때까지 변환된 것만)
+    Cancelled,
+    /// API 할당량 소진으로 멈춤. 남은 파일은 pending 에
+    QuotaExhausted,
+}
+
+#[derive(Debug, Default)]
+pub struct TranspileReport {
+    pub status: RunStatus,
+    pub files: Vec<FileRecord>,
+    /// 시크릿이 의심되는 파일과 처리 결과
+    pub secrets: Vec<SecretFlag>,
+    /// fine-tuning 데이터셋으로 내보낸 예제 수
+    pub finetune_examples: usize,
+    /// 이어쓰기 후에도 응답이 잘려서 쓰지 않은 파일
+    pub truncated: Vec<PathBuf>,
+    /// 변환에 실패해서 실패 이유와 원본을 주석으로 남긴 출력 파일
+    pub failed_outputs: Vec<PathBuf>,
+    /// 이번 변환 동안의 provider / model 별 사용량
+    pub per_provider: HashMap<String, ProviderStats>,
+    /// python 출력 디렉토리에 새로 만든 __init__.py
+    pub init_files: Vec<PathBuf>,
+    /// 출력 트리 smoke build 결과 (요청한 경우만)
+    pub smoke: Option<SmokeResult>,
+    /// 중간에 멈춰서 변환하지 못한 파일 (다시 실행하면 이어서 변환)
+    pub pending: Vec<PathBuf>,
+    /// 출력까지 쓴 단위 수 (merge 묶음은 1)
+    pub converted: usize,
+    /// 대상이 아니거나, 이미 있거나, 시크릿 / 읽기 실패로 건너뛴 파일 수
+    pub skipped: usize,
+    /// 요청 또는 쓰기에 실패한 파일 수
+    pub failed: usize,
+    /// 실행을 멈추지 않고 넘어간 개별 실패 (로그와 같은 내용)
+    pub errors: Vec<TranspileError>,
+    /// dry run 에서 변환했을 (원본, 출력) 쌍. merge 묶음이면 원본은 디렉토리
+    pub planned: Vec<(PathBuf, PathBuf)>,
+    /// --estimate: planned 항목별 예상 입력 토큰
+    pub estimated: Vec<(PathBuf, usize)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SecretFlag {
+    pub source: PathBuf,
+    pub findings: Vec<Finding>,
+    pub action: SecretPolicy,
+}
+
+#[derive(Debug)]
+pub enum TranspileError {
+    /// src_dir 이 존재하지 않음
+    SourceNotFound(PathBuf),
+    /// src_dir 이 디렉토리가 아님
+    SourceNotDir(PathBuf),
+    /// out_dir 을 만들거나 쓸 수 없음
+    OutputNotWritable(PathBuf, io::Error),
+    /// 파일 감시 시작 실패
+    Watch(String),
+    /// SecretPolicy::Block 인데 시크릿이 발견됨
+    SecretsBlocked(Vec<Finding>),
+    /// LLM 호출 실패 (오류 응답 원문)
+    ApiFailure(String),
+    /// 디렉토리 읽기 / 출력 쓰기 실패
+    Io(PathBuf, io::Error),
+    /// 원본 파일을 읽을 수 없어서 건너뜀 (권한, 도중에 삭제됨, UTF-8 아님)
+    SkippedUnreadable(PathBuf, io::Error),
+    /// ConflictPolicy::Error 인데 출력 파일이 이미 있음
+    OutputExists(Vec<PathBuf>),
+}
+
+impl fmt::Display for TranspileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TranspileError::SourceNotFound(p) => write!(f, "source directory not found: {}", p.display()),
+            TranspileError::SourceNotDir(p) => write!(f, "source path is not a directory: {}", p.display()),
+            TranspileError::OutputNotWritable(p, e) => {
+                write!(f, "output directory not writable: {} ({})", p.display(), e)
+            }
+            TranspileError::Watch(e) => write!(f, "cannot watch source directory: {}", e),
+            TranspileError::SecretsBlocked(found) => {
+                write!(f, "{} possible secrets found (use SecretPolicy::Redact or Allow)", found.len())
+            }
+            TranspileError::ApiFailure(e) => write!(f, "LLM request failed: {}", e),
+            TranspileError::Io(p, e) => write!(f, "{}: {}", p.display(), describe_io_failure(p, e)),
+            TranspileError::SkippedUnreadable(p, e) => write!(f, "cannot read {}: {} (skipped)", p.display(), e),
+            TranspileError::OutputExists(paths) => write!(
+                f,
+                "{} output files already exist (first: {}); use --on-conflict overwrite / skip or --clean",
+                paths.len(),
+                paths.first().map(|p| p.display().to_string()).unwrap_or_default()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TranspileError {}
+
+// 실행 전에 경로 확인 (오타난 경로로 중간에 panic 하지 않도록)
+fn validate_project_dirs(fs: &dyn Fs, src: &Path, out: &Path, dry_run: bool) -> Result<(), TranspileError> {
+    match fs.metadata(src) {
+        Err(_) => return Err(TranspileError::SourceNotFound(src.to_path_buf())),
+        Ok(m) if !m.is_dir => return Err(TranspileError::SourceNotDir(src.to_path_buf())),
+        Ok(_) => {}
+    }
+    // dry run 은 출력 디렉토리를 만들지 않음
+    if dry_run {
+        return Ok(());
+    }
+
+    let not_writable = |e| TranspileError::OutputNotWritable(out.to_path_buf(), e);
+    fs.create_dir_all(out).map_err(not_writable)?;
+    let probe = out.join(".intelligent-compiler-write-test");
+    fs.write(&probe, b"").map_err(not_writable)?;
+    let _ = fs.remove_file(&probe);
+    Ok(())
+}
+
+// ==========================================================
+// 5) ADVANCED PROJECT DIRECTORY TRANSPILER
+// ==========================================================
+
+// 기본적으로 변환하지 않을 폴더
+fn should_skip_dir(path: &Path) -> bool {
+    let skip_list = [
+        ".git",
+        "target",
+        "build",
+        "node_modules",
+        "__pycache__",
+        ".idea",
+        ".vscode",
+    ];
+
+    skip_list.iter().any(|&name| path.ends_with(name))
+}
+
+// ----------------------------------------------------------
+// .gitignore (소스 루트의 파일 하나만. 하위 디렉토리의 .gitignore 는 읽지 않음)
+// ----------------------------------------------------------
+// git 에는 두되 변환에서만 뺄 것 (생성된 데이터, 라이선스 헤더 등). 문법은 .gitignore 와 같음
+pub const TRANSPILEIGNORE_FILE: &str = ".transpileignore";
+
+#[derive(Debug, Clone)]
+struct IgnoreRule {
+    re: Regex,
+    negate: bool,
+    dir_only: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct GitIgnore {
+    root: PathBuf,
+    rules: Vec<IgnoreRule>,
+}
+
+// gitignore glob 하나를 루트 기준 상대 경로 ('/' 구분) 에 대한 regex 로
+fn glob_regex(pattern: &str) -> Option<Regex> {
+    // 중간에 '/' 가 있으면 루트 기준, 없으면 어느 깊이의 이름이든
+    let pattern = pattern.trim_end_matches('/');
+    let anchored = pattern.contains('/');
+    let pattern = pattern.trim_start_matches('/');
+    let mut re = String::from(if anchored { "^" } else { "^(?:.*/)?" });
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                if chars.get(i + 2) == Some(&'/') {
+                    re.push_str("(?:.*/)?");
+                    i += 3;
+                } else {
+                    re.push_str(".*");
+                    i += 2;
+                }
+                continue;
+            }
+            '*' => re.push_str("[^/]*"),
+            '?' => re.push_str("[^/]"),
+            '[' => match chars[i..].iter().position(|&c| c == ']') {
+                Some(end) if end > 1 => {
+                    let class: String = chars[i + 1..i + end].iter().collect();
+                    let class = class.strip_prefix('!').map(|c| format!("^{}", c)).unwrap_or(class);
+                    re.push_str(&format!("[{}]", class.replace('\\', r"\\")));
+                    i += end + 1;
+                    continue;
+                }
+                _ => re.push_str(r"\["),
+            },
+            '\\' if i + 1 < chars.len() => {
+                re.push_str(&regex::escape(&chars[i + 1].to_string()));
+                i += 2;
+                continue;
+            }
+            c => re.push_str(&regex::escape(&c.to_string())),
+        }
+        i += 1;
+    }
+    re.push('$');
+    Regex::new(&re).ok()
+}
+
+impl GitIgnore {
+    pub fn parse(root: &Path, text: &str) -> Self {
+        let rules = text
+            .lines()
+            .map(|l| l.trim_end())
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .filter_map(|line| {
+                let (negate, pattern) = match line.strip_prefix('!') {
+                    Some(rest) => (true, rest),
+                    None => (false, line.strip_prefix('\\').unwrap_or(line)),
+                };
+                let re = glob_regex(pattern)?;
+                Some(IgnoreRule { re, negate, dir_only: pattern.ends_with('/') })
+            })
+            .collect();
+        Self { root: root.to_path_buf(), rules }
+    }
+
+    // 루트의 .gitignore. 없거나 읽을 수 없으면 None
+    pub fn load(fs: &dyn Fs, root: &Path) -> Option<Self> {
+        Self::load_file(fs, root, ".gitignore")
+    }
+
+    // 루트의 같은 문법 파일 (.transpileignore 등)
+    pub fn load_file(fs: &dyn Fs, root: &Path, name: &str) -> Option<Self> {
+        let text = fs.read_to_string(&root.join(name)).ok()?;
+        Some(Self::parse(root, &text))
+    }
+
+    // 마지막으로 일치한 규칙이 결정. 상위 디렉토리가 제외되면 그 안의 모든 것도 제외
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        let Ok(rel) = path.strip_prefix(&self.root) else { return false };
+        let parts: Vec<String> = rel.components().map(|c| c.as_os_str().to_string_lossy().into_owned()).collect();
+        (1..=parts.len()).any(|n| {
+            let sub = parts[..n].join("/");
+            let dir = n < parts.len() || is_dir;
+            self.rules
+                .iter()
+                .rev()
+                .find(|r| (dir || !r.dir_only) && r.re.is_match(&sub))
+                .is_some_and(|r| !r.negate)
+        })
+    }
+}
+
+// ----------------------------------------------------------
+// INCREMENTAL MANIFEST (내용이 그대로인 원본은 다시 변환하지 않음)
+// ----------------------------------------------------------
+/// 출력 루트에 저장
+pub const MANIFEST_FILE: &str = ".transpile-manifest.json";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// 원본 (+ companion 지시문) 의 sha256
+    pub hash: String,
+    /// 출력 루트 기준 경로
+    pub output: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    /// 소스 루트 기준 경로 ('/' 구분) -> 마지막으로 변환했을 때의 상태
+    pub files: BTreeMap<String, ManifestEntry>,
+}
+
+// root 기준 상대 경로. OS 와 관계없이 같은 키가 되도록 '/' 로 이음
+fn slash_relative(root: &Path, path: &Path) -> String {
+    let rel = path.strip_prefix(root).unwrap_or(path);
+    rel.components().map(|c| c.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/")
+}
+
+fn source_hash(content: &str, instructions: Option<&str>) -> String {
+    sha256_hex(format!("{}\u{0}{}", content, instructions.unwrap_or("")).as_bytes())
+}
+
+impl Manifest {
+    // 없으면 빈 manifest. 깨졌으면 경고하고 빈 것으로 (전부 다시 변환)
+    pub fn load(fs: &dyn Fs, out_root: &Path) -> Self {
+        let path = out_root.join(MANIFEST_FILE);
+        let Ok(text) = fs.read_to_string(&path) else { return Self::default() };
+        serde_json::from_str(&text).unwrap_or_else(|e| {
+            warn!("[MANIFEST] {} is invalid ({}), converting everything", path.display(), e);
+            Self::default()
+        })
+    }
+
+    pub fn save(&self, fs: &dyn Fs, out_root: &Path) -> io::Result<()> {
+        let text = serde_json::to_string_pretty(self).map_err(io::Error::other)?;
+        fs.write_atomic(&out_root.join(MANIFEST_FILE), format!("{}\n", text).as_bytes())
+    }
+}
+
+// --include / --exclude glob (소스 루트 기준, .gitignore 와 같은 문법). exclude 가 우선
+#[derive(Debug, Clone, Default)]
+pub struct PathFilter {
+    pub include: Vec<Regex>,
+    pub exclude: Vec<Regex>,
+}
+
+impl PathFilter {
+    pub fn parse(include: &[String], exclude: &[String]) -> Result<Self, String> {
+        let compile = |patterns: &[String]| -> Result<Vec<Regex>, String> {
+            patterns
+                .iter()
+                .map(|p| p.trim())
+                .filter(|p| !p.is_empty())
+                .map(|p| glob_regex(p).ok_or_else(|| format!("invalid glob pattern '{}'", p)))
+                .collect()
+        };
+        Ok(Self { include: compile(include)?, exclude: compile(exclude)? })
+    }
+
+    // rel: 소스 루트 기준 '/' 경로. include 가 없으면 확장자 기본값 (is_convertible_file)
+    pub fn selects(&self, rel: &str, path: &Path) -> bool {
+        if self.exclude.iter().any(|re| re.is_match(rel)) {
+            return false;
+        }
+        if self.include.is_empty() {
+            is_convertible_file(path)
+        } else {
+            self.include.iter().any(|re| re.is_match(rel))
+        }
+    }
+
+    // 디렉토리 자체 ("build") 나 그 아래 전부 ("**/tests/**") 가 제외되면 들어가지 않음
+    pub fn excludes_dir(&self, rel: &str) -> bool {
+        let inner = format!("{}/", rel);
+        self.exclude.iter().any(|re| re.is_match(rel) || re.is_match(&inner))
+    }
+}
+
+// ----------------------------------------------------------
+// LANGUAGES (원본 확장자 / 대상 언어 / 프롬프트 이름의 단일 표)
+// ----------------------------------------------------------
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Language {
+    Rust,
+    Cpp,
+    C,
+    Python,
+    Go,
+    TypeScript,
+    JavaScript,
+    Swift,
+    Java,
+}
+
+impl Language {
+    pub const ALL: [Language; 9] = [
+        Language::Rust,
+        Language::Cpp,
+        Language::C,
+        Language::Python,
+        Language::Go,
+        Language::TypeScript,
+        Language::JavaScript,
+        Language::Swift,
+        Language::Java,
+    ];
+
+    /// --lang 으로 고를 수 있는 대상 언어
+    pub const TARGETS: [Language; 6] =
+        [Language::Go, Language::Cpp, Language::Swift, Language::Rust, Language::Python, Language::Java];
+
+    /// CLI / 내부 문자열 이름 (--lang 값)
+    pub fn id(self) -> &'static str {
+        match self {
+            Language::Rust => "rust",
+            Language::Cpp => "cpp",
+            Language::C => "c",
+            Language::Python => "python",
+            Language::Go => "go",
+            Language::TypeScript => "typescript",
+            Language::JavaScript => "javascript",
+            Language::Swift => "swift",
+            Language::Java => "java",
+        }
+    }
+
+    /// 출력 파일 확장자
+    pub fn extension(self) -> &'static str {
+        match self {
+            Language::Rust => "rs",
+            Language::Cpp => "cpp",
+            Language::C => "c",
+            Language::Python => "py",
+            Language::Go => "go",
+            Language::TypeScript => "ts",
+            Language::JavaScript => "js",
+            Language::Swift => "swift",
+            Language::Java => "java",
+        }
+    }
+
+    /// 프롬프트 / 로그에 쓰는 이름
+    pub fn display_name(self) -> &'static str {
+        match self {
+            Language::Rust => "Rust",
+            Language::Cpp => "C++",
+            Language::C => "C",
+            Language::Python => "Python",
+            Language::Go => "Go",
+            Language::TypeScript => "TypeScript",
+            Language::JavaScript => "JavaScript",
+            Language::Swift => "Swift",
+            Language::Java => "Java",
+        }
+    }
+
+    /// 변환 원본으로 읽는 확장자 (.h 는 C 로 두고 detect_language 가 내용으로 C++ 를 가림)
+    fn source_extensions(self) -> &'static [&'static str] {
+        match self {
+            Language::Rust => &["rs"],
+            Language::Cpp => &["cpp"],
+            Language::C => &["c", "h"],
+            Language::Python => &["py"],
+            Language::Go => &["go"],
+            Language::TypeScript => &["ts"],
+            Language::JavaScript => &["js"],
+            Language::Swift => &["swift"],
+            // 대상 전용
+            Language::Java => &[],
+        }
+    }
+
+    pub fn from_extension(ext: &str) -> Option<Self> {
+        let ext = ext.to_ascii_lowercase();
+        Self::ALL.into_iter().find(|l| l.source_extensions().contains(&ext.as_str()))
+    }
+
+    pub fn is_target(self) -> bool {
+        Self::TARGETS.contains(&self)
+    }
+
+    /// 한 줄 주석 기호
+    pub fn line_comment(self) -> &'static str {
+        match self {
+            Language::Python => "#",
+            _ => "//",
+        }
+    }
+}
+
+impl std::str::FromStr for Language {
+    type Err = String;
+
+    // id 또는 표시 이름 (대소문자 무시. c++ 도 허용)
+    fn from_str(s: &str) -> Result<Self, String> {
+        let name = s.trim().to_ascii_lowercase();
+        Self::ALL
+            .into_iter()
+            .find(|l| l.id() == name || l.display_name().to_ascii_lowercase() == name)
+            .ok_or_else(|| format!("unknown language '{}'", s))
+    }
+}
+
+impl fmt::Display for Language {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.display_name())
+    }
+}
+
+// 변환할 파일 확장자 (Language 표에서)
+fn convertible_exts() -> Vec<&'static str> {
+    Language::ALL.into_iter().flat_map(Language::source_extensions).copied().collect()
+}
+
+fn is_convertible_file(path: &Path) -> bool {
+    path.extension().is_some_and(|e| Language::from_extension(&e.to_string_lossy()).is_some())
+}
+
+// 변환 실패 자리에 쓰는 출력 파일의 첫 줄 표시
+const FAILED_MARKER: &str = "TRANSPILE FAILED:";
+
+// 실패 이유 + 원본 전체를 대상 언어 주석으로. 오류 문자열이 코드처럼 보이지 않게
+pub fn failure_stub(lang: &str, reason: &str, original: &str) -> String {
+    let c = lang.parse::<Language>().map_or("//", Language::line_comment);
+    let reason = reason.trim().trim_start_matches('(').trim_end_matches(')');
+    let mut out = format!("{} {} {}\n", c, FAILED_MARKER, reason.lines().next().unwrap_or(""));
+    out.push_str(&format!("{} The original source is preserved below; run again to retry.\n{}\n", c, c));
+    for line in original.lines() {
+        match line.trim_end() {
+            "" => out.push_str(&format!("{}\n", c)),
+            l => out.push_str(&format!("{} {}\n", c, l)),
+        }
+    }
+    out
+}
+
+// 이전 실행이 남긴 실패 표시 파일인지 (다시 변환해도 되는지)
+pub fn is_failure_stub(text: &str) -> bool {
+    let first = text.lines().next().unwrap_or("");
+    let body = first.trim_start_matches(['/', '#']).trim_start();
+    body.len() < first.len() && body.starts_with(FAILED_MARKER)
+}
+
+// 프롬프트용 이름 ("cpp" -> "C++"). 모르는 언어는 그대로
+fn language_name(lang: &str) -> &str {
+    match lang.parse::<Language>() {
+        Ok(l) => l.display_name(),
+        Err(_) => lang,
+    }
+}
+
+// 원본 언어 감지: 확장자 우선, .h 와 확장자 없는 파일은 내용으로 추정
+pub fn detect_language(path: &Path, content: &str) -> Option<String> {
+    let by_ext = path.extension().and_then(|e| Language::from_extension(&e.to_string_lossy())).map(|l| match l {
+        Language::C if looks_like_cpp(content) => Language::Cpp,
+        other => other,
+    });
+    by_ext.or_else(|| detect_language_from_content(content)).map(|l| l.display_name().to_string())
+}
+
+fn looks_like_cpp(content: &str) -> bool {
+    static RE: std::sync::OnceLock<Regex> = std::sync::OnceLock::new();
+    RE.get_or_init(|| {
+        Regex::new(r"(?m)\b(class|namespace|template\s*<|public:|private:|virtual)\b|std::|#include\s*<(iostream|string|vector|memory|map)>")
+            .unwrap()
+    })
+    .is_match(content)
+}
+
+// 확장자가 없을 때: shebang -> 언어별 특징 구문 순으로 가볍게 추정
+fn detect_language_from_content(content: &str) -> Option<Language> {
+    if let (Some(bang), _) = split_shebang(content) {
+        if bang.contains("python") {
+            return Some(Language::Python);
+        }
+        if bang.contains("node") || bang.contains("deno") {
+            return Some(Language::JavaScript);
+        }
+        if bang.contains("swift") {
+            return Some(Language::Swift);
+        }
+    }
+    // #include 는 C / C++ 공통이라 C 로 두고 아래에서 가림. 파일마다 불리므로 한 번만 컴파일
+    static RULES: std::sync::OnceLock<Vec<(Regex, Language)>> = std::sync::OnceLock::new();
+    let rules = RULES.get_or_init(|| {
+        CONTENT_RULES.iter().map(|&(pattern, lang)| (Regex::new(pattern).unwrap(), lang)).collect()
+    });
+    let lang = rules.iter().find(|(re, _)| re.is_match(content))?.1;
+    Some(if lang == Language::C && looks_like_cpp(content) { Language::Cpp } else { lang })
+}
+
+// detect_language_from_content 가 순서대로 보는 언어별 특징 구문
+const CONTENT_RULES: [(&str, Language); 8] = [
+    (r"(?m)^\s*(pub\s+)?fn\s+\w+|^\s*use\s+\w+::|\blet\s+mut\b", Language::Rust),
+    (r"(?m)^package\s+\w+\s*$|^func\s+(\(\w+\s+\*?\w+\)\s*)?\w+\(", Language::Go),
+    (r"(?m)^import\s+(Foundation|UIKit|SwiftUI)\b|\bguard\s+let\b|^\s*func\s+\w+\(.*\)\s*->", Language::Swift),
+    (r"(?m)^\s*(def|class)\s+\w+.*:\s*$|^\s*(from\s+\S+\s+)?import\s+\w+\s*$", Language::Python),
+    (r"(?m)^\s*#include\b", Language::C),
+    (r"(?m)\binterface\s+\w+\s*\{|:\s*(string|number|boolean)\b", Language::TypeScript),
+    (r"(?m)\bfunction\s+\w+\s*\(|\b(const|let)\s+\w+\s*=.*=>|\brequire\(|module\.exports", Language::JavaScript),
+    (r"(?m)^\s*(int|void|char)\s+\*?\w+\s*\(", Language::C),
+];
+
+// 모델이 주석 / docstring 을 자주 빠뜨리거나 요약하므로 기본 템플릿에서 명시
+pub const DEFAULT_PROMPT_TEMPLATE: &str = "Transpile this {src_lang} code fully into {lang} code. \
+Keep every comment and docstring: carry each one over at the same place in {lang} comment syntax, \
+without dropping, summarizing or adding comments.\n{content}";
+
+impl TranspileOptions {
+    // source_lang 은 detect_language 결과 (모르면 None -> any.<대상> 또는 기본 템플릿)
+    pub fn prompt_template(&self, source_lang: Option<&str>, lang: &str) -> &str {
+        self.prompt_templates.get(source_lang, lang)
+    }
+
+    pub fn render_prompt(&self, source_lang: Option<&str>, lang: &str, content: &str) -> String {
+        render_prompt(self.prompt_template(source_lang, lang), source_lang, lang, content)
+    }
+}
+
+// 템플릿 채우기. 원본 언어를 모르면 {src_lang} 은 "source".
+// 코드 안의 "{lang}" 같은 글자는 건드리지 않도록 {content} 는 따로 끼워 넣음 (없으면 끝에 붙임)
+pub fn render_prompt(template: &str, source_lang: Option<&str>, lang: &str, content: &str) -> String {
+    let fill = |part: &str| {
+        part.replace("{lang}", language_name(lang)).replace("{src_lang}", source_lang.unwrap_or("source"))
+    };
+    match template.split_once("{content}") {
+        Some((head, tail)) => format!("{}{}{}", fill(head), content, fill(tail)),
+        None => format!("{}\n{}", fill(template), content),
+    }
+}
+
+// ----------------------------------------------------------
+// PROMPT TEMPLATES ((원본, 대상) 언어 쌍별 템플릿. 없으면 any.<대상>, 그다음 기본)
+// ----------------------------------------------------------
+/// 작업 디렉토리에 있으면 내장 템플릿 위에 덮어씀
+pub const PROMPTS_FILE: &str = "prompts.toml";
+/// 원본 언어 자리의 wildcard (원본을 모를 때도 이것)
+pub const ANY_LANG: &str = "any";
+
+// 언어 쌍마다 모델이 자주 틀리는 부분 (메모리 모델, 오류 처리 관용구)
+const BUILTIN_PROMPT_NOTES: &[(&str, &str, &str)] = &[
+    (
+        "python",
+        "rust",
+        "Map exceptions to Result with the ? operator and None to Option; give lists and dicts concrete \
+         Vec / HashMap element types; take parameters by reference (&T, &str) and avoid unwrap() unless the \
+         Python code would crash at that point too.",
+    ),
+    (
+        "python",
+        "go",
+        "Turn exceptions into returned error values checked at each call site, give every variable a concrete \
+         type, and write comprehensions as explicit loops.",
+    ),
+    (
+        "cpp",
+        "go",
+        "Drop manual memory management (new/delete, smart pointers, destructors) in favor of Go values, slices \
+         and garbage collection, using defer for cleanup; turn exceptions into returned error values and \
+         templates into generics or interfaces.",
+    ),
+    (
+        "cpp",
+        "rust",
+        "Express RAII and smart pointers as ownership (Box, Rc / Arc, Drop), raw pointers as references or \
+         indices, and exceptions as Result; keep unsafe out unless the original relies on pointer arithmetic.",
+    ),
+    (
+        "c",
+        "rust",
+        "Replace malloc/free and out-parameters with owned values and return types, error codes with Result, \
+         and null pointers with Option.",
+    ),
+    (
+        "go",
+        "rust",
+        "Map (value, error) returns to Result, goroutines and channels to std::thread and std::sync::mpsc, \
+         and nil to Option.",
+    ),
+];
+
+#[derive(Debug, Clone)]
+pub struct PromptTemplates {
+    /// 맞는 쌍이 없을 때
+    pub default: String,
+    /// (원본 id 또는 ANY_LANG, 대상 id) -> 템플릿
+    pairs: HashMap<(String, String), String>,
+}
+
+impl Default for PromptTemplates {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+// 언어 id 로 맞춤 (C++ / c++ -> cpp). 표에 없는 이름은 소문자 그대로
+fn prompt_lang_key(lang: &str) -> String {
+    lang.parse::<Language>().map_or_else(|_| lang.trim().to_ascii_lowercase(), |l| l.id().to_string())
+}
+
+// 템플릿의 {content} 앞에 언어별 지시를 끼워 넣음 (여러 줄 템플릿이면 한 줄로 따로)
+pub fn prompt_with_notes(template: &str, notes: &str) -> String {
+    let (head, tail) = template.split_once("{content}").unwrap_or((template, ""));
+    let head = head.trim_end();
+    let sep = if head.contains('\n') { "\n" } else { " " };
+    let content = if template.contains("{content}") { "\n{content}" } else { "" };
+    format!("{}{}{}{}{}", head, sep, notes.trim(), content, tail)
+}
+
+impl PromptTemplates {
+    pub fn builtin() -> Self {
+        let mut t = Self { default: DEFAULT_PROMPT_TEMPLATE.into(), pairs: HashMap::new() };
+        for (from, to, notes) in BUILTIN_PROMPT_NOTES {
+            t.set(from, to, prompt_with_notes(DEFAULT_PROMPT_TEMPLATE, notes));
+        }
+        t
+    }
+
+    // --prompt-template: 모든 언어 쌍에 같은 템플릿
+    pub fn uniform(template: impl Into<String>) -> Self {
+        Self { default: template.into(), pairs: HashMap::new() }
+    }
+
+    pub fn set(&mut self, from: &str, to: &str, template: impl Into<String>) {
+        self.pairs.insert((prompt_lang_key(from), prompt_lang_key(to)), template.into());
+    }
+
+    pub fn get(&self, from: Option<&str>, to: &str) -> &str {
+        let to = prompt_lang_key(to);
+        from.map(prompt_lang_key)
+            .and_then(|from| self.pairs.get(&(from, to.clone())))
+            .or_else(|| self.pairs.get(&(ANY_LANG.to_string(), to)))
+            .unwrap_or(&self.default)
+    }
+
+    // prompts.toml 이 있으면 읽고, 없거나 잘못됐으면 내장 템플릿
+    pub fn discover() -> Self {
+        let path = Path::new(PROMPTS_FILE);
+        if !path.exists() {
+            return Self::builtin();
+        }
+        match Self::load(path) {
+            Ok(t) => {
+                debug!("[TEMPLATE] loaded {}", path.display());
+                t
+            }
+            Err(e) => {
+                warn!("[TEMPLATE] {}: {} (using bundled templates)", path.display(), e);
+                Self::builtin()
+            }
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Self::parse(&text)
+    }
+
+    // TOML 의 필요한 부분만 지원:
+    //   [default]
+    //   template = """
+    //   Convert this {src_lang} code to {lang}.
+    //   {content}
+    //   """
+    //   [python.rust]
+    //   notes = "Prefer iterators over index loops."
+    //   [any.go]
+    //   template = "..."
+    // template 은 그 쌍의 템플릿을 교체, notes 는 (파일의) 기본 템플릿에 지시를 덧붙임.
+    // 파일에 없는 쌍은 내장 템플릿 그대로
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut t = Self::builtin();
+        // notes 는 파일의 [default] 위치와 관계없이 최종 기본 템플릿 기준
+        let mut notes: Vec<(String, String, String)> = Vec::new();
+        let mut section: Option<Option<(String, String)>> = None;
+        let mut lines = text.lines().enumerate();
+        while let Some((i, raw)) = lines.next() {
+            let line = strip_toml_comment(raw).trim();
+            if line.is_empty() {
+                continue;
+            }
+            let err = |msg: String| format!("line {}: {}", i + 1, msg);
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                let name = name.trim();
+                section = Some(match name.split_once('.') {
+                    None if name == "default" => None,
+                    Some((from, to)) if !from.trim().is_empty() && !to.trim().is_empty() && !to.contains('.') => {
+                        Some((from.trim().to_string(), to.trim().to_string()))
+                    }
+                    _ => return Err(err(format!("unknown table [{}] (expected [default] or [from.to])", name))),
+                });
+                continue;
+            }
+            let Some((key, value)) = raw.split_once('=') else {
+                return Err(err(format!("expected key = value, got '{}'", line)));
+            };
+            let Some(pair) = &section else {
+                return Err(err("key outside of a [default] or [from.to] table".into()));
+            };
+            let key = key.trim();
+            let value = match value.trim_start().strip_prefix("\"\"\"") {
+                // 여는 따옴표 바로 뒤의 줄바꿈은 값에 넣지 않음
+                Some(rest) => match rest.split_once("\"\"\"") {
+                    Some((body, _)) => body.to_string(),
+                    None => {
+                        let mut body = rest.to_string();
+                        loop {
+                            let Some((_, next)) = lines.next() else {
+                                return Err(err(format!("unterminated \"\"\" string for {}", key)));
+                            };
+                            if let Some((last, _)) = next.split_once("\"\"\"") {
+                                body.push('\n');
+                                body.push_str(last);
+                                break;
+                            }
+                            body.push('\n');
+                            body.push_str(next);
+                        }
+                        body.strip_prefix('\n').unwrap_or(&body).to_string()
+                    }
+                },
+                None => parse_toml_scalar(strip_toml_comment(value))
+                    .ok_or_else(|| err(format!("invalid string for {}", key)))?,
+            };
+            match (key, pair) {
+                ("template", None) => t.default = value,
+                ("template", Some((from, to))) => t.set(from, to, value),
+                ("notes", Some((from, to))) => notes.push((from.clone(), to.clone(), value)),
+                ("notes", None) => return Err(err("notes needs a [from.to] table".into())),
+                _ => return Err(err(format!("unknown key '{}' (expected template or notes)", key))),
+            }
+        }
+        for (from, to, n) in notes {
+            let template = prompt_with_notes(&t.default, &n);
+            t.set(&from, &to, template);
+        }
+        Ok(t)
+    }
+}
+
+// --ext 로 이번 실행만 확장자 제한 (기본 allowlist 와 교집합)
+fn is_selected_ext(path: &Path, only: &[String]) -> bool {
+    if only.is_empty() {
+        return true;
+    }
+    let e = path.extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+    only.iter().any(|o| o == &e)
+}
+
+// --lang 검증. 내부에서는 Language::id 문자열로 다룸 (c++ -> cpp)
+fn parse_target_lang(s: &str) -> Result<String, String> {
+    match s.parse::<Language>() {
+        Ok(lang) if lang.is_target() => Ok(lang.id().to_string()),
+        _ => {
+            let valid: Vec<&str> = Language::TARGETS.iter().map(|l| l.id()).collect();
+            Err(format!("unsupported target language '{}' (expected one of: {})", s, valid.join(", ")))
+        }
+    }
+}
+
+// 언어별 변환된 확장자 (라이브러리에서 모르는 언어를 넘기면 txt)
+fn mapped_ext(lang: &str) -> &'static str {
+    lang.parse::<Language>().map_or("txt", Language::extension)
+}
+
+// 원본 확장자를 대상 확장자로 교체 (math.py -> math.go, foo.test.ts -> foo.test.go, Makefile -> Makefile.go)
+fn output_filename(path: &Path, lang: &str) -> String {
+    let stem = match path.extension() {
+        Some(_) => path.file_stem(),
+        None => path.file_name(),
+    };
+    format!("{}.{}", stem.unwrap_or_default().to_string_lossy(), mapped_ext(lang))
+}
+
+// ----------------------------------------------------------
+// 출력 디렉토리 레이아웃 (실험 run 끼리 덮어쓰지 않도록)
+// ----------------------------------------------------------
+#[derive(Debug, Clone, Default)]
+pub struct OutputLayout {
+    /// out_dir/run-YYYYMMDD-HHMMSS/ 아래에 기록
+    pub run_stamp: Option<String>,
+    /// out_dir/<lang>/ 아래에 기록
+    pub per_language: bool,
+}
+
+impl OutputLayout {
+    pub fn resolve(&self, base: &Path, lang: &str) -> PathBuf {
+        let mut dir = base.to_path_buf();
+        if let Some(stamp) = &self.run_stamp {
+            dir.push(format!("run-{}", stamp));
+        }
+        if self.per_language {
+            dir.push(lang);
+        }
+        dir
+    }
+}
+
+// UTC 기준 YYYYMMDD-HHMMSS (chrono 없이 civil-from-days 계산)
+pub fn utc_timestamp() -> String {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+    let (days, rem) = (secs.div_euclid(86_400), secs.rem_euclid(86_400));
+
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year, month, day, rem / 3_600, (rem % 3_600) / 60, rem % 60
+    )
+}
+
+// foo.py 옆의 foo.py.transpile 에 적힌 파일별 지시문 (없으면 None)
+fn companion_instructions(fs: &dyn Fs, path: &Path) -> Option<String> {
+    let mut companion = path.as_os_str().to_owned();
+    companion.push(".transpile");
+    fs.read_to_string(Path::new(&companion))
+        .ok()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+}
+
+// walk 전체에서 공유하는 상태
+struct WalkCtx<'a, L: LLM> {
+    fs: &'a dyn Fs,
+    llm: &'a L,
+    lang: &'a str,
+    /// 소스 트리 루트 (--include / --exclude, manifest 경로 기준)
+    src_root: PathBuf,
+    /// 출력 트리 루트 (package 이름 계산 기준)
+    out_root: PathBuf,
+    opts: &'a TranspileOptions,
+    system: String,
+    /// worker 끼리 공유 (TPM 한도는 프로세스 전체 기준)
+    budget: Option<Arc<Mutex<TokenBudget>>>,
+    redactor: Option<Redactor>,
+    /// 소스 루트의 .gitignore (no_gitignore 거나 파일이 없으면 None)
+    gitignore: Option<GitIgnore>,
+    /// 소스 루트의 .transpileignore (no_gitignore 와 관계없이 적용)
+    transpileignore: Option<GitIgnore>,
+    /// fine-tuning 예제 (JSONL 한 줄씩)
+    finetune: Vec<String>,
+    report: TranspileReport,
+    /// 할당량 소진을 본 worker 가 세움 (모든 worker 공유)
+    quota_exhausted: Arc<AtomicBool>,
+    /// load_manifest 전에는 None (프로젝트 변환과 watch 모드가 시작할 때 읽음)
+    manifest: Option<Arc<Mutex<Manifest>>>,
+}
+
+// 오류가 아니고 구문 검사를 통과한 결과만 학습 데이터로 사용
+fn finetune_example(system: &str, prompt: &str, code: &str) -> Option<String> {
+    if is_error_response(code) || code.trim().is_empty() || !has_balanced_delimiters(code) {
+        return None;
+    }
+    let line = json!({
+        "messages": [
+            { "role": "system", "content": system },
+            { "role": "user", "content": prompt },
+            { "role": "assistant", "content": code }
+        ]
+    });
+    Some(line.to_string())
+}
+
+impl<'a, L: LLM> WalkCtx<'a, L> {
+    fn new(
+        fs: &'a dyn Fs,
+        llm: &'a L,
+        lang: &'a str,
+        src_root: &Path,
+        out_root: &Path,
+        opts: &'a TranspileOptions,
+    ) -> Self {
+        Self {
+            fs,
+            llm,
+            lang,
+            src_root: src_root.to_path_buf(),
+            out_root: out_root.to_path_buf(),
+            opts,
+            system: transpile_system(lang, opts, None),
+            budget: opts.tpm_limit.map(|tpm| Arc::new(Mutex::new(TokenBudget::new(tpm)))),
+            redactor: opts
+                .redaction
+                .clone()
+                .or_else(|| (opts.secrets == SecretPolicy::Redact).then(Redactor::secrets_only)),
+            gitignore: None,
+            transpileignore: None,
+            finetune: Vec::new(),
+            report: TranspileReport::default(),
+            quota_exhausted: Arc::default(),
+            manifest: None,
+        }
+    }
+
+    fn load_manifest(&mut self) {
+        let manifest = Manifest::load(self.fs, &self.out_root);
+        if !manifest.files.is_empty() {
+            debug!("[MANIFEST] {} tracked files", manifest.files.len());
+        }
+        self.manifest = Some(Arc::new(Mutex::new(manifest)));
+    }
+
+    fn save_manifest(&self) {
+        let Some(manifest) = &self.manifest else { return };
+        let Ok(manifest) = manifest.lock() else { return };
+        if let Err(e) = manifest.save(self.fs, &self.out_root) {
+            warn!("[MANIFEST] cannot write {}: {}", self.out_root.join(MANIFEST_FILE).display(), e);
+        }
+    }
+
+    // 이전 실행에서 이 출력 경로로 변환한 기록 (force 면 무시)
+    fn tracked(&self, source: &Path, out_path: &Path) -> Option<ManifestEntry> {
+        if self.opts.force {
+            return None;
+        }
+        let manifest = self.manifest.as_ref()?.lock().ok()?;
+        let entry = manifest.files.get(&slash_relative(&self.src_root, source)).cloned()?;
+        (entry.output == slash_relative(&self.out_root, out_path)).then_some(entry)
+    }
+
+    // 실패 표시 파일은 변환 결과가 아니므로 --overwrite 없이도 다시 씀
+    fn failed_before(&self, out_path: &Path) -> bool {
+        self.fs.read_to_string(out_path).is_ok_and(|t| is_failure_stub(&t))
+    }
+
+    // 이전 기록이 없는 출력 파일이 이미 있을 때 on_conflict 대로. 변환을 계속하면 true
+    fn resolve_conflict(&mut self, out_path: &Path, files: usize) -> bool {
+        if self.failed_before(out_path) {
+            return true;
+        }
+        match self.opts.on_conflict {
+            ConflictPolicy::Overwrite => true,
+            ConflictPolicy::Skip => {
+                self.skip("EXISTS", out_path, files);
+                false
+            }
+            // 프로젝트 변환은 시작 전에 이미 멈추므로 여기 오는 건 watch 뿐
+            ConflictPolicy::Error => {
+                self.fail(TranspileError::OutputExists(vec![out_path.to_path_buf()]));
+                false
+            }
+        }
+    }
+
+    fn record(&self, source: &Path, hash: &str, out_path: &Path) {
+        let Some(manifest) = &self.manifest else { return };
+        if let Ok(mut manifest) = manifest.lock() {
+            manifest.files.insert(
+                slash_relative(&self.src_root, source),
+                ManifestEntry { hash: hash.to_string(), output: slash_relative(&self.out_root, out_path) },
+            );
+        }
+    }
+
+    fn cancelled(&self) -> bool {
+        self.opts.abort.as_ref().is_some_and(|t| t.is_cancelled())
+    }
+
+    fn load_ignore_files(&mut self, src_root: &Path) {
+        self.transpileignore = GitIgnore::load_file(self.fs, src_root, TRANSPILEIGNORE_FILE);
+        if let Some(t) = &self.transpileignore {
+            debug!("[TRANSPILEIGNORE] {} rules from {}", t.rules.len(), src_root.join(TRANSPILEIGNORE_FILE).display());
+        }
+        if self.opts.no_gitignore {
+            return;
+        }
+        self.gitignore = GitIgnore::load(self.fs, src_root);
+        if let Some(g) = &self.gitignore {
+            debug!("[GITIGNORE] {} rules from {}", g.rules.len(), src_root.join(".gitignore").display());
+        }
+    }
+
+    fn gitignored(&self, path: &Path, is_dir: bool) -> bool {
+        self.gitignore.as_ref().is_some_and(|g| g.is_ignored(path, is_dir))
+    }
+
+    fn transpileignored(&self, path: &Path, is_dir: bool) -> bool {
+        self.transpileignore.as_ref().is_some_and(|t| t.is_ignored(path, is_dir))
+    }
+
+    // 변환 대상인지 (--include / --exclude, 없으면 확장자 기본값) + --ext
+    fn selected(&self, path: &Path) -> bool {
+        let rel = slash_relative(&self.src_root, path);
+        self.opts.path_filter.selects(&rel, path) && is_selected_ext(path, &self.opts.only_exts)
+    }
+
+    fn excluded_dir(&self, path: &Path) -> bool {
+        self.opts.path_filter.excludes_dir(&slash_relative(&self.src_root, path))
+    }
+
+    // 건너뛴 파일 로그 (debug). dry run 이면 [WOULD SKIP] 으로
+    fn skip(&mut self, tag: &str, path: &Path, count: usize) {
+        self.skip_at(log::Level::Debug, tag, path, count);
+    }
+
+    // 기본 출력에도 보여야 하는 skip (바이너리, 큰 파일 등)
+    fn skip_at(&mut self, level: log::Level, tag: &str, path: &Path, count: usize) {
+        if self.opts.dry_run {
+            info!("[WOULD SKIP] {} ({})", path.display(), tag);
+        } else {
+            log::log!(level, "[{}] {}", tag, path.display());
+        }
+        self.report.skipped += count;
+    }
+
+    // dry run: 실제 변환 대신 계획만 기록. --estimate 면 prompt 기준 토큰 / 비용도
+    fn plan(&mut self, source: &Path, output: &Path, prompt: Option<&str>) {
+        info!("[WOULD CONVERT] {} -> {}", source.display(), output.display());
+        self.report.planned.push((source.to_path_buf(), output.to_path_buf()));
+        if let (Some(info), Some(prompt)) = (&self.opts.estimate, prompt) {
+            let tokens = estimate_tokens(&format!("{}{}", self.system, prompt));
+            info!("[ESTIMATE] {}: ~{} input tokens, ~${:.4}", source.display(), tokens, estimate_cost(info, tokens));
+            self.report.estimated.push((source.to_path_buf(), tokens));
+        }
+    }
+
+    // 크기 제한을 넘으면 [TOO LARGE] 로 건너뜀
+    fn too_large(&mut self, path: &Path) -> bool {
+        let Some(limit) = max_file_size(self.opts) else { return false };
+        match self.fs.metadata(path) {
+            Ok(m) if m.len > limit => {
+                self.skip_at(log::Level::Info, "TOO LARGE", path, 1);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // 원본 읽기. UTF-8 이 아니거나 NUL 이 있으면 [BINARY], 그 외 오류는 unreadable
+    fn read_source(&mut self, path: &Path) -> Option<String> {
+        match self.fs.read_to_string(path) {
+            Ok(text) if looks_binary(&text) => {
+                self.skip_at(log::Level::Info, "BINARY", path, 1);
+                None
+            }
+            Ok(text) => Some(text),
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                self.skip_at(log::Level::Info, "BINARY", path, 1);
+                None
+            }
+            Err(e) => {
+                self.skip_unreadable(path, e);
+                None
+            }
+        }
+    }
+
+    fn skip_unreadable(&mut self, path: &Path, e: io::Error) {
+        let err = TranspileError::SkippedUnreadable(path.to_path_buf(), e);
+        warn!("[SKIP] {}", err);
+        self.report.skipped += 1;
+        self.report.errors.push(err);
+    }
+
+    // 실패는 기록만 하고 다음 파일로 진행
+    fn fail(&mut self, err: TranspileError) {
+        error!("[FAILED] {}", err);
+        self.report.failed += 1;
+        self.report.errors.push(err);
+    }
+
+    // 더 이상 요청을 보내면 안 되는 상태 (취소 또는 quota 소진)
+    fn halted(&self) -> bool {
+        self.cancelled() || self.quota_exhausted.load(Ordering::SeqCst)
+    }
+
+    // 같은 설정 / 예산을 공유하고 결과만 따로 모으는 worker 용 복사본
+    fn worker(&self) -> Self {
+        Self {
+            fs: self.fs,
+            llm: self.llm,
+            lang: self.lang,
+            src_root: self.src_root.clone(),
+            out_root: self.out_root.clone(),
+            opts: self.opts,
+            system: self.system.clone(),
+            budget: self.budget.clone(),
+            redactor: self.redactor.clone(),
+            gitignore: self.gitignore.clone(),
+            transpileignore: self.transpileignore.clone(),
+            finetune: Vec::new(),
+            report: TranspileReport::default(),
+            quota_exhausted: self.quota_exhausted.clone(),
+            manifest: self.manifest.clone(),
+        }
+    }
+}
+
+// 파일 하나 변환 (walk 와 watch 가 공유)
+fn convert_file<L: LLM>(ctx: &mut WalkCtx<L>, path: &Path, out: &Path) {
+    if !ctx.selected(path) {
+        ctx.skip("IGNORE", path, 1);
+        return;
+    }
+
+    if ctx.too_large(path) {
+        return;
+    }
+
+    let out_path = out.join(output_filename(path, ctx.lang));
+    let exists = ctx.fs.exists(&out_path);
+    // 직접 만든 출력은 manifest 기준으로 덮어써도 됨
+    let tracked = exists.then(|| ctx.tracked(path, &out_path)).flatten();
+    if exists && tracked.is_none() && !ctx.resolve_conflict(&out_path, 1) {
+        return;
+    }
+    let Some(content) = ctx.read_source(path) else { return };
+    let instructions = companion_instructions(ctx.fs, path);
+    let hash = source_hash(&content, instructions.as_deref());
+    if tracked.is_some_and(|t| t.hash == hash) {
+        ctx.skip("UNCHANGED", path, 1);
+        return;
+    }
+    let source_lang = detect_language(path, &content);
+    if ctx.opts.dry_run {
+        let prompt = ctx.opts.render_prompt(source_lang.as_deref(), ctx.lang, &content);
+        ctx.plan(path, &out_path, Some(&prompt));
+        return;
+    }
+
+    match &source_lang {
+        Some(src) => info!("[CONVERT] {} ({})", path.display(), src),
+        None => info!("[CONVERT] {}", path.display()),
+    }
+    // shebang 은 모델에 맡기지 않고 직접 처리
+    let (shebang, body) = split_shebang(&content);
+    let shebang = shebang.is_some();
+    let mut content = body.to_string();
+    let mut header = String::new();
+    if let Some(limit) = ctx.opts.window_tokens.filter(|&n| estimate_tokens(&content) > n) {
+        match sliding_window(&content, limit) {
+            Some(w) => {
+                debug!("[WINDOW] {}: omitted {} repetitive middle lines", path.display(), w.omitted_lines);
+                content = w.text;
+                header.push_str(
+                    "Some repetitive lines in the middle were omitted; reproduce that section in full following the visible pattern.",
+                );
+            }
+            None => debug!("[WINDOW] {}: middle is not repetitive, sending the full file", path.display()),
+        }
+    }
+    let ext = path.extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+    transpile_unit(
+        ctx,
+        Unit {
+            source: path.to_path_buf(),
+            merged_from: Vec::new(),
+            header,
+            source_lang,
+            content,
+            instructions,
+            ext,
+            shebang,
+            out_path,
+            source_hash: Some(hash),
+        },
+    );
+}
+
+// ----------------------------------------------------------
+// MERGE (여러 원본 -> 출력 하나)
+// ----------------------------------------------------------
+#[derive(Debug, Clone)]
+pub enum MergeMode {
+    /// 디렉토리 안의 변환 대상 파일 전부
+    PerDirectory,
+    /// 파일 이름이 regex 에 맞는 파일만
+    Matching(Regex),
+}
+
+impl MergeMode {
+    // "dir" 이면 디렉토리 단위, 그 외는 파일 이름 regex
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        match spec.trim() {
+            "dir" => Ok(MergeMode::PerDirectory),
+            pattern => Regex::new(pattern)
+                .map(MergeMode::Matching)
+                .map_err(|e| format!("invalid merge pattern '{}': {}", pattern, e)),
+        }
+    }
+
+    fn includes(&self, path: &Path) -> bool {
+        match self {
+            MergeMode::PerDirectory => true,
+            MergeMode::Matching(re) => path
+                .file_name()
+                .is_some_and(|n| re.is_match(&n.to_string_lossy())),
+        }
+    }
+}
+
+const MERGE_SEPARATOR: &str = "===== FILE:";
+
+// 묶음 전송 한도: 기본 모델 context window, TPM 한도가 더 작으면 그쪽
+fn merge_token_limit(opts: &TranspileOptions) -> usize {
+    let window = model_info_or_default(DEFAULT_MODEL).context_window;
+    opts.tpm_limit.map_or(window, |tpm| tpm.min(window))
+}
+
+// 묶음 출력 파일: 디렉토리 이름 + 대상 확장자
+fn group_output(dir: &Path, out: &Path, lang: &str) -> PathBuf {
+    let name = dir
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "merged".into());
+    out.join(format!("{}.{}", name, mapped_ext(lang)))
+}
+
+// 한 디렉토리의 파일 묶음을 출력 하나로. 너무 크면 파일별 변환으로 대체
+fn convert_group<L: LLM>(ctx: &mut WalkCtx<L>, files: &[PathBuf], dir: &Path, out: &Path) {
+    let out_path = group_output(dir, out, ctx.lang);
+    if ctx.fs.exists(&out_path) && !ctx.resolve_conflict(&out_path, files.len()) {
+        return;
+    }
+
+    let mut content = String::new();
+    let mut instructions = Vec::new();
+    let mut shebang = false;
+    for f in files {
+        if ctx.too_large(f) {
+            continue;
+        }
+        let Some(text) = ctx.read_source(f) else { continue };
+        let file_name = f.file_name().unwrap().to_string_lossy();
+        content.push_str(&format!("{} {} =====\n", MERGE_SEPARATOR, file_name));
+        let (bang, body) = split_shebang(&text);
+        shebang |= bang.is_some();
+        content.push_str(body);
+        if !content.ends_with('\n') {
+            content.push('\n');
+        }
+        if let Some(extra) = companion_instructions(ctx.fs, f) {
+            instructions.push(format!("{}: {}", file_name, extra));
+        }
+    }
+    if content.is_empty() {
+        return;
+    }
+    let header = format!(
+        "The following {} source files form one module. Transpile them together into a single coherent {} file \
+         (lines starting with '{}' mark where each original file begins):",
+        files.len(),
+        ctx.lang,
+        MERGE_SEPARATOR
+    );
+
+    let prompt = format!("{}\n{}", header, ctx.opts.render_prompt(None, ctx.lang, &content));
+    let tokens = estimate_request_tokens(&format!("{}{}", ctx.system, prompt));
+    let limit = merge_token_limit(ctx.opts);
+    if tokens > limit {
+        warn!(
+            "[MERGE FALLBACK] {}: ~{} tokens exceeds {}, converting files separately",
+            dir.display(),
+            tokens,
+            limit
+        );
+        for f in files {
+            convert_file(ctx, f, out);
+        }
+        return;
+    }
+
+    if ctx.opts.dry_run {
+        ctx.plan(dir, &out_path, Some(&prompt));
+        return;
+    }
+    info!("[MERGE] {} <- {} files", out_path.display(), files.len());
+    let ext = files[0].extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+    transpile_unit(
+        ctx,
+        Unit {
+            source: dir.to_path_buf(),
+            merged_from: files.to_vec(),
+            header,
+            source_lang: None,
+            content,
+            instructions: (!instructions.is_empty()).then(|| instructions.join("\n")),
+            ext,
+            shebang,
+            out_path,
+            source_hash: None,
+        },
+    );
+}
+
+// ----------------------------------------------------------
+// SHEBANG (#!/usr/bin/env python3 등)
+// ----------------------------------------------------------
+
+// 첫 줄이 shebang 이면 (shebang, 나머지) 로 분리. Rust 의 #![...] 속성은 shebang 이 아님
+pub fn split_shebang(src: &str) -> (Option<&str>, &str) {
+    if !src.starts_with("#!") || src.starts_with("#![") {
+        return (None, src);
+    }
+    match src.split_once('\n') {
+        Some((line, rest)) => (Some(line.trim_end_matches('\r')), rest),
+        None => (Some(src), ""),
+    }
+}
+
+// 스크립트로 실행되는 대상 언어의 shebang. 컴파일 언어는 None (shebang 제거)
+pub fn target_shebang(lang: &str) -> Option<&'static str> {
+    match lang {
+        "python" => Some("#!/usr/bin/env python3"),
+        "swift" => Some("#!/usr/bin/env swift"),
+        _ => None,
+    }
+}
+
+// ----------------------------------------------------------
+// PACKAGE / MODULE DECLARATIONS
+// ----------------------------------------------------------
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackageFix {
+    pub expected: String,
+    /// 모델이 쓴 선언 (없었으면 None)
+    pub found: Option<String>,
+}
+
+// go 는 디렉토리 이름으로 쓸 수 있는 식별자만 허용
+fn go_package_name(dir: &str) -> String {
+    let name: String = dir
+        .to_lowercase()
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '_')
+        .collect();
+    match name.chars().next() {
+        None => "main".into(),
+        Some(c) if c.is_ascii_digit() => format!("p{}", name),
+        Some(_) => name,
+    }
+}
+
+// 출력 경로에서 나오는 package 이름. 루트 바로 아래 go 파일은 main, java 는 루트 기준 경로
+pub fn expected_package(lang: &str, out_root: &Path, out_path: &Path) -> Option<String> {
+    let rel = out_path.parent()?.strip_prefix(out_root).ok()?;
+    let dirs: Vec<String> = rel.components().map(|c| c.as_os_str().to_string_lossy().into_owned()).collect();
+    match lang {
+        "go" => Some(dirs.last().map(|d| go_package_name(d)).unwrap_or_else(|| "main".into())),
+        "java" if !dirs.is_empty() => Some(
+            dirs.iter()
+                .map(|d| d.to_lowercase().replace(|c: char| !c.is_ascii_alphanumeric() && c != '_', "_"))
+                .collect::<Vec<_>>()
+                .join("."),
+        ),
+        _ => None,
+    }
+}
+
+// 첫 package 선언을 expected 로 맞춤. 선언이 없으면 맨 앞에 추가
+pub fn fix_package_decl(lang: &str, code: &str, expected: &str) -> (String, Option<PackageFix>) {
+    let re = Regex::new(r"(?m)^[ \t]*package[ \t]+([A-Za-z_][\w.]*)[ \t]*;?[ \t]*$").unwrap();
+    let decl = if lang == "java" { format!("package {};", expected) } else { format!("package {}", expected) };
+    match re.captures(code) {
+        Some(c) if &c[1] == expected => (code.to_string(), None),
+        Some(c) => {
+            let found = c[1].to_string();
+            let range = c.get(0).unwrap().range();
+            let fixed = format!("{}{}{}", &code[..range.start], decl, &code[range.end..]);
+            (fixed, Some(PackageFix { expected: expected.into(), found: Some(found) }))
+        }
+        None => (
+            format!("{}\n\n{}", decl, code),
+            Some(PackageFix { expected: expected.into(), found: None }),
+        ),
+    }
+}
+
+// 루트 아래 python 출력 디렉토리마다 __init__.py (없을 때만)
+fn ensure_python_package<L: LLM>(ctx: &mut WalkCtx<L>, out_path: &Path) {
+    let Some(dir) = out_path.parent() else { return };
+    if dir == ctx.out_root || !dir.starts_with(&ctx.out_root) {
+        return;
+    }
+    let init = dir.join("__init__.py");
+    if !ctx.fs.exists(&init) && ctx.fs.write(&init, b"").is_ok() {
+        info!("[PACKAGE] created {}", init.display());
+        ctx.report.init_files.push(init);
+    }
+}
+
+// 변환하지 못한 단위를 pending 으로 (묶음이면 원본 파일들)
+fn pend<L: LLM>(ctx: &mut WalkCtx<L>, source: &Path, merged_from: &[PathBuf]) {
+    if merged_from.is_empty() {
+        ctx.report.pending.push(source.to_path_buf());
+    } else {
+        ctx.report.pending.extend(merged_from.iter().cloned());
+    }
+}
+
+// 변환 단위 하나: 파일 하나, 또는 merge 된 파일 묶음
+struct Unit {
+    /// 로그 / report 에 쓰는 이름
+    source: PathBuf,
+    merged_from: Vec<PathBuf>,
+    /// 템플릿 앞에 붙는 추가 지시 (묶음 설명, window 안내). 없으면 빈 문자열
+    header: String,
+    /// 템플릿의 {src_lang} (묶음이면 None)
+    source_lang: Option<String>,
+    content: String,
+    instructions: Option<String>,
+    /// 라우팅 규칙용 원본 확장자
+    ext: String,
+    /// 원본에 shebang 이 있었는지 (전송 전에 제거됨)
+    shebang: bool,
+    out_path: PathBuf,
+    /// 저장에 성공하면 manifest 에 기록 (merge 묶음은 None)
+    source_hash: Option<String>,
+}
+
+// 파일 / 묶음 공통 파이프라인 (시크릿 검사 -> 전송 -> 후처리 -> 저장)
+fn transpile_unit<L: LLM>(ctx: &mut WalkCtx<L>, unit: Unit) {
+    let Unit { source, merged_from, header, source_lang, mut content, instructions, ext, shebang, out_path, source_hash } =
+        unit;
+    let path = source.as_path();
+
+    let secrets = detect_secrets(&content);
+    if !secrets.is_empty() {
+        // redactor 가 설정돼 있으면 시크릿도 치환되므로 막을 필요 없음
+        let action = match ctx.opts.secrets {
+            SecretPolicy::Block if ctx.redactor.is_some() => SecretPolicy::Redact,
+            other => other,
+        };
+        for f in &secrets {
+            warn!("[SECRET] {}: {}", path.display(), f);
+        }
+        ctx.report.secrets.push(SecretFlag { source: path.to_path_buf(), findings: secrets, action });
+        match action {
+            SecretPolicy::Block => {
+                warn!("[SECRET SKIP] {} (use --allow-secrets or --redact-secrets)", path.display());
+                ctx.report.skipped += merged_from.len().max(1);
+                return;
+            }
+            SecretPolicy::Redact | SecretPolicy::Allow => {}
+        }
+    }
+
+    // 실패하면 출력 파일에 남길 원본 (치환 / window 전)
+    let original = if merged_from.is_empty() {
+        ctx.fs.read_to_string(path).unwrap_or_else(|_| content.clone())
+    } else {
+        content.clone()
+    };
+    let mut redaction = None;
+    if let Some(redactor) = &ctx.redactor {
+        let (redacted, map) = redactor.redact(&content);
+        if !map.is_empty() {
+            info!("[REDACT] {} ({} values)", path.display(), map.len());
+            content = redacted;
+            redaction = redactor.restore.then_some(map);
+        }
+    }
+
+    let package = expected_package(ctx.lang, &ctx.out_root, &out_path);
+    let mut prompt = ctx.opts.render_prompt(source_lang.as_deref(), ctx.lang, &content);
+    if let Some(pkg) = &package {
+        prompt = format!("Use `package {}` as the package declaration.\n{}", pkg, prompt);
+    }
+    if !header.is_empty() {
+        prompt = format!("{}\n{}", header, prompt);
+    }
+    if let Some(extra) = instructions {
+        debug!("[STYLE] {} has companion instructions", path.display());
+        prompt = format!("File-specific instructions:\n{}\n\n{}", extra, prompt);
+    }
+    if let Some(budget) = &ctx.budget {
+        let tokens = estimate_request_tokens(&format!("{}{}", ctx.system, prompt));
+        // TPM 대기 중에 취소되면 보내지 않고 다음 실행으로
+        if !TokenBudget::acquire_shared(budget, tokens, ctx.opts.abort.as_ref()) {
+            warn!("[CANCELLED] {}", path.display());
+            pend(ctx, &source, &merged_from);
+            return;
+        }
+    }
+
+    let code = if ctx.opts.candidates > 1 {
+        match ctx.llm.predict_n_with_system(&ctx.system, &prompt, ctx.opts.candidates) {
+            Ok(c) => pick_candidate(c).unwrap_or_else(|| "(EMPTY)".into()),
+            Err(e) => e.to_string(),
+        }
+    } else if let Some(model) = route_model(
+        &ctx.opts.routes,
+        estimate_tokens(&prompt),
+        &ext,
+    ) {
+        debug!("[ROUTE] {} -> {}", path.display(), model);
+        ctx.llm.predict_with_model(model, &ctx.system, &prompt)
+    } else {
+        ctx.llm.predict_with_system(&ctx.system, &prompt)
+    };
+
+    if code.starts_with(QUOTA_MARKER) {
+        if !ctx.quota_exhausted.swap(true, Ordering::SeqCst) {
+            error!("[QUOTA] {}: {}", path.display(), code);
+            error!("[QUOTA] API quota exhausted, stopping the run (no retries)");
+        }
+        pend(ctx, &source, &merged_from);
+        return;
+    }
+
+    // 요청 도중 취소돼서 응답을 못 받았으면 다음 실행에서 변환. 받은 응답은 (이미 비용을 냈으니) 씀
+    if ctx.cancelled() && is_error_response(&code) {
+        warn!("[CANCELLED] {}", path.display());
+        pend(ctx, &source, &merged_from);
+        return;
+    }
+
+    // 잘린 코드는 완성된 것처럼 쓰지 않음
+    if code.starts_with(TRUNCATED_MARKER) {
+        error!("[FAILED TRUNCATED] {}: {}", path.display(), code);
+        ctx.report.truncated.push(path.to_path_buf());
+        ctx.report.failed += 1;
+        return;
+    }
+    // 오류 응답 (UTF-8 거부 포함) 은 코드로 쓰지 않고 실패 표시 + 원본 주석으로
+    if is_error_response(&code) {
+        ctx.fail(TranspileError::ApiFailure(format!("{}: {}", path.display(), code)));
+        let stub = failure_stub(ctx.lang, &code, &original);
+        let bytes = encode_output(&stub, ctx.opts.output_encoding)
+            .map(|b| b.into_owned())
+            .unwrap_or_else(|_| stub.clone().into_bytes());
+        match ctx.fs.write_atomic(&out_path, &bytes) {
+            Ok(()) => ctx.report.failed_outputs.push(out_path),
+            Err(e) => error!("[FAILED] {}: cannot write failure note: {}", out_path.display(), e),
+        }
+        return;
+    }
+
+    let (mut code, _) = strip_code_fences(&code);
+    if let Some(map) = &redaction {
+        let (restored, n, missing) = map.restore(&code);
+        code = restored;
+        info!("[RESTORE] {} ({} values)", out_path.display(), n);
+        for m in missing {
+            warn!("[RESTORE MISSING] {}: {} not found in output", out_path.display(), m);
+        }
+    }
+
+    let mut imports = None;
+    if ctx.opts.fix_imports {
+        let (fixed, how) = fix_imports(ctx.llm, &code, ctx.lang);
+        info!("[IMPORTS] {} {:?}", out_path.display(), how);
+        code = fixed;
+        imports = Some(how);
+    }
+
+    if ctx.opts.finetune_export.is_some() {
+        if let Some(example) = finetune_example(&ctx.system, &prompt, &code) {
+            ctx.finetune.push(example);
+        }
+    }
+
+    let mut code = normalize_output(&code);
+    // 모델이 직접 붙인 shebang 은 버리고, 원본이 스크립트였으면 대상 언어 shebang 을 붙임
+    if let (Some(_), rest) = split_shebang(&code) {
+        code = rest.to_string();
+    }
+    let mut package_fix = None;
+    if let Some(pkg) = package.as_ref().filter(|_| !is_error_response(&code)) {
+        let (fixed, fix) = fix_package_decl(ctx.lang, &code, pkg);
+        if let Some(f) = &fix {
+            info!(
+                "[PACKAGE] {}: {} -> {}",
+                out_path.display(),
+                f.found.as_deref().unwrap_or("(missing)"),
+                f.expected
+            );
+        }
+        code = fixed;
+        package_fix = fix;
+    }
+    let executable = shebang && target_shebang(ctx.lang).is_some();
+    if shebang {
+        match target_shebang(ctx.lang) {
+            Some(line) => code = format!("{}\n{}", line, code),
+            None => debug!("[SHEBANG] {}: dropped for compiled target {}", path.display(), ctx.lang),
+        }
+    }
+    let indent_issues = if is_indentation_sensitive(ctx.lang) {
+        indentation_issues(&code)
+    } else {
+        Vec::new()
+    };
+    if !indent_issues.is_empty() {
+        warn!("[INDENT] {} mixes tabs and spaces on lines {:?}", out_path.display(), indent_issues);
+    }
+    let loose_types = if ctx.opts.explicit_types { count_loose_types(&code, ctx.lang) } else { 0 };
+    if loose_types > 0 {
+        warn!("[TYPES] {}: {} lines with loose or placeholder types", out_path.display(), loose_types);
+    }
+    // 검사 실패해도 파일은 씀 (사람이 고칠 출발점). 결과는 report 에
+    let validation = ctx.opts.validate.then(|| validate_output(&code, ctx.lang));
+    match &validation {
+        Some(r) if r.is_failure() => warn!("[VALIDATE] {}: {}", out_path.display(), r),
+        Some(r) => debug!("[VALIDATE] {}: {}", out_path.display(), r),
+        None => {}
+    }
+
+    let bytes = match encode_output(&code, ctx.opts.output_encoding) {
+        Ok(b) => b,
+        Err(e) => {
+            error!("[FAILED] {}: {}", out_path.display(), e);
+            ctx.report.failed += 1;
+            return;
+        }
+    };
+    // 응답을 다 받은 뒤에만 제자리로 rename (죽어도 반쯤 쓴 출력이 완성본처럼 남지 않음)
+    if let Err(e) = ctx.fs.write_atomic(&out_path, &bytes) {
+        ctx.fail(TranspileError::Io(out_path, e));
+        return;
+    }
+    if ctx.lang == "python" {
+        ensure_python_package(ctx, &out_path);
+    }
+    if executable {
+        if let Err(e) = ctx.fs.set_executable(&out_path) {
+            error!("[SHEBANG] {}: cannot set executable bit: {}", out_path.display(), e);
+        }
+    } else if merged_from.is_empty() && target_shebang(ctx.lang).is_some() {
+        // shebang 없이 실행 권한만 있던 스크립트도 그대로 실행할 수 있게
+        match ctx.fs.copy_executable_bits(path, &out_path) {
+            Ok(true) => debug!("[MODE] {}: executable bit copied from {}", out_path.display(), path.display()),
+            Ok(false) => {}
+            Err(e) => error!("[MODE] {}: cannot copy permissions: {}", out_path.display(), e),
+        }
+    }
+    if let Some(hash) = &source_hash {
+        ctx.record(path, hash, &out_path);
+    }
+    ctx.report.converted += 1;
+    ctx.report.files.push(FileRecord {
+        source: path.to_path_buf(),
+        merged_from,
+        output: out_path,
+        package_fix,
+        imports,
+        indent_issues,
+        loose_types,
+        validation,
+    });
+}
+
+pub fn transpile_project<L: LLM + Sync>(
+    llm: &L,
+    src_dir: &str,
+    out_dir: &str,
+    lang: &str,
+    opts: &TranspileOptions,
+) -> Result<TranspileReport, TranspileError> {
+    let mut report = transpile_project_in(&RealFs, llm, Path::new(src_dir), Path::new(out_dir), lang, opts)?;
+    if let Some(limit) = opts.smoke_build.filter(|_| report.status == RunStatus::Completed && !opts.dry_run) {
+        let result = smoke_compile(Path::new(out_dir), lang, limit);
+        info!("[SMOKE] {}", result);
+        report.smoke = Some(result);
+    }
+    Ok(report)
+}
+
+// walk 가 모은 변환 작업 하나
+enum Job {
+    File { path: PathBuf, out: PathBuf },
+    Group { files: Vec<PathBuf>, dir: PathBuf, out: PathBuf },
+}
+
+fn run_job<L: LLM>(ctx: &mut WalkCtx<L>, job: Job) {
+    // 멈춘 뒤의 작업은 변환하지 않고 pending 으로만 기록
+    if ctx.halted() {
+        match job {
+            Job::File { path, .. } => ctx.report.pending.push(path),
+            Job::Group { files, .. } => ctx.report.pending.extend(files),
+        }
+        return;
+    }
+    match job {
+        Job::File { path, out } => convert_file(ctx, &path, &out),
+        Job::Group { files, dir, out } => convert_group(ctx, &files, &dir, &out),
+    }
+}
+
+impl TranspileReport {
+    // worker 결과 합치기
+    fn absorb(&mut self, other: TranspileReport) {
+        if other.status != RunStatus::Completed {
+            self.status = other.status;
+        }
+        self.pending.extend(other.pending);
+        self.files.extend(other.files);
+        self.secrets.extend(other.secrets);
+        self.truncated.extend(other.truncated);
+        self.failed_outputs.extend(other.failed_outputs);
+        self.init_files.extend(other.init_files);
+        self.converted += other.converted;
+        self.skipped += other.skipped;
+        self.failed += other.failed;
+        self.errors.extend(other.errors);
+        self.planned.extend(other.planned);
+        self.estimated.extend(other.estimated);
+    }
+}
+
+// 작업 수와 설정으로 worker 수 결정 (1 이면 순차)
+fn plan_workers(jobs: usize, opts: &TranspileOptions) -> usize {
+    let min = opts.parallel_min_files.unwrap_or(DEFAULT_PARALLEL_MIN_FILES);
+    let workers = effective_jobs(opts);
+    if workers <= 1 || jobs < min.max(2) {
+        1
+    } else {
+        workers.min(jobs)
+    }
+}
+
+// opts.jobs == 0 이면 CPU 수 (LLM 호출은 I/O 대기라 CPU 수보다 많아도 됨)
+fn effective_jobs(opts: &TranspileOptions) -> usize {
+    match opts.jobs {
+        0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    }
+}
+
+// ----------------------------------------------------------
+// PROGRESS (변환할 단위 수 기준 진행률)
+// ----------------------------------------------------------
+// 지금 터미널에 그려진 진행 막대 (ConsoleLogger 가 로그 전에 지우고 다시 그림)
+static PROGRESS_LINE: Mutex<Option<String>> = Mutex::new(None);
+
+const PROGRESS_BAR_WIDTH: usize = 30;
+
+pub struct Progress {
+    total: usize,
+    done: AtomicUsize,
+    started: Instant,
+    /// false 면 막대 대신 5% 마다 [PROGRESS] 한 줄 (CI 로그용)
+    tty: bool,
+}
+
+impl Progress {
+    pub fn new(total: usize) -> Self {
+        let tty = io::stdout().is_terminal() && io::stderr().is_terminal();
+        Self { total, done: AtomicUsize::new(0), started: Instant::now(), tty }
+    }
+
+    // 단위 하나 완료 (worker 끼리 공유)
+    pub fn tick(&self, name: &str) {
+        let done = self.done.fetch_add(1, Ordering::SeqCst) + 1;
+        let elapsed = self.started.elapsed();
+        let eta = elapsed.mul_f64(self.total.saturating_sub(done) as f64 / done as f64);
+        let times = format!("elapsed {}, eta {}", short_duration(elapsed), short_duration(eta));
+        if self.tty {
+            let filled = PROGRESS_BAR_WIDTH * done.min(self.total) / self.total.max(1);
+            draw_progress(Some(format!(
+                "[{}{}] {}/{} {} ({})",
+                "#".repeat(filled),
+                "-".repeat(PROGRESS_BAR_WIDTH - filled),
+                done,
+                self.total,
+                name,
+                times
+            )));
+        } else if done.is_multiple_of((self.total / 20).max(1)) || done == self.total {
+            info!("[PROGRESS] {}/{} ({}%) {}", done, self.total, done * 100 / self.total.max(1), times);
+        }
+    }
+
+    pub fn finish(&self) {
+        if self.tty {
+            draw_progress(None);
+        }
+    }
+}
+
+fn draw_progress(line: Option<String>) {
+    let Ok(mut current) = PROGRESS_LINE.lock() else { return };
+    let mut err = io::stderr().lock();
+    let _ = write!(err, "\r\x1b[2K{}", line.as_deref().unwrap_or(""));
+    let _ = err.flush();
+    *current = line;
+}
+
+// 1h02m / 3m05s / 42s
+fn short_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+impl Job {
+    // 진행률에 셀 단위인지 (대상이 아닌 파일은 바로 건너뛰므로 세지 않음)
+    fn counted<L: LLM>(&self, ctx: &WalkCtx<L>) -> bool {
+        match self {
+            Job::File { path, .. } => is_convertible_file(path) && ctx.selected(path),
+            Job::Group { .. } => true,
+        }
+    }
+
+    // 이 작업이 쓸 출력 파일
+    fn output_path(&self, lang: &str) -> PathBuf {
+        match self {
+            Job::File { path, out } => out.join(output_filename(path, lang)),
+            Job::Group { dir, out, .. } => group_output(dir, out, lang),
+        }
+    }
+
+    fn name(&self) -> String {
+        let path = match self {
+            Job::File { path, .. } => path,
+            Job::Group { dir, .. } => dir,
+        };
+        path.file_name().map_or_else(|| path.display().to_string(), |n| n.to_string_lossy().into_owned())
+    }
+}
+
+// 진행률을 세면서 run_job
+fn run_counted<L: LLM>(ctx: &mut WalkCtx<L>, job: Job, progress: Option<&Progress>) {
+    let tick = progress.filter(|_| job.counted(ctx)).map(|p| (p, job.name()));
+    run_job(ctx, job);
+    if let Some((p, name)) = tick {
+        p.tick(&name);
+    }
+}
+
+// 파일시스템을 주입받는 버전 (MemoryFs 로 디스크 없이 실행 가능)
+pub fn transpile_project_in<L: LLM + Sync>(
+    fs: &dyn Fs,
+    llm: &L,
+    src_dir: &Path,
+    out_dir: &Path,
+    lang: &str,
+    opts: &TranspileOptions,
+) -> Result<TranspileReport, TranspileError> {
+    validate_project_dirs(fs, src_dir, out_dir, opts.dry_run)?;
+    info!("\n--- PROJECT TRANSPILER START ---");
+    info!("fidelity: {}", opts.fidelity.describe());
+    if opts.explicit_types {
+        info!("explicit types: on (unknown types -> {})", TYPE_PLACEHOLDER);
+    }
+
+    // 출력 디렉토리를 만들면서 작업 목록만 모음 (변환은 나중에)
+    // visited: follow_symlinks 일 때 이미 들어간 디렉토리의 실제 경로 (순환 symlink 차단)
+    fn walk<L: LLM>(ctx: &mut WalkCtx<L>, src: &Path, out: &Path, jobs: &mut Vec<Job>, visited: &mut HashSet<PathBuf>) {
+        // 읽을 수 없는 디렉토리 하나 때문에 전체를 멈추지 않음
+        let entries = match ctx.fs.read_dir(src) {
+            Ok(entries) => entries,
+            Err(e) => return ctx.fail(TranspileError::Io(src.to_path_buf(), e)),
+        };
+        // merge 대상은 모아뒀다가 디렉토리 끝에서 한 번에
+        let mut group = Vec::new();
+        for path in entries {
+            let Ok(meta) = ctx.fs.metadata(&path) else { continue };
+
+            if ctx.gitignored(&path, meta.is_dir) {
+                ctx.skip("GITIGNORE SKIP", &path, meta.is_file as usize);
+                continue;
+            }
+            // 사용자가 직접 뺀 것이므로 기본 로그에도 보임
+            if ctx.transpileignored(&path, meta.is_dir) {
+                ctx.skip_at(log::Level::Info, "TRANSPILEIGNORE", &path, meta.is_file as usize);
+                continue;
+            }
+
+            if meta.is_dir {
+                if should_skip_dir(&path) || ctx.excluded_dir(&path) {
+                    debug!("[SKIP] directory: {}", path.display());
+                    continue;
+                }
+                // 조상을 가리키는 링크는 끝없이 재귀하므로 기본은 따라가지 않음
+                if meta.is_symlink && !ctx.opts.follow_symlinks {
+                    ctx.skip_at(log::Level::Info, "SKIP SYMLINK", &path, 0);
+                    continue;
+                }
+                if ctx.opts.follow_symlinks {
+                    match ctx.fs.canonicalize(&path) {
+                        Ok(real) if !visited.insert(real.clone()) => {
+                            info!("[SKIP SYMLINK] {} (already visited as {})", path.display(), real.display());
+                            continue;
+                        }
+                        Ok(_) => {}
+                        Err(e) => {
+                            ctx.fail(TranspileError::Io(path, e));
+                            continue;
+                        }
+                    }
+                }
+
+                let next = out.join(path.file_name().unwrap());
+                if ctx.opts.dry_run {
+                    walk(ctx, &path, &next, jobs, visited);
+                    continue;
+                }
+                if let Err(e) = ctx.fs.create_dir_all(&next) {
+                    ctx.fail(TranspileError::Io(next, e));
+                    continue;
+                }
+                walk(ctx, &path, &next, jobs, visited);
+            } else if meta.is_file {
+                let merge = ctx.opts.merge.as_ref().is_some_and(|m| {
+                    m.includes(&path) && ctx.selected(&path)
+                });
+                if merge {
+                    group.push(path);
+                } else {
+                    jobs.push(Job::File { path, out: out.to_path_buf() });
+                }
+            }
+        }
+
+        match group.len() {
+            0 => {}
+            1 => jobs.push(Job::File { path: group.remove(0), out: out.to_path_buf() }),
+            _ => jobs.push(Job::Group { files: group, dir: src.to_path_buf(), out: out.to_path_buf() }),
+        }
+    }
+
+    let usage_before = llm.provider_stats();
+    let mut ctx = WalkCtx::new(fs, llm, lang, src_dir, out_dir, opts);
+    ctx.load_ignore_files(src_dir);
+    ctx.load_manifest();
+    let mut jobs = Vec::new();
+    let mut visited = HashSet::new();
+    if opts.follow_symlinks {
+        visited.extend(fs.canonicalize(src_dir).ok());
+    }
+    walk(&mut ctx, src_dir, out_dir, &mut jobs, &mut visited);
+
+    // error 모드는 하나라도 겹치면 아무것도 쓰기 전에 멈춤
+    if opts.on_conflict == ConflictPolicy::Error {
+        let conflicts: Vec<PathBuf> = jobs
+            .iter()
+            .filter(|j| j.counted(&ctx))
+            .filter_map(|j| {
+                let out_path = j.output_path(lang);
+                let tracked = match j {
+                    Job::File { path, .. } => ctx.tracked(path, &out_path).is_some(),
+                    Job::Group { .. } => false,
+                };
+                (fs.exists(&out_path) && !tracked && !ctx.failed_before(&out_path)).then_some(out_path)
+            })
+            .collect();
+        if !conflicts.is_empty() {
+            return Err(TranspileError::OutputExists(conflicts));
+        }
+    }
+
+    let progress = Some(jobs.iter().filter(|j| j.counted(&ctx)).count())
+        .filter(|&n| opts.progress && !opts.dry_run && n > 0)
+        .map(Progress::new);
+    let workers = plan_workers(jobs.len(), opts);
+    if workers <= 1 {
+        if effective_jobs(opts) > 1 {
+            debug!("[SEQUENTIAL] {} jobs is below the parallel threshold", jobs.len());
+        }
+        for job in jobs {
+            run_counted(&mut ctx, job, progress.as_ref());
+        }
+    } else {
+        info!("[PARALLEL] {} jobs across {} workers", jobs.len(), workers);
+        let (tx, rx) = std::sync::mpsc::sync_channel::<Job>(workers * 2);
+        let rx = Mutex::new(rx);
+        let results: Vec<(TranspileReport, Vec<String>)> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..workers)
+                .map(|_| {
+                    let (base, rx, progress) = (&ctx, &rx, progress.as_ref());
+                    scope.spawn(move || {
+                        let mut w = base.worker();
+                        loop {
+                            let next = rx.lock().map(|r| r.recv());
+                            let Ok(Ok(job)) = next else { break };
+                            run_counted(&mut w, job, progress);
+                        }
+                        (w.report, w.finetune)
+                    })
+                })
+                .collect();
+            for job in jobs {
+                if tx.send(job).is_err() {
+                    break;
+                }
+            }
+            drop(tx);
+            handles.into_iter().map(|h| h.join().expect("transpile worker panicked")).collect()
+        });
+        for (report, finetune) in results {
+            ctx.report.absorb(report);
+            ctx.finetune.extend(finetune);
+        }
+        // worker 순서와 관계없이 같은 report
+        ctx.report.files.sort_by(|a, b| a.source.cmp(&b.source));
+        ctx.report.planned.sort();
+    }
+    if let Some(p) = &progress {
+        p.finish();
+    }
+
+    ctx.report.per_provider = provider_stats_since(&llm.provider_stats(), &usage_before);
+    ctx.report.pending.sort();
+    if ctx.quota_exhausted.load(Ordering::SeqCst) {
+        ctx.report.status = RunStatus::QuotaExhausted;
+        error!(
+            "[QUOTA] stopped after {} files, {} pending; rerun once the quota resets (finished outputs are kept)",
+            ctx.report.files.len(),
+            ctx.report.pending.len()
+        );
+    } else if ctx.cancelled() {
+        ctx.report.status = RunStatus::Cancelled;
+        warn!("[CANCELLED] stopped after {} files", ctx.report.files.len());
+    }
+
+    if let Some(dataset) = opts.finetune_export.as_ref().filter(|_| !opts.dry_run) {
+        let mut body = ctx.finetune.join("\n");
+        if !body.is_empty() {
+            body.push('\n');
+        }
+        match fs.write(dataset, body.as_bytes()) {
+            Ok(()) => {
+                info!("[FINETUNE] {} examples -> {}", ctx.finetune.len(), dataset.display());
+                ctx.report.finetune_examples = ctx.finetune.len();
+            }
+            Err(e) => error!("[FINETUNE] failed to write {}: {}", dataset.display(), e),
+        }
+    }
+    if opts.dry_run {
+        info!(
+            "--- PROJECT TRANSPILER DRY RUN --- would convert {}, would skip {}",
+            ctx.report.planned.len(),
+            ctx.report.skipped
+        );
+        if let Some(info) = &opts.estimate {
+            let tokens: usize = ctx.report.estimated.iter().map(|(_, n)| n).sum();
+            info!(
+                "[ESTIMATE] total ~{} input tokens (+~{} completion) across {} units, projected cost ~${:.4} \
+                 (${}/${} per 1K prompt/completion tokens)",
+                tokens,
+                tokens,
+                ctx.report.estimated.len(),
+                estimate_cost(info, tokens),
+                info.input_per_1k,
+                info.output_per_1k
+            );
+        }
+        return Ok(ctx.report);
+    }
+    ctx.save_manifest();
+    info!(
+        "--- PROJECT TRANSPILER DONE --- converted {}, skipped {}, failed {}",
+        ctx.report.converted, ctx.report.skipped, ctx.report.failed
+    );
+    // provider 가 usage 를 돌려준 경우만 (mock / 캐시만 쓴 run 은 없음)
+    let usage = &ctx.report.per_provider;
+    if !usage.is_empty() {
+        info!(
+            "[USAGE] {} files, {} prompt + {} completion tokens, ~${:.4}",
+            ctx.report.files.len(),
+            usage.values().map(|s| s.prompt_tokens).sum::<u64>(),
+            usage.values().map(|s| s.completion_tokens).sum::<u64>(),
+            usage_cost(usage)
+        );
+    }
+    Ok(ctx.report)
+}
+
+// ==========================================================
+// WATCH MODE (저장할 때마다 해당 파일만 다시 변환)
+// ==========================================================
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(500);
+
+// src 기준 상대 경로가 skip 디렉토리 안에 있는지
+fn in_skipped_dir(rel: &Path) -> bool {
+    rel.parent()
+        .map(|p| p.ancestors().any(|a| !a.as_os_str().is_empty() && should_skip_dir(a)))
+        .unwrap_or(false)
+}
+
+pub fn watch_project<L: LLM>(
+    llm: &L,
+    src_dir: &Path,
+    out_dir: &Path,
+    lang: &str,
+    opts: &TranspileOptions,
+) -> Result<(), TranspileError> {
+    use notify::{EventKind, RecursiveMode, Watcher};
+
+    validate_project_dirs(&RealFs, src_dir, out_dir, false)?;
+    let src_root = fs::canonicalize(src_dir).map_err(|e| TranspileError::Watch(e.to_string()))?;
+
+    let (tx, rx) = std::sync::mpsc::channel::<notify::Result<notify::Event>>();
+    let mut watcher = notify::recommended_watcher(tx).map_err(|e| TranspileError::Watch(e.to_string()))?;
+    watcher
+        .watch(&src_root, RecursiveMode::Recursive)
+        .map_err(|e| TranspileError::Watch(e.to_string()))?;
+
+    // 저장 시마다 새 결과가 필요하므로 항상 덮어쓰기
+    let mut opts = opts.clone();
+    opts.on_conflict = ConflictPolicy::Overwrite;
+    // 저장된 파일 하나만 다시 변환하므로 묶음 변환은 적용하지 않음
+    if opts.merge.take().is_some() {
+        warn!("[WATCH] --merge is ignored in watch mode");
+    }
+    let mut ctx = WalkCtx::new(&RealFs, llm, lang, &src_root, out_dir, &opts);
+    ctx.load_ignore_files(&src_root);
+    // 저장할 때마다 기록해 두면 watch 를 끈 뒤 프로젝트 변환이 바뀐 파일만 다시 변환
+    ctx.load_manifest();
+
+    info!("[WATCH] {} -> {} (Ctrl-C to stop)", src_dir.display(), out_dir.display());
+
+    let collect = |ev: notify::Result<notify::Event>, changed: &mut Vec<PathBuf>| {
+        if let Ok(ev) = ev {
+            if matches!(ev.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+                for p in ev.paths {
+                    if !changed.contains(&p) {
+                        changed.push(p);
+                    }
+                }
+            }
+        }
+    };
+
+    loop {
+        // 취소 확인을 위해 주기적으로 깨어남
+        let first = match rx.recv_timeout(WATCH_DEBOUNCE) {
+            Ok(ev) => ev,
+            Err(std::sync::mpsc::RecvTimeoutError::Timeout) if !ctx.cancelled() => continue,
+            Err(_) => break,
+        };
+        let mut changed = Vec::new();
+        collect(first, &mut changed);
+        // 에디터가 연속으로 여러 이벤트를 내므로 잠잠해질 때까지 모음
+        while let Ok(ev) = rx.recv_timeout(WATCH_DEBOUNCE) {
+            collect(ev, &mut changed);
+        }
+
+        for path in changed {
+            if ctx.cancelled() {
+                break;
+            }
+            // foo.py.transpile 이 바뀌면 foo.py 를 다시 변환
+            let path = if path.extension().is_some_and(|e| e == "transpile") {
+                path.with_extension("")
+            } else {
+                path
+            };
+            if !path.is_file() {
+                continue;
+            }
+            let Ok(rel) = path.strip_prefix(&src_root) else { continue };
+            if in_skipped_dir(rel) {
+                continue;
+            }
+            if ctx.gitignored(&path, false) {
+                debug!("[GITIGNORE SKIP] {}", path.display());
+                continue;
+            }
+            if ctx.transpileignored(&path, false) {
+                info!("[TRANSPILEIGNORE] {}", path.display());
+                continue;
+            }
+
+            let out = match rel.parent() {
+                Some(parent) => out_dir.join(parent),
+                None => out_dir.to_path_buf(),
+            };
+            if let Err(e) = ctx.fs.create_dir_all(&out) {
+                ctx.fail(TranspileError::Io(out, e));
+                continue;
+            }
+            convert_file(&mut ctx, &path, &out);
+        }
+        ctx.save_manifest();
+    }
+    Ok(())
+}
+
+// ==========================================================
+// OUTPUT NORMALIZATION
+// ==========================================================
+
+// CRLF / CR -> LF
+pub fn normalize_line_endings(s: &str) -> String {
+    s.replace("\r\n", "\n").replace('\r', "\n")
+}
+
+// 비교 전용: 공백 덩어리를 하나로, 줄 앞뒤 공백과 빈 줄 제거 (포맷 차이만 있는 두 코드는 같아짐)
+pub fn collapse_whitespace(s: &str) -> String {
+    normalize_line_endings(s)
+        .lines()
+        .map(|l| l.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|l| !l.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+pub fn is_whitespace_only_change(old: &str, new: &str) -> bool {
+    old != new && collapse_whitespace(old) == collapse_whitespace(new)
+}
+
+// 출력 파일용: 줄 끝과 줄 뒤 공백만 정리. 들여쓰기(앞 공백)는 절대 건드리지 않음
+// (Python / YAML 처럼 들여쓰기가 의미를 가지는 언어 보호)
+pub fn normalize_output(code: &str) -> String {
+    let mut out: String = normalize_line_endings(code)
+        .lines()
+        .map(|l| l.trim_end())
+        .collect::<Vec<_>>()
+        .join("\n");
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    out
+}
+
+// 들여쓰기에 탭과 공백이 섞인 줄 번호 (한 줄 안에서 섞였거나, 파일 안에서 방식이 섞인 경우)
+pub fn indentation_issues(code: &str) -> Vec<usize> {
+    let mut issues = Vec::new();
+    let mut first_style: Option<char> = None;
+
+    for (i, line) in code.lines().enumerate() {
+        let indent: String = line.chars().take_while(|c| *c == ' ' || *c == '\t').collect();
+        if indent.is_empty() || indent.len() == line.len() {
+            continue;
+        }
+        let has_tab = indent.contains('\t');
+        let has_space = indent.contains(' ');
+        if has_tab && has_space {
+            issues.push(i + 1);
+            continue;
+        }
+        let style = if has_tab { '\t' } else { ' ' };
+        match first_style {
+            None => first_style = Some(style),
+            Some(s) if s != style => issues.push(i + 1),
+            _ => {}
+        }
+    }
+    issues
+}
+
+// 들여쓰기가 문법인 대상 언어
+fn is_indentation_sensitive(lang: &str) -> bool {
+    matches!(lang, "python" | "py" | "yaml" | "yml")
+}
+
+// ==========================================================
+// OUTPUT ENCODING (레거시 대상 시스템용)
+// ==========================================================
+
+// "shift_jis", "latin1" 같은 WHATWG label. UTF-8 이면 None (기본 경로)
+pub fn parse_output_encoding(label: &str) -> Result<Option<&'static encoding_rs::Encoding>, String> {
+    let enc = encoding_rs::Encoding::for_label(label.trim().as_bytes())
+        .ok_or_else(|| format!("unknown output encoding '{}'", label))?;
+    if enc == encoding_rs::UTF_8 {
+        return Ok(None);
+    }
+    // UTF-16 계열은 encoding_rs 가 인코딩을 지원하지 않음 (UTF-8 로 대체됨)
+    if enc.output_encoding() != enc {
+        return Err(format!("output encoding '{}' is not supported for writing", enc.name()));
+    }
+    Ok(Some(enc))
+}
+
+// 출력 코드를 지정한 인코딩 바이트로. 표현할 수 없는 문자가 있으면 첫 위치와 함께 실패
+pub fn encode_output<'a>(
+    code: &'a str,
+    enc: Option<&'static encoding_rs::Encoding>,
+) -> Result<std::borrow::Cow<'a, [u8]>, String> {
+    let Some(enc) = enc else {
+        return Ok(code.as_bytes().into());
+    };
+    let (bytes, _, had_errors) = enc.encode(code);
+    if !had_errors {
+        return Ok(bytes);
+    }
+
+    for (lineno, line) in code.lines().enumerate() {
+        for (col, ch) in line.chars().enumerate() {
+            if enc.encode(ch.encode_utf8(&mut [0; 4])).2 {
+                return Err(format!(
+                    "character {:?} (U+{:04X}) at line {}, column {} cannot be represented in {}",
+                    ch,
+                    ch as u32,
+                    lineno + 1,
+                    col + 1,
+                    enc.name()
+                ));
+            }
+        }
+    }
+    Err(format!("output cannot be represented in {}", enc.name()))
+}
+
+// ==========================================================
+// GOLDEN OUTPUT CHECK (회귀 테스트용)
+// ==========================================================
+#[derive(Debug)]
+pub struct GoldenDiff {
+    pub path: PathBuf,
+    pub diff: String,
+    /// 공백/포맷만 다름
+    pub whitespace_only: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct GoldenReport {
+    pub matched: Vec<PathBuf>,
+    pub changed: Vec<GoldenDiff>,
+    /// --ignore-whitespace 로 통과 처리된 공백 전용 차이
+    pub whitespace_only: Vec<PathBuf>,
+    /// golden 에는 있는데 출력에 없는 파일
+    pub missing: Vec<PathBuf>,
+    /// 출력에는 있는데 golden 에 없는 파일
+    pub added: Vec<PathBuf>,
+}
+
+impl GoldenReport {
+    pub fn is_ok(&self) -> bool {
+        self.changed.is_empty() && self.missing.is_empty() && self.added.is_empty()
+    }
+
+    pub fn print(&self) {
+        for d in &self.changed {
+            let kind = if d.whitespace_only { " (whitespace only)" } else { "" };
+            println!("[GOLDEN DIFF{}] {}\n{}", kind, d.path.display(), d.diff);
+        }
+        for rel in &self.whitespace_only {
+            println!("[GOLDEN WHITESPACE] {}", rel.display());
+        }
+        for rel in &self.missing {
+            println!("[GOLDEN MISSING] {}", rel.display());
+        }
+        for rel in &self.added {
+            println!("[GOLDEN NEW] {}", rel.display());
+        }
+        let substantive = self.changed.iter().filter(|d| !d.whitespace_only).count();
+        println!(
+            "golden: {} matched, {} changed ({} substantive), {} whitespace-only ignored, {} missing, {} new",
+            self.matched.len(),
+            self.changed.len(),
+            substantive,
+            self.whitespace_only.len(),
+            self.missing.len(),
+            self.added.len()
+        );
+    }
+}
+
+// 디렉토리 아래 모든 파일의 상대 경로 (정렬). 변환 기록인 manifest 는 결과물이 아니므로 제외
+fn relative_files(root: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = walkdir::WalkDir::new(root)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .filter_map(|e| e.path().strip_prefix(root).ok().map(|p| p.to_path_buf()))
+        .filter(|p| p != Path::new(MANIFEST_FILE))
+        .collect();
+    files.sort();
+    files
+}
+
+pub fn unified_diff(old: &str, new: &str, old_name: &str, new_name: &str) -> String {
+    similar::TextDiff::from_lines(old, new)
+        .unified_diff()
+        .header(old_name, new_name)
+        .to_string()
+}
+
+// unified diff 에 ANSI 색 (추가 초록, 삭제 빨강, hunk 머리 cyan)
+pub fn colorize_diff(diff: &str) -> String {
+    diff.lines()
+        .map(|line| {
+            let code = if line.starts_with("+++") || line.starts_with("---") {
+                "1"
+            } else if line.starts_with('+') {
+                "32"
+            } else if line.starts_with('-') {
+                "31"
+            } else if line.starts_with("@@") {
+                "36"
+            } else {
+                return format!("{}\n", line);
+            };
+            format!("\x1b[{}m{}\x1b[0m\n", code, line)
+        })
+        .collect()
+}
+
+pub fn check_golden(out_dir: &Path, golden_dir: &Path, ignore_whitespace: bool) -> GoldenReport {
+    let mut report = GoldenReport::default();
+    let outputs = relative_files(out_dir);
+    let goldens = relative_files(golden_dir);
+
+    for rel in &outputs {
+        if !goldens.contains(rel) {
+            report.added.push(rel.clone());
+            continue;
+        }
+        let actual = fs::read_to_string(out_dir.join(rel)).unwrap_or_default();
+        let expected = fs::read_to_string(golden_dir.join(rel)).unwrap_or_default();
+        if actual == expected {
+            report.matched.push(rel.clone());
+            continue;
+        }
+
+        let whitespace_only = is_whitespace_only_change(&expected, &actual);
+        if whitespace_only && ignore_whitespace {
+            report.whitespace_only.push(rel.clone());
+        } else {
+            let name = rel.display().to_string();
+            let diff = unified_diff(&expected, &actual, &format!("golden/{}", name), &format!("output/{}", name));
+            report.changed.push(GoldenDiff { path: rel.clone(), diff, whitespace_only });
+        }
+    }
+
+    report.missing = goldens.into_iter().filter(|g| !outputs.contains(g)).collect();
+    report
+}
+
+// golden 디렉토리를 현재 출력으로 교체 (출력에 없는 golden 파일은 삭제)
+pub fn accept_golden(out_dir: &Path, golden_dir: &Path) -> io::Result<usize> {
+    let outputs = relative_files(out_dir);
+    for stale in relative_files(golden_dir) {
+        if !outputs.contains(&stale) {
+            fs::remove_file(golden_dir.join(&stale))?;
+        }
+    }
+    for rel in &outputs {
+        let dest = golden_dir.join(rel);
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(out_dir.join(rel), dest)?;
+    }
+    Ok(outputs.len())
+}
+
+// ==========================================================
+// SMOKE BUILD (출력 트리 전체를 대상 toolchain 으로 빌드)
+// ==========================================================
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SmokeResult {
+    /// toolchain 이 없거나 지원하지 않는 대상
+    Skipped(String),
+    Passed { command: String },
+    Failed { command: String, output: String },
+    TimedOut { command: String, after: Duration },
+}
+
+impl SmokeResult {
+    pub fn is_failure(&self) -> bool {
+        matches!(self, SmokeResult::Failed { .. } | SmokeResult::TimedOut { .. })
+    }
+}
+
+impl fmt::Display for SmokeResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SmokeResult::Skipped(why) => write!(f, "skipped ({})", why),
+            SmokeResult::Passed { command } => write!(f, "passed: {}", command),
+            SmokeResult::Failed { command, output } => write!(f, "failed: {}\n{}", command, output.trim_end()),
+            SmokeResult::TimedOut { command, after } => write!(f, "timed out after {:?}: {}", after, command),
+        }
+    }
+}
+
+// 명령 목록을 순서대로 실행. 하나라도 실패하면 거기서 멈춤
+fn run_smoke_steps(dir: &Path, steps: &[Vec<String>], deadline: Instant, limit: Duration) -> SmokeResult {
+    use std::process::{Command, Stdio};
+
+    let mut last = String::new();
+    for step in steps {
+        let command = step.join(" ");
+        let mut child = match Command::new(&step[0])
+            .args(&step[1..])
+            .current_dir(dir)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+        {
+            Ok(c) => c,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return SmokeResult::Skipped(format!("toolchain '{}' not found", step[0]));
+            }
+            Err(e) => return SmokeResult::Failed { command, output: e.to_string() },
+        };
+
+        loop {
+            match child.try_wait() {
+                Ok(Some(_)) => break,
+                Ok(None) if Instant::now() >= deadline => {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return SmokeResult::TimedOut { command, after: limit };
+                }
+                Ok(None) => std::thread::sleep(Duration::from_millis(100)),
+                Err(e) => return SmokeResult::Failed { command, output: e.to_string() },
+            }
+        }
+        let out = match child.wait_with_output() {
+            Ok(o) => o,
+            Err(e) => return SmokeResult::Failed { command, output: e.to_string() },
+        };
+        if !out.status.success() {
+            let mut output = String::from_utf8_lossy(&out.stdout).into_owned();
+            output.push_str(&String::from_utf8_lossy(&out.stderr));
+            return SmokeResult::Failed { command, output };
+        }
+        last = command;
+    }
+    SmokeResult::Passed { command: last }
+}
+
+// 임시 작업 공간에 출력 트리를 복사해서 빌드 (출력 트리에는 빌드 산출물을 남기지 않음)
+pub fn smoke_compile(out_dir: &Path, lang: &str, limit: Duration) -> SmokeResult {
+    let files = relative_files(out_dir);
+    let has = |name: &str| files.iter().any(|f| f == Path::new(name));
+    let with_ext = |ext: &str| -> Vec<String> {
+        files
+            .iter()
+            .filter(|f| f.extension().is_some_and(|e| e == ext))
+            .map(|f| f.to_string_lossy().into_owned())
+            .collect()
+    };
+    let argv = |args: &[&str]| args.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+
+    let steps: Vec<Vec<String>> = match lang {
+        "go" => vec![argv(&["go", "build", "./..."])],
+        "rust" if has("Cargo.toml") => vec![argv(&["cargo", "build", "--quiet"])],
+        "rust" => return SmokeResult::Skipped("no Cargo.toml in output tree".into()),
+        "cpp" if has("CMakeLists.txt") => vec![
+            argv(&["cmake", "-S", ".", "-B", "build"]),
+            argv(&["cmake", "--build", "build"]),
+        ],
+        "cpp" => {
+            let sources = with_ext("cpp");
+            if sources.is_empty() {
+                return SmokeResult::Skipped("no .cpp files in output tree".into());
+            }
+            let mut cmd = argv(&["c++", "-fsyntax-only", "-std=c++17", "-I."]);
+            cmd.extend(sources);
+            vec![cmd]
+        }
+        other => return SmokeResult::Skipped(format!("no smoke build for target '{}'", other)),
+    };
+
+    let workspace = env::temp_dir().join(format!("ic-smoke-{}-{}", std::process::id(), utc_timestamp()));
+    let copied = files.iter().try_for_each(|rel| {
+        let dest = workspace.join(rel);
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(out_dir.join(rel), dest).map(|_| ())
+    });
+    if let Err(e) = copied {
+        let _ = fs::remove_dir_all(&workspace);
+        return SmokeResult::Skipped(format!("cannot prepare workspace: {}", e));
+    }
+    // go build ./... 는 module 이 필요함
+    if lang == "go" && !has("go.mod") {
+        let _ = fs::write(workspace.join("go.mod"), "module smoke\n\ngo 1.21\n");
+    }
+
+    info!("[SMOKE] building {} in {}", out_dir.display(), workspace.display());
+    let result = run_smoke_steps(&workspace, &steps, Instant::now() + limit, limit);
+    let _ = fs::remove_dir_all(&workspace);
+    result
+}
+
+// ==========================================================
+// OUTPUT VALIDATION (출력 파일 하나를 대상 컴파일러로 검사)
+// ==========================================================
+pub const VALIDATE_TIMEOUT: Duration = Duration::from_secs(60);
+
+// worker 끼리 작업 디렉토리가 겹치지 않도록
+static VALIDATE_SEQ: AtomicU64 = AtomicU64::new(0);
+
+// 대상별 (파일 이름, 검사 명령). 새 대상은 여기에 추가 (gofmt -e, clang -fsyntax-only 등)
+fn validator_steps(lang: &str, code: &str) -> Option<(&'static str, Vec<Vec<String>>)> {
+    let argv = |args: &[&str]| args.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+    match lang {
+        "rust" => {
+            // main 이 없는 조각은 bin 으로 검사하면 E0601 로 실패함
+            let crate_type = if Regex::new(r"(?m)^\s*(pub\s+)?fn\s+main\s*\(").unwrap().is_match(code) {
+                "bin"
+            } else {
+                "lib"
+            };
+            let cmd = ["rustc", "--edition", "2021", "--crate-type", crate_type, "--emit=metadata", "-o", "out.rmeta", "main.rs"];
+            Some(("main.rs", vec![argv(&cmd)]))
+        }
+        _ => None,
+    }
+}
+
+pub fn validate_output(code: &str, lang: &str) -> SmokeResult {
+    let Some((file, steps)) = validator_steps(lang, code) else {
+        return SmokeResult::Skipped(format!("no validator for target '{}'", lang));
+    };
+    let workspace = env::temp_dir().join(format!(
+        "ic-validate-{}-{}",
+        std::process::id(),
+        VALIDATE_SEQ.fetch_add(1, Ordering::Relaxed)
+    ));
+    if let Err(e) = fs::create_dir_all(&workspace).and_then(|_| fs::write(workspace.join(file), code)) {
+        let _ = fs::remove_dir_all(&workspace);
+        return SmokeResult::Skipped(format!("cannot prepare workspace: {}", e));
+    }
+    let result = run_smoke_steps(&workspace, &steps, Instant::now() + VALIDATE_TIMEOUT, VALIDATE_TIMEOUT);
+    let _ = fs::remove_dir_all(&workspace);
+    result
+}
+
+// ==========================================================
+// SEMANTIC NODE CACHE (alpha-renaming)
+// ==========================================================
+// 식별자 이름만 다른 노드는 같은 결과를 공유: 이름을 __ic_v0, __ic_v1 ... 로 바꿔서 키를 만들고
+// 결과 문자열에서 다시 원래 이름으로 되돌림
+
+const ALPHA_PREFIX: &str = "__ic_v";
+
+// (정규화된 노드, 등장 순서대로의 원래 이름)
+pub fn alpha_normalize(node: &Node) -> (Node, Vec<String>) {
+    fn rename(name: &str, names: &mut Vec<String>) -> String {
+        let idx = match names.iter().position(|n| n == name) {
+            Some(i) => i,
+            None => {
+                names.push(name.to_string());
+                names.len() - 1
+            }
+        };
+        format!("{}{}", ALPHA_PREFIX, idx)
+    }
+
+    fn go(node: &Node, names: &mut Vec<String>) -> Node {
+        let kind = match &node.kind {
+            NodeKind::Identifier(x) => NodeKind::Identifier(rename(x, names)),
+            NodeKind::Integer(n) => NodeKind::Integer(*n),
+            NodeKind::Number(n) => NodeKind::Number(*n),
+            NodeKind::BinaryOp { op, left, right } => NodeKind::BinaryOp {
+                op: op.clone(),
+                left: Box::new(go(left, names)),
+                right: Box::new(go(right, names)),
+            },
+            NodeKind::Function { name, args, body } => NodeKind::Function {
+                name: rename(name, names),
+                args: args.iter().map(|a| rename(a, names)).collect(),
+                body: body.iter().map(|b| go(b, names)).collect(),
+            },
+            NodeKind::Unknown => NodeKind::Unknown,
+        };
+        Node { kind, meta: node.meta.clone() }
+    }
+
+    let mut names = Vec::new();
+    let normalized = go(node, &mut names);
+    (normalized, names)
+}
+
+// placeholder 를 원래 이름으로 (뒤 번호부터 바꿔야 __ic_v1 이 __ic_v10 을 먹지 않음)
+pub fn dealias(text: &str, names: &[String]) -> String {
+    let mut out = text.to_string();
+    for (i, name) in names.iter().enumerate().rev() {
+        out = out.replace(&format!("{}{}", ALPHA_PREFIX, i), name);
+    }
+    out
+}
+
+fn cache_key(normalized: &Node, lang: &str, ver: &str) -> String {
+    let mut meta: Vec<_> = normalized.meta.iter().collect();
+    meta.sort();
+    format!("{}|{}|{:?}|{:?}", lang, ver, normalized.kind, meta)
+}
+
+#[derive(Debug, Clone)]
+struct CachedCompile {
+    refined: String,
+    security: Vec<SecurityFinding>,
+}
+
+#[derive(Debug, Default)]
+pub struct SemanticCache {
+    entries: RefCell<HashMap<String, CachedCompile>>,
+    hits: Cell<usize>,
+    misses: Cell<usize>,
+}
+
+impl SemanticCache {
+    pub fn hits(&self) -> usize {
+        self.hits.get()
+    }
+
+    pub fn misses(&self) -> usize {
+        self.misses.get()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.borrow().is_empty()
+    }
+}
+
+// ==========================================================
+// REFINE IMPACT (base vs refined)
+// ==========================================================
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RefineDelta {
+    pub base_lines: usize,
+    pub refined_lines: usize,
+    /// 추가 + 삭제된 줄 수
+    pub lines_changed: usize,
+    /// refined 바이트 수 - base 바이트 수
+    pub size_delta: i64,
+}
+
+impl RefineDelta {
+    pub fn measure(base: &str, refined: &str) -> Self {
+        let diff = similar::TextDiff::from_lines(base, refined);
+        let lines_changed = diff
+            .iter_all_changes()
+            .filter(|c| c.tag() != similar::ChangeTag::Equal)
+            .count();
+        Self {
+            base_lines: base.lines().count(),
+            refined_lines: refined.lines().count(),
+            lines_changed,
+            size_delta: refined.len() as i64 - base.len() as i64,
+        }
+    }
+
+    // base 한 줄당 바뀐 줄 수
+    pub fn ratio(&self) -> f64 {
+        self.lines_changed as f64 / self.base_lines.max(1) as f64
+    }
+}
+
+// 단순한 노드(base 줄 수가 이 이하)에서 ratio 가 HEAVY_REFINE_RATIO 를 넘으면 과한 재작성으로 봄
+const SIMPLE_NODE_LINES: usize = 3;
+const HEAVY_REFINE_RATIO: f64 = 3.0;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RefineStats {
+    pub nodes: usize,
+    pub lines_changed: usize,
+    pub size_delta: i64,
+    /// refine 결과가 base 와 같았던 노드 수 (refine 생략 후보)
+    pub unchanged: usize,
+    /// 단순한 노드를 크게 다시 쓴 횟수
+    pub heavy: usize,
+}
+
+impl RefineStats {
+    pub fn record(&mut self, d: &RefineDelta) {
+        self.nodes += 1;
+        self.lines_changed += d.lines_changed;
+        self.size_delta += d.size_delta;
+        if d.lines_changed == 0 {
+            self.unchanged += 1;
+        }
+        if d.base_lines <= SIMPLE_NODE_LINES && d.ratio() > HEAVY_REFINE_RATIO {
+            self.heavy += 1;
+        }
+    }
+
+    pub fn print(&self) {
+        if self.nodes == 0 {
+            return;
+        }
+        println!(
+            "refine: {} nodes, {} lines changed (avg {:.1}), size {:+} bytes, {} unchanged, {} heavy rewrites",
+            self.nodes,
+            self.lines_changed,
+            self.lines_changed as f64 / self.nodes as f64,
+            self.size_delta,
+            self.unchanged,
+            self.heavy
+        );
+    }
+}
+
+// ==========================================================
+// FULL INTELLIGENT COMPILER
+// ==========================================================
+// compile_node 결과. Display 는 사람이 읽는 형식, Serialize 는 --format json
+#[derive(Debug, Clone, Serialize)]
+pub struct CompileResult {
+    pub language: String,
+    pub version: String,
+    pub meaning: String,
+    pub base_code: String,
+    pub refined_code: String,
+    /// 심각도 높은 순
+    pub security_findings: Vec<SecurityFinding>,
+    /// offline 이라 LLM 단계 (refine / LLM 보안 검사) 를 건너뜀. refined_code 는 base_code 그대로이고
+    /// security_findings 는 오프라인 규칙 결과만
+    pub ai_skipped: bool,
+}
+
+/// base / refined 를 보여주는 방식
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum RefineView {
+    /// 둘 다 전체
+    #[default]
+    Full,
+    /// base 는 전체, refined 는 base 대비 unified diff 만
+    Diff,
+    /// 둘 다 전체 + diff
+    Both,
+}
+
+impl CompileResult {
+    // refine 단계가 base 에서 바꾼 부분
+    pub fn refine_diff(&self) -> String {
+        unified_diff(&self.base_code, &self.refined_code, "base", "refined")
+    }
+
+    // color: 추가 / 삭제 줄 색칠 (터미널 출력일 때만)
+    pub fn render(&self, view: RefineView, color: bool) -> String {
+        let mut out = format!(
+            "=== Intelligent Compiler ===\nLanguage: {}\nVersion: {}\nMeaning: {}\n\nBase:\n{}\n\n",
+            self.language, self.version, self.meaning, self.base_code
+        );
+        if self.ai_skipped {
+            out.push_str("AI Refined:\n(skipped: offline mode)\n\n");
+        } else {
+            if view != RefineView::Diff {
+                out.push_str(&format!("AI Refined:\n{}\n\n", self.refined_code));
+            }
+            if view != RefineView::Full {
+                let diff = self.refine_diff();
+                let diff = match (diff.is_empty(), color) {
+                    (true, _) => "(no changes)\n".to_string(),
+                    (false, true) => colorize_diff(&diff),
+                    (false, false) => diff,
+                };
+                out.push_str(&format!("AI Refined (diff):\n{}\n", diff));
+            }
+        }
+        // offline 이면 LLM 검사 없이 언어별 규칙 결과만
+        out.push_str(if self.ai_skipped { "Security (offline rules only):\n" } else { "Security:\n" });
+        if self.security_findings.is_empty() {
+            out.push_str("(no findings)");
+        } else {
+            let lines: Vec<String> = self.security_findings.iter().map(|s| s.to_string()).collect();
+            out.push_str(&lines.join("\n"));
+        }
+        out
+    }
+}
+
+impl fmt::Display for CompileResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.render(RefineView::Full, false))
+    }
+}
+
+pub struct Compiler<L: LLM + Clone> {
+    pub llm: L,
+    pub version_ai: VersionAI,
+    pub semantic: SemanticEngine,
+    pub security: SecurityAI<L>,
+    pub persona: Option<String>,
+    /// 언어별 대상 버전. 있으면 VersionAI 추론 대신 이 값 사용
+    pub target_versions: HashMap<String, String>,
+    /// Some 이면 alpha-renaming 기준으로 LLM 결과 재사용
+    pub cache: Option<SemanticCache>,
+    /// compile_node 마다 refine 단계가 base 를 얼마나 바꿨는지 누적
+    pub refine_stats: RefCell<RefineStats>,
+    /// true 면 결정적인 단계 (version / semantic / base) 만 실행하고 LLM 은 부르지 않음
+    pub offline: bool,
+}
+
+impl<L: LLM + Clone> Compiler<L> {
+    pub fn new(llm: L) -> Self {
+        Self {
+            version_ai: VersionAI::new(),
+            semantic: SemanticEngine,
+            security: SecurityAI::new(llm.clone()),
+            persona: None,
+            target_versions: HashMap::new(),
+            cache: None,
+            refine_stats: RefCell::new(RefineStats::default()),
+            offline: false,
+            llm
+        }
+    }
+
+    pub fn with_offline(mut self, offline: bool) -> Self {
+        self.security.offline = offline;
+        self.offline = offline;
+        self
+    }
+
+    pub fn with_version_ai(mut self, version_ai: VersionAI) -> Self {
+        self.version_ai = version_ai;
+        self
+    }
+
+    pub fn with_target_versions(mut self, targets: HashMap<String, String>) -> Self {
+        self.target_versions = targets;
+        self
+    }
+
+    pub fn with_semantic_cache(mut self) -> Self {
+        self.cache = Some(SemanticCache::default());
+        self
+    }
+
+    // LLM 이 필요한 refine / security 단계 (캐시가 있으면 정규화된 노드 기준으로 재사용)
+    fn ai_stages(&self, node: &Node, lang: &str, ver: &str, target: Option<&str>) -> (String, Vec<SecurityFinding>) {
+        let refine = |n: &Node| {
+            let base = base_code(n, lang);
+            LLMGenerator {
+                llm: self.llm.clone(),
+                persona: self.persona.clone(),
+                target_version: target.map(str::to_string),
+            }
+            .refine(lang, ver, &base)
+        };
+
+        // 정적 규칙은 사용자가 받는 refine 결과에 적용
+        let Some(cache) = &self.cache else {
+            let refined = refine(node);
+            let security = self.security.analyze(node, &refined, lang);
+            return (refined, security);
+        };
+
+        let (normalized, names) = alpha_normalize(node);
+        let key = cache_key(&normalized, lang, ver);
+        let cached = cache.entries.borrow().get(&key).cloned();
+        let entry = match cached {
+            Some(e) => {
+                cache.hits.set(cache.hits.get() + 1);
+                e
+            }
+            None => {
+                cache.misses.set(cache.misses.get() + 1);
+                let refined = refine(&normalized);
+                let security = self.security.analyze(&normalized, &refined, lang);
+                let e = CachedCompile { refined, security };
+                cache.entries.borrow_mut().insert(key, e.clone());
+                e
+            }
+        };
+
+        (
+            dealias(&entry.refined, &names),
+            entry
+                .security
+                .into_iter()
+                .map(|f| SecurityFinding {
+                    message: dealias(&f.message, &names),
+                    node_summary: dealias(&f.node_summary, &names),
+                    ..f
+                })
+                .collect(),
+        )
+    }
+
+    // refine / security 호출에 공통 페르소나 적용
+    pub fn with_persona(mut self, persona: Option<String>) -> Self {
+        self.security.persona = persona.clone();
+        self.persona = persona;
+        self
+    }
+
+    pub fn compile_node(&self, node: &Node, lang: &str) -> String {
+        self.compile_node_with_version(node, lang, None)
+    }
+
+    pub fn compile_node_with_version(&self, node: &Node, lang: &str, version: Option<&str>) -> String {
+        self.compile_node_result(node, lang, version).to_string()
+    }
+
+    // version 이 있으면 target_versions 보다 우선하고, 둘 다 없을 때만 VersionAI 로 추론
+    pub fn compile_node_result(&self, node: &Node, lang: &str, version: Option<&str>) -> CompileResult {
+        let target = version.or(self.target_versions.get(lang).map(String::as_str));
+        let ver = self.version_ai.resolve(lang, node, target);
+        let sem = self.semantic.analyze(node);
+        let base = base_code(node, lang);
+        if self.offline {
+            return CompileResult {
+                language: lang.to_string(),
+                version: ver,
+                meaning: sem.meaning,
+                // security.offline 도 켜져 있으므로 base 코드에 오프라인 규칙만 돌림
+                security_findings: self.security.analyze(node, &base, lang),
+                refined_code: base.clone(),
+                base_code: base,
+                ai_skipped: true,
+            };
+        }
+        let (refined, sec) = self.ai_stages(node, lang, &ver, target);
+        // API 오류 문자열은 refine 결과가 아니므로 통계에서 제외
+        if !is_error_response(&refined) {
+            let delta = RefineDelta::measure(&base, &refined);
+            if delta.base_lines <= SIMPLE_NODE_LINES && delta.ratio() > HEAVY_REFINE_RATIO {
+                warn!("[REFINE] {} base lines rewritten into {} lines", delta.base_lines, delta.refined_lines);
+            }
+            self.refine_stats.borrow_mut().record(&delta);
+        }
+
+        CompileResult {
+            language: lang.to_string(),
+            version: ver,
+            meaning: sem.meaning,
+            base_code: base,
+            refined_code: refined,
+            // analyze 가 이미 심각도 순으로 정렬
+            security_findings: sec,
+            ai_skipped: false,
+        }
+    }
+}
+
+// ==========================================================
+// CLI
+// ==========================================================
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
+#[derive(Parser, Debug)]
+#[command(name = "intelligent-compiler", version, about = "Intelligent Compiler AI Engine")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// 프로젝트 변환 결과를 golden 디렉토리와 비교하고, 다르면 실패
+    #[arg(long, value_name = "DIR")]
+    check_golden: Option<PathBuf>,
+
+    /// 비교 대신 golden 디렉토리를 현재 결과로 갱신
+    #[arg(long, requires = "check_golden")]
+    accept_golden: bool,
+
+    /// 공백/포맷만 다른 차이는 무시하고 비교
+    #[arg(long)]
+    ignore_whitespace: bool,
+
+    /// 시크릿이 의심되는 파일도 그대로 LLM 에 전송
+    #[arg(long, conflicts_with = "redact_secrets")]
+    allow_secrets: bool,
+
+    /// 시크릿이 의심되는 값을 placeholder 로 바꿔서 전송
+    #[arg(long)]
+    redact_secrets: bool,
+
+    /// 시크릿/이메일/사내 URL 을 placeholder 로 바꿔서 전송
+    #[arg(long)]
+    redact: bool,
+
+    /// 추가 치환 정규식 (여러 번 지정 가능, --redact 포함)
+    #[arg(long, value_name = "REGEX")]
+    redact_pattern: Vec<String>,
+
+    /// 결과 코드의 placeholder 를 원래 값으로 복원
+    #[arg(long)]
+    restore_redacted: bool,
+
+    /// 파일별 모델 라우팅 규칙 (예: "tokens>4000:gpt-4.1", "ext=py:gpt-4o-mini", "*:gpt-4o-mini")
+    #[arg(long, value_name = "RULE")]
+    route: Vec<String>,
+
+    /// 검증을 통과한 변환을 fine-tuning 용 JSONL 로 내보내기
+    #[arg(long, value_name = "FILE")]
+    export_finetune: Option<PathBuf>,
+
+    /// 이번 실행에서 변환할 확장자만 지정 (예: --ext py,go)
+    #[arg(long, value_delimiter = ',', value_name = "EXTS")]
+    ext: Vec<String>,
+
+    /// 이 glob 에 맞는 파일만 변환 (소스 루트 기준, 반복 가능. 예: --include "src/**/*.rs")
+    #[arg(long, value_name = "GLOB")]
+    include: Vec<String>,
+
+    /// 이 glob 에 맞는 파일 / 디렉토리는 제외 (--include 보다 우선. 예: --exclude "**/tests/**")
+    #[arg(long, value_name = "GLOB")]
+    exclude: Vec<String>,
+
+    /// 이미 존재하는 출력 파일도 다시 변환해서 덮어쓰기 (--on-conflict overwrite 와 같음)
+    #[arg(long)]
+    overwrite: bool,
+
+    /// 이전 실행 기록에 없는 출력 파일이 이미 있을 때: overwrite, skip, error (하나라도 있으면 시작하지 않음)
+    #[arg(long, value_name = "MODE", default_value = "skip", value_parser = ConflictPolicy::parse)]
+    on_conflict: ConflictPolicy,
+
+    /// 시작 전에 출력 디렉토리를 비움 (확인 질문. 터미널이 아니면 --yes 필요)
+    #[arg(long)]
+    clean: bool,
+
+    /// 변경 기록(.transpile-manifest.json)을 무시하고 전부 다시 변환
+    #[arg(long)]
+    force: bool,
+
+    /// 변환 후 import 문 보정 (go 는 goimports 가 있으면 사용)
+    #[arg(long)]
+    fix_imports: bool,
+
+    /// 출력 디렉토리 아래 run-<timestamp>/ 로 분리 (run 끼리 덮어쓰지 않음)
+    #[arg(long)]
+    run_dir: bool,
+
+    /// 출력 디렉토리 아래 <lang>/ 로 분리
+    #[arg(long)]
+    per_language: bool,
+
+    /// 대상 버전 고정 (예: --target-version go=1.18, 반복 가능). 언어를 생략하면 --lang 의 언어. 추론 대신 이 버전 사용
+    #[arg(long, value_name = "[LANG=]VER")]
+    target_version: Vec<String>,
+
+    /// 출력 파일 인코딩 (기본 utf-8, 예: shift_jis, latin1)
+    #[arg(long, value_name = "ENCODING")]
+    output_encoding: Option<String>,
+
+    /// 여러 원본을 출력 하나로 묶기: "dir" (디렉토리 단위) 또는 파일 이름 regex
+    #[arg(long, value_name = "dir|REGEX")]
+    merge: Option<String>,
+
+    /// TOKENS 를 넘는 파일은 가운데가 반복적이면 앞/뒤만 전송 (싸지만 가운데 문맥을 잃을 수 있음)
+    #[arg(long, value_name = "TOKENS")]
+    window_tokens: Option<usize>,
+
+    /// 변환 프롬프트 템플릿 파일 (모든 언어 쌍에 같은 템플릿. {lang}, {src_lang}, {content} 치환. 기본은 주석 / docstring 보존을 강조)
+    #[arg(long, value_name = "FILE")]
+    prompt_template: Option<PathBuf>,
+
+    /// 언어 쌍별 프롬프트 템플릿 파일 ([default], [python.rust] 의 template / notes. 기본: 있으면 prompts.toml)
+    #[arg(long, value_name = "FILE", conflicts_with = "prompt_template")]
+    prompts: Option<PathBuf>,
+
+    /// 변환 후 출력 트리 전체를 대상 toolchain 으로 빌드 (go build ./..., cargo build, cmake)
+    #[arg(long)]
+    smoke_build: bool,
+
+    /// smoke build 시간 제한 (초)
+    #[arg(long, default_value_t = 300, value_name = "SECS", requires = "smoke_build")]
+    smoke_timeout: u64,
+
+    /// 출력 파일마다 대상 컴파일러로 검사하고 결과를 요약에 표시 (rust: rustc --emit=metadata)
+    #[arg(long)]
+    validate: bool,
+
+    /// 직역 / 관용적 재작성 정도: literal, balanced, idiomatic
+    #[arg(long, default_value = "balanced", value_parser = Fidelity::parse)]
+    fidelity: Fidelity,
+
+    /// 명시적 타입을 요구하고, 알 수 없는 타입은 any 대신 TodoType 으로 표시
+    #[arg(long)]
+    explicit_types: bool,
+
+    /// 동시에 변환할 파일 수 (0: CPU 수)
+    #[arg(long, short = 'j', default_value_t = 0, value_name = "N")]
+    jobs: usize,
+
+    /// 분당 최대 요청 수 (계정 tier 에 맞춤. 기본: OPENAI_RPM_LIMIT, 없으면 제한 없음)
+    #[arg(long, value_name = "N")]
+    rpm_limit: Option<usize>,
+
+    /// 동시에 진행 중인 LLM 요청 수 상한 (--jobs 보다 작으면 나머지 worker 는 대기)
+    #[arg(long, value_name = "N")]
+    max_concurrency: Option<usize>,
+
+    /// 분당 최대 토큰 수 (기본: OPENAI_TPM_LIMIT, 없으면 제한 없음)
+    #[arg(long, value_name = "N")]
+    tpm_limit: Option<usize>,
+
+    /// 파일이 이 개수 미만이면 --jobs 와 관계없이 순차 처리 (1 이면 항상 병렬)
+    #[arg(long, value_name = "N")]
+    parallel_min_files: Option<usize>,
+
+    /// 소스 루트의 .gitignore 를 무시하고 전부 변환
+    #[arg(long)]
+    no_gitignore: bool,
+
+    /// 디렉토리 symlink 도 따라가서 변환 (순환 링크는 한 번만)
+    #[arg(long)]
+    follow_symlinks: bool,
+
+    /// 이보다 큰 원본 파일은 건너뜀 (bytes, 0: 제한 없음)
+    #[arg(long, value_name = "BYTES", default_value_t = DEFAULT_MAX_FILE_SIZE)]
+    max_file_size: u64,
+
+    /// LLM 호출 / 파일 쓰기 없이 변환 대상과 출력 경로만 출력
+    #[arg(long)]
+    dry_run: bool,
+
+    /// dry run + 파일별 / 전체 예상 토큰과 비용 출력
+    #[arg(long)]
+    estimate: bool,
+
+    /// --estimate 에 쓸 1K 토큰당 가격 (USD, prompt/completion 공통. 기본: 모델 표)
+    #[arg(long, value_name = "USD", requires = "estimate")]
+    price_per_1k: Option<f64>,
+
+    /// 경고 / 오류만 출력 (stderr)
+    #[arg(long, short = 'q', global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// 건너뛴 파일 등 상세 로그 (-vv: trace)
+    #[arg(long, short = 'v', global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// 프로젝트 변환 전 확인 질문 없이 바로 시작 (stdin 이 터미널이 아니면 원래 묻지 않음)
+    #[arg(long, short = 'y', global = true)]
+    yes: bool,
+
+    /// LLM 을 전혀 부르지 않음 (API 키 / 네트워크 불필요). node 는 base 코드만, scan 은 오프라인 규칙만
+    #[arg(long, global = true)]
+    offline: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// src 를 감시하면서 저장된 파일만 다시 변환
+    Watch {
+        src: PathBuf,
+        out: PathBuf,
+        /// 대상 언어
+        #[arg(long, default_value = "go", value_parser = parse_target_lang)]
+        lang: String,
+    },
+    /// 파일 하나를 변환해서 stdout 으로 출력 (path 가 - 면 stdin)
+    File {
+        path: PathBuf,
+        /// 대상 언어
+        #[arg(long, default_value = "go", value_parser = parse_target_lang)]
+        lang: String,
+    },
+    /// 프로젝트 전체 변환 (src -> out)
+    Project {
+        src: PathBuf,
+        out: PathBuf,
+        /// 대상 언어
+        #[arg(long, default_value = "go", value_parser = parse_target_lang)]
+        lang: String,
+    },
+    /// 코드 조각을 AST 로 파싱해 노드 단위로 컴파일 (생략 시 데모 노드)
+    Node {
+        source: Option<String>,
+        /// 대상 언어
+        #[arg(long, default_value = "go", value_parser = parse_target_lang)]
+        lang: String,
+        /// 출력 형식 (json: CompileResult 배열만 stdout 으로)
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+        /// text 출력에서 AI refined 를 보여주는 방식 (diff: base 대비 바뀐 부분만)
+        #[arg(long, value_enum, default_value_t = RefineView::Full)]
+        view: RefineView,
+    },
+    /// A -> B -> A 로 변환해서 원본과의 차이와 유사도를 보고 (차이가 크면 사람이 검토할 변환)
+    Roundtrip {
+        path: PathBuf,
+        /// 중간 대상 언어 (B)
+        #[arg(long, default_value = "go", value_parser = parse_target_lang)]
+        lang: String,
+        /// 원본 언어 (기본: 확장자)
+        #[arg(long)]
+        from: Option<String>,
+        /// 유사도 (0.0 ~ 1.0) 가 이보다 낮으면 실패
+        #[arg(long, default_value_t = DEFAULT_ROUNDTRIP_THRESHOLD)]
+        min_similarity: f64,
+    },
+    /// 소스 파일 보안 검사 (오프라인 규칙 + LLM)
+    Scan {
+        path: PathBuf,
+        /// 소스 언어 (기본: 확장자)
+        #[arg(long)]
+        lang: Option<String>,
+        /// 실행할 규칙 카테고리 (기본: 전부. secrets, injection, unsafe-math, resource-leak, memory-safety)
+        #[arg(long, value_delimiter = ',', value_name = "CATEGORIES")]
+        rules: Vec<String>,
+    },
+}
+
+// 실제 변환 전에 dry run 으로 대상 수 / 예상 토큰을 세고 확인 받음. 계속하면 true
+fn confirm_project_run<L: LLM + Sync>(llm: &L, opts: &TranspileOptions, src: &Path, out: &Path, lang: &str) -> bool {
+    let plan_opts = TranspileOptions {
+        dry_run: true,
+        estimate: Some(opts.estimate.unwrap_or(CONSERVATIVE_MODEL_INFO)),
+        progress: false,
+        ..opts.clone()
+    };
+    // 계획 단계의 [WOULD CONVERT] 줄은 숨김
+    let level = log::max_level();
+    log::set_max_level(level.min(LevelFilter::Warn));
+    let plan = transpile_project_in(&RealFs, llm, src, out, lang, &plan_opts);
+    log::set_max_level(level);
+    // 경로 오류 등은 실제 실행에서 그대로 보고
+    let Ok(plan) = plan else { return true };
+    if plan.planned.is_empty() {
+        return true;
+    }
+    let tokens: usize = plan.estimated.iter().map(|(_, n)| n).sum();
+    ask_yes_no(&format!(
+        "About to transpile {} files (~{} estimated tokens) to {}. Continue?",
+        plan.planned.len(),
+        tokens,
+        language_name(lang)
+    ))
+}
+
+// [y/N] 질문. y / yes 만 true
+fn ask_yes_no(question: &str) -> bool {
+    print!("{} [y/N] ", question);
+    let _ = io::stdout().flush();
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes")
+}
+
+// --clean: 출력 디렉토리를 통째로 지움. 원본을 품은 디렉토리는 거부. 계속 진행하면 true
+fn clean_output_dir(cli: &Cli, src: &Path, out_dir: &Path, dry_run: bool) -> bool {
+    if !out_dir.exists() {
+        return true;
+    }
+    // 원본 경로가 없으면 지우지 않고 실제 실행이 오류를 보고하게 둠
+    let (Ok(real_src), Ok(real_out)) = (fs::canonicalize(src), fs::canonicalize(out_dir)) else { return true };
+    if real_src.starts_with(&real_out) {
+        error!("[CLEAN] refusing to delete {}: it contains the source directory", out_dir.display());
+        return false;
+    }
+    if dry_run {
+        info!("[CLEAN] would remove {}", out_dir.display());
+        return true;
+    }
+    if !cli.yes {
+        if !io::stdin().is_terminal() {
+            error!("[CLEAN] pass --yes to delete {} without an interactive terminal", out_dir.display());
+            return false;
+        }
+        if !ask_yes_no(&format!("Delete everything in {}?", out_dir.display())) {
+            return false;
+        }
+    }
+    match fs::remove_dir_all(out_dir) {
+        Ok(()) => {
+            info!("[CLEAN] removed {}", out_dir.display());
+            true
+        }
+        Err(e) => {
+            error!("[CLEAN] cannot remove {}: {}", out_dir.display(), e);
+            false
+        }
+    }
+}
+
+// 프로젝트 변환 + 요약 + golden 비교. 실패면 true
+fn run_project<L: LLM + Sync>(llm: &L, cli: &Cli, opts: &TranspileOptions, src: &Path, out: &Path, lang: &str) -> bool {
+    println!("\n=== PROJECT TRANSPILER ===");
+    let layout = OutputLayout {
+        run_stamp: cli.run_dir.then(utc_timestamp),
+        per_language: cli.per_language,
+    };
+    let out_dir = layout.resolve(out, lang);
+    if cli.clean && !clean_output_dir(cli, src, &out_dir, opts.dry_run) {
+        println!("[ABORTED] nothing was transpiled");
+        return true;
+    }
+    // 큰 디렉토리를 실수로 돌려서 과금되지 않도록 대화형 실행에서만 확인
+    let interactive = io::stdin().is_terminal() && !cli.yes && !opts.dry_run;
+    if interactive && !confirm_project_run(llm, opts, src, &out_dir, lang) {
+        println!("[ABORTED] nothing was transpiled");
+        return true;
+    }
+    // 확인 프롬프트가 끝난 뒤에 설치 (프롬프트에서는 Ctrl-C 가 그대로 종료)
+    let opts = &TranspileOptions { abort: opts.abort.clone().or_else(|| Some(install_interrupt_handler())), ..opts.clone() };
+    let mut failed = false;
+    let report = match transpile_project(llm, &src.to_string_lossy(), &out_dir.to_string_lossy(), lang, opts) {
+        Ok(r) => r,
+        Err(e) => {
+            error!("[ERROR] {}", e);
+            failed = true;
+            TranspileReport::default()
+        }
+    };
+    if opts.dry_run {
+        return failed;
+    }
+    if report.status == RunStatus::Cancelled {
+        println!(
+            "interrupted: {} files converted, {} pending (rerun the same command to continue)",
+            report.converted,
+            report.pending.len()
+        );
+        for p in &report.pending {
+            println!("  {}", p.display());
+        }
+        failed = true;
+    }
+    let fixed = report
+        .files
+        .iter()
+        .filter(|f| f.imports.as_ref().is_some_and(ImportFix::changed))
+        .count();
+    if opts.fix_imports {
+        println!("imports auto-fixed in {}/{} files", fixed, report.files.len());
+    }
+    if !report.secrets.is_empty() {
+        println!("possible secrets flagged in {} files", report.secrets.len());
+    }
+    if report.smoke.as_ref().is_some_and(SmokeResult::is_failure) || report.status == RunStatus::QuotaExhausted {
+        failed = true;
+    }
+    if opts.validate {
+        let invalid: Vec<&FileRecord> =
+            report.files.iter().filter(|f| f.validation.as_ref().is_some_and(SmokeResult::is_failure)).collect();
+        let checked = report.files.iter().filter(|f| matches!(f.validation, Some(SmokeResult::Passed { .. }))).count();
+        println!("validation: {} passed, {} failed", checked, invalid.len());
+        for f in &invalid {
+            println!("  {}", f.output.display());
+        }
+        failed |= !invalid.is_empty();
+    }
+    print_provider_stats(&report.per_provider);
+    let package_fixes = report.files.iter().filter(|f| f.package_fix.is_some()).count();
+    if package_fixes > 0 {
+        println!("package declarations corrected in {} files", package_fixes);
+    }
+    if !report.truncated.is_empty() {
+        println!("truncated responses (not written): {} files", report.truncated.len());
+    }
+    if !report.failed_outputs.is_empty() {
+        println!("failed conversions (original kept as comments): {} files", report.failed_outputs.len());
+        for f in &report.failed_outputs {
+            println!("  {}", f.display());
+        }
+    }
+    if opts.explicit_types {
+        let typed: Vec<&FileRecord> = report.files.iter().filter(|f| f.loose_types > 0).collect();
+        let lines: usize = typed.iter().map(|f| f.loose_types).sum();
+        println!("loose/placeholder types left: {} lines in {} files", lines, typed.len());
+    }
+    for f in report.files.iter().filter(|f| !f.merged_from.is_empty()) {
+        println!("merged {} files -> {}", f.merged_from.len(), f.output.display());
+    }
+
+    if let Some(golden_dir) = &cli.check_golden {
+        let out_dir = out_dir.as_path();
+        if cli.accept_golden {
+            match accept_golden(out_dir, golden_dir) {
+                Ok(n) => println!("\n[GOLDEN] accepted {} files into {}", n, golden_dir.display()),
+                Err(e) => {
+                    error!("[GOLDEN] failed to update {}: {}", golden_dir.display(), e);
+                    failed = true;
+                }
+            }
+        } else {
+            println!("\n=== GOLDEN CHECK ===");
+            let report = check_golden(out_dir, golden_dir, cli.ignore_whitespace);
+            report.print();
+            failed |= !report.is_ok();
+        }
+    }
+    failed
+}
+
+// stdout 에는 변환된 코드만 (파이프 필터로 쓸 수 있게). 오류는 stderr
+fn run_file<L: LLM + Sync>(llm: &L, opts: &TranspileOptions, path: &Path, lang: &str) {
+    let stdin = path == Path::new("-");
+    let read = if stdin { io::read_to_string(io::stdin()) } else { fs::read_to_string(path) };
+    let src = match read {
+        Ok(s) => s,
+        Err(e) => {
+            error!("[ERROR] {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    };
+    // 빈 프롬프트를 API 에 보내지 않음
+    if src.trim().is_empty() {
+        let input = if stdin { "stdin".into() } else { path.display().to_string() };
+        error!("[ERROR] {}: empty input, nothing to transpile", input);
+        std::process::exit(2);
+    }
+    // 터미널이면 응답을 받는 대로 보여주고, 정리한 코드가 그와 다를 때만 다시 출력. 파이프면 정리한 코드만
+    let live = io::stdout().is_terminal();
+    let mut streamed = String::new();
+    let mut echo = |token: &str| {
+        streamed.push_str(token);
+        let mut stdout = io::stdout();
+        let _ = stdout.write_all(token.as_bytes());
+        let _ = stdout.flush();
+    };
+    let source = (!stdin).then_some(path);
+    let result = transpile_str_streaming(opts, llm, &src, lang, source, live.then_some(&mut echo as &mut dyn FnMut(&str)));
+    if live && !streamed.is_empty() && !streamed.ends_with('\n') {
+        println!();
+    }
+    match result {
+        Ok(out) => {
+            if !live || out.code.trim() != streamed.trim() {
+                if live {
+                    info!("[FILE] cleaned output:");
+                }
+                println!("{}", out.code);
+            }
+            if let Some(r) = out.validation.filter(SmokeResult::is_failure) {
+                error!("[VALIDATE] {}: {}", path.display(), r);
+                std::process::exit(1);
+            }
+        }
+        Err(e) => {
+            error!("[ERROR] {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+// 유사도가 기준보다 낮거나 변환이 실패하면 true
+fn run_roundtrip<L: LLM>(llm: &L, opts: &TranspileOptions, path: &Path, from: Option<&str>, lang: &str, min: f64) -> bool {
+    let src = match fs::read_to_string(path) {
+        Ok(s) => s,
+        Err(e) => {
+            error!("[ERROR] {}: {}", path.display(), e);
+            return true;
+        }
+    };
+    let ext = path.extension().map(|e| e.to_string_lossy().into_owned()).unwrap_or_default();
+    let from = match from {
+        Some(name) => name.parse::<Language>(),
+        None => Language::from_extension(&ext)
+            .ok_or_else(|| format!("cannot tell the source language of '{}' (use --from)", path.display())),
+    };
+    let from = match from {
+        Ok(l) => l,
+        Err(e) => {
+            error!("[ERROR] {}", e);
+            return true;
+        }
+    };
+
+    println!("\n=== ROUNDTRIP {} -> {} -> {} ===", from, language_name(lang), from);
+    let report = roundtrip_check_with(llm, opts, &src, from.id(), lang);
+    if let Some(e) = &report.error {
+        error!("[ERROR] {}", e);
+        return true;
+    }
+    print!("{}", report.diff);
+    println!(
+        "similarity: {:.1}% ({} lines changed, threshold {:.0}%)",
+        report.similarity * 100.0,
+        report.lines_changed,
+        min * 100.0
+    );
+    if !report.passed(min) {
+        warn!("[ROUNDTRIP] {} diverged from the original; review the {} output by hand", path.display(), language_name(lang));
+        return true;
+    }
+    false
+}
+
+fn run_node<L: LLM + Clone>(compiler: &Compiler<L>, source: Option<&str>, lang: &str, format: OutputFormat, view: RefineView) {
+    let nodes = match source {
+        Some(src) => match AstParser::parse(src) {
+            Ok(mut nodes) => {
+                analyze_features(&mut nodes);
+                nodes
+            }
+            Err(e) => {
+                error!("[ERROR] {}", e);
+                std::process::exit(2);
+            }
+        },
+        None => {
+            let mut node = Node::new(NodeKind::Identifier("x".into()));
+            node.meta.insert(FEATURE_GENERICS.into(), "true".into());
+            vec![node]
+        }
+    };
+    let results: Vec<CompileResult> = nodes.iter().map(|n| compiler.compile_node_result(n, lang, None)).collect();
+    if let Some(cache) = &compiler.cache {
+        debug!("[SEMANTIC CACHE] {} hits, {} misses", cache.hits(), cache.misses());
+    }
+    match format {
+        OutputFormat::Text => {
+            // 파이프 / NO_COLOR 면 색 없이
+            let color = clicolors_control::colors_enabled();
+            for r in &results {
+                println!("{}", r.render(view, color));
+            }
+            compiler.refine_stats.borrow().print();
+        }
+        OutputFormat::Json => match serde_json::to_string_pretty(&results) {
+            Ok(json) => println!("{}", json),
+            Err(e) => {
+                error!("[ERROR] {}", e);
+                std::process::exit(1);
+            }
+        },
+    }
+}
+
+fn run_scan<L: LLM>(llm: L, path: &Path, lang: Option<&str>, rules: &[String], offline: bool) {
+    let categories = match parse_rule_categories(rules) {
+        Ok(c) => c,
+        Err(e) => {
+            error!("[ERROR] {}", e);
+            std::process::exit(2);
+        }
+    };
+    let src = match fs::read_to_string(path) {
+        Ok(s) => s,
+        Err(e) => {
+            error!("[ERROR] cannot read {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    };
+    let ext = path.extension().map(|e| e.to_string_lossy().to_string()).unwrap_or_default();
+    let lang = lang.unwrap_or(&ext);
+
+    let findings = SecurityAI::new(llm)
+        .with_persona(persona_from_env())
+        .with_rule_categories(categories)
+        .with_offline(offline)
+        .analyze_source(&src, lang);
+    println!("=== SECURITY SCAN: {} ===", path.display());
+    for f in &findings {
+        println!("{}", f);
+    }
+    println!("{} findings", findings.len());
+}
+
+// ==========================================================
+// MAIN
+// ==========================================================
+fn main() {
+    install_panic_hook();
+    let cli = Cli::parse();
+    init_logging(log_level(cli.quiet, cli.verbose));
+
+    // 코드 / json 을 stdout 으로 내보내는 명령은 그대로 파이프로 넘길 수 있게 배너 없이
+    let piped_output = matches!(cli.command, Some(Command::Node { format: OutputFormat::Json, .. } | Command::File { .. }));
+    if !cli.quiet && !piped_output {
+        println!("==============================================");
+        println!("        INTELLIGENT COMPILER AI ENGINE");
+        println!("==============================================");
+    }
+
+    let provider = provider_from_env();
+    // offline 이면 API 키를 찾거나 묻지도 않음
+    let llm: Arc<dyn LLM + Send + Sync> = if cli.offline {
+        Arc::new(OfflineLLM)
+    } else {
+        match make_llm(&provider) {
+            Ok(l) => Arc::from(l),
+            Err(e) => {
+                error!("[ERROR] {}", e);
+                std::process::exit(2);
+            }
+        }
+    };
+    // TPM 은 프로젝트 변환의 TokenBudget 이 세므로 여기서는 RPM 만
+    let rpm = cli.rpm_limit.filter(|&n| n > 0).or_else(rpm_limit_from_env);
+    let max_concurrency = cli.max_concurrency.filter(|&n| n > 0);
+    let llm = if rpm.is_some() || max_concurrency.is_some() {
+        let mut limited = RateLimitedLLM::new(llm);
+        if let Some(rpm) = rpm {
+            limited = limited.with_rpm(rpm);
+        }
+        if let Some(n) = max_concurrency {
+            limited = limited.with_max_concurrency(n);
+        }
+        Arc::new(limited) as Arc<dyn LLM + Send + Sync>
+    } else {
+        llm
+    };
+
+    if let Some(Command::Scan { path, lang, rules }) = &cli.command {
+        run_scan(llm, path, lang.as_deref(), rules, cli.offline);
+        return;
+    }
+    let mut redaction = None;
+    if cli.redact || !cli.redact_pattern.is_empty() {
+        let mut r = Redactor::with_defaults();
+        for pat in &cli.redact_pattern {
+            if let Err(e) = r.add_pattern(pat) {
+                error!("[ERROR] invalid --redact-pattern '{}': {}", pat, e);
+                std::process::exit(2);
+            }
+        }
+        r.restore = cli.restore_redacted;
+        redaction = Some(r);
+    }
+
+    // CLI 규칙이 환경변수 규칙보다 먼저 평가됨
+    let routes: Result<Vec<RoutingRule>, String> = cli.route.iter().map(|r| RoutingRule::parse(r)).collect();
+    let routes = match (routes, routes_from_env()) {
+        (Ok(mut cli_routes), Ok(env_routes)) => {
+            cli_routes.extend(env_routes);
+            cli_routes
+        }
+        (Err(e), _) | (_, Err(e)) => {
+            error!("[ERROR] invalid model route: {}", e);
+            std::process::exit(2);
+        }
+    };
+
+    // 설정된 모델 확인 (모르는 모델은 시작할 때 한 번 경고)
+    if provider == "openai" {
+        model_info_or_default(&model_from_env().unwrap_or_else(|| DEFAULT_MODEL.into()));
+    }
+    for r in &routes {
+        model_info_or_default(&r.model);
+    }
+
+    let only_exts: Vec<String> = cli
+        .ext
+        .iter()
+        .map(|e| e.trim().trim_start_matches('.').to_lowercase())
+        .filter(|e| !e.is_empty())
+        .collect();
+    for e in &only_exts {
+        if Language::from_extension(e).is_none() {
+            warn!("[WARN] --ext {} is not a convertible extension ({})", e, convertible_exts().join(", "));
+        }
+    }
+
+    let version_ai = VersionAI::discover();
+    let mut target_versions = HashMap::new();
+    // 언어 없이 준 --target-version 은 선택한 대상 언어에 적용
+    let chosen_lang = match &cli.command {
+        Some(
+            Command::Watch { lang, .. }
+            | Command::File { lang, .. }
+            | Command::Project { lang, .. }
+            | Command::Roundtrip { lang, .. }
+            | Command::Node { lang, .. },
+        ) => lang.as_str(),
+        _ => "go",
+    };
+    for spec in &cli.target_version {
+        match parse_target_version(spec, chosen_lang).and_then(|(lang, ver)| {
+            version_ai.validate(&lang, &ver)?;
+            Ok((lang, ver))
+        }) {
+            Ok((lang, ver)) => {
+                target_versions.insert(lang, ver);
+            }
+            Err(e) => {
+                error!("[ERROR] {}", e);
+                std::process::exit(2);
+            }
+        }
+    }
+
+    let path_filter = match PathFilter::parse(&cli.include, &cli.exclude) {
+        Ok(f) => f,
+        Err(e) => {
+            error!("[ERROR] {}", e);
+            std::process::exit(2);
+        }
+    };
+
+    let output_encoding = match cli.output_encoding.as_deref().map(parse_output_encoding) {
+        None => None,
+        Some(Ok(enc)) => enc,
+        Some(Err(e)) => {
+            error!("[ERROR] {}", e);
+            std::process::exit(2);
+        }
+    };
+
+    // --prompt-template 은 모든 언어 쌍에 그 템플릿, 아니면 --prompts 또는 prompts.toml 로 쌍별 조정
+    let prompt_templates = match (&cli.prompt_template, &cli.prompts) {
+        (Some(path), _) => match fs::read_to_string(path) {
+            Ok(t) => {
+                if !t.contains("{content}") {
+                    warn!("[TEMPLATE] {} has no {{content}} placeholder; the source is appended at the end", path.display());
+                }
+                PromptTemplates::uniform(t)
+            }
+            Err(e) => {
+                error!("[ERROR] cannot read prompt template {}: {}", path.display(), e);
+                std::process::exit(2);
+            }
+        },
+        (None, Some(path)) => match PromptTemplates::load(path) {
+            Ok(t) => t,
+            Err(e) => {
+                error!("[ERROR] invalid prompt templates {}: {}", path.display(), e);
+                std::process::exit(2);
+            }
+        },
+        (None, None) => PromptTemplates::discover(),
+    };
+
+    let merge = match cli.merge.as_deref().map(MergeMode::parse) {
+        None => None,
+        Some(Ok(m)) => Some(m),
+        Some(Err(e)) => {
+            error!("[ERROR] {}", e);
+            std::process::exit(2);
+        }
+    };
+
+    let opts = TranspileOptions {
+        persona: persona_from_env(),
+        target_versions,
+        fidelity: cli.fidelity,
+        explicit_types: cli.explicit_types,
+        tpm_limit: cli.tpm_limit.filter(|&n| n > 0).or_else(tpm_limit_from_env),
+        candidates: n_from_env(),
+        // golden 비교는 항상 새 결과가 필요
+        on_conflict: if cli.overwrite || cli.force || cli.check_golden.is_some() {
+            ConflictPolicy::Overwrite
+        } else {
+            cli.on_conflict
+        },
+        fix_imports: cli.fix_imports,
+        secrets: if cli.allow_secrets {
+            SecretPolicy::Allow
+        } else if cli.redact_secrets {
+            SecretPolicy::Redact
+        } else {
+            SecretPolicy::Block
+        },
+        redaction,
+        routes,
+        finetune_export: cli.export_finetune.clone(),
+        only_exts,
+        path_filter,
+        output_encoding,
+        // project 실행은 run_project 가 Ctrl-C token 을 넣음. 라이브러리 사용자는 직접 AbortToken 을 넘김
+        abort: None,
+        merge,
+        window_tokens: cli.window_tokens,
+        prompt_templates,
+        smoke_build: cli.smoke_build.then(|| Duration::from_secs(cli.smoke_timeout)),
+        validate: cli.validate,
+        progress: !cli.quiet,
+        jobs: cli.jobs,
+        parallel_min_files: cli.parallel_min_files,
+        no_gitignore: cli.no_gitignore,
+        follow_symlinks: cli.follow_symlinks,
+        force: cli.force,
+        max_file_size: Some(cli.max_file_size),
+        dry_run: cli.dry_run || cli.estimate,
+        estimate: cli.estimate.then(|| match cli.price_per_1k {
+            Some(price) => ModelInfo { input_per_1k: price, output_per_1k: price, ..CONSERVATIVE_MODEL_INFO },
+            None if provider == "openai" => model_info_or_default(&model_from_env().unwrap_or_else(|| DEFAULT_MODEL.into())),
+            None => CONSERVATIVE_MODEL_INFO,
+        }),
+    };
+    // 파일 변환은 LLM 이 있어야 함 (dry run / 견적은 요청을 보내지 않으므로 허용)
+    let transpiles = match &cli.command {
+        Some(Command::Watch { .. } | Command::File { .. } | Command::Roundtrip { .. }) => true,
+        Some(Command::Project { .. }) => !opts.dry_run,
+        _ => false,
+    };
+    if cli.offline && transpiles {
+        error!("[ERROR] --offline cannot transpile files (use --dry-run, or the node / scan commands)");
+        std::process::exit(2);
+    }
+    if let Some(Command::Watch { src, out, lang }) = &cli.command {
+        if let Err(e) = watch_project(&llm, src, out, lang, &opts) {
+            error!("[ERROR] {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Some(Command::File { path, lang }) = &cli.command {
+        run_file(&llm, &opts, path, lang);
+        return;
+    }
+    if let Some(Command::Roundtrip { path, lang, from, min_similarity }) = &cli.command {
+        let failed = run_roundtrip(&llm, &opts, path, from.as_deref(), lang, *min_similarity);
+        std::process::exit(failed as i32);
+    }
+    if let Some(Command::Project { src, out, lang }) = &cli.command {
+        let failed = run_project(&llm, &cli, &opts, src, out, lang);
+        std::process::exit(failed as i32);
+    }
+
+    let compiler = Compiler::new(llm.clone())
+        .with_persona(opts.persona.clone())
+        .with_target_versions(opts.target_versions.clone())
+        .with_version_ai(version_ai)
+        .with_offline(cli.offline)
+        // node 명령은 이름만 다른 노드를 여러 개 넘길 수 있음
+        .with_semantic_cache();
+    if let Some(Command::Node { source, lang, format, view }) = &cli.command {
+        run_node(&compiler, source.as_deref(), lang, *format, *view);
+        return;
+    }
+
+    // dry run 은 LLM 을 부르는 데모를 건너뜀
+    if !opts.dry_run {
+        // Test Node
+        run_node(&compiler, None, "go", OutputFormat::Text, RefineView::Full);
+
+        if cli.offline {
+            println!("\n[OFFLINE] skipped the file and project transpiler demos");
+            println!("\nFINISHED.");
+            return;
+        }
+
+        // Test File
+        println!("\n=== FILE TRANSPILER ===");
+        let sample = "fn add(a: i32, b: i32) -> i32 { a + b }";
+        match transpile_str(&opts, &llm, sample, "go", None) {
+            Ok(out) => println!("{}", out.code),
+            Err(e) => error!("[ERROR] {}", e),
+        }
+    }
+
+    // Test Project
+    let failed = run_project(&llm, &cli, &opts, Path::new("src"), Path::new("output_go"), "go");
+    if opts.dry_run {
+        std::process::exit(failed as i32);
+    }
+
+    println!("\nFINISHED.");
 
     #[cfg(target_os = "windows")]
     {
@@ -446,4 +9184,486 @@ fn main() {
         let mut s = String::new();
         let _ = io::stdin().read_line(&mut s);
     }
+
+    if failed {
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ident(x: &str) -> Node {
+        Node::new(NodeKind::Identifier(x.into()))
+    }
+
+    fn bin(op: &str, left: Node, right: Node) -> Node {
+        Node::new(NodeKind::BinaryOp { op: op.into(), left: Box::new(left), right: Box::new(right) })
+    }
+
+    // 출력 -> 다시 파싱 -> 원래 트리와 같은지
+    fn assert_roundtrip(node: &Node) {
+        let printed = node.to_string();
+        let parsed = AstParser::parse(&printed).unwrap_or_else(|e| panic!("{}: {}", printed, e));
+        assert_eq!(parsed.len(), 1, "{}", printed);
+        assert!(node.structurally_eq(&parsed[0]), "{} reparsed as {:?}", printed, parsed[0]);
+    }
+
+    #[test]
+    fn printer_roundtrips_through_parser() {
+        let cases = vec![
+            ident("x"),
+            Node::new(NodeKind::Integer(42)),
+            Node::new(NodeKind::Integer(-7)),
+            Node::new(NodeKind::Number(2.0)),
+            Node::new(NodeKind::Number(0.25)),
+            Node::new(NodeKind::Number(1e20)),
+            bin("+", ident("a"), ident("b")),
+            // (a - b) - c 와 a - (b - c) 는 다른 트리
+            bin("-", bin("-", ident("a"), ident("b")), ident("c")),
+            bin("-", ident("a"), bin("-", ident("b"), ident("c"))),
+            bin("*", bin("+", ident("a"), Node::new(NodeKind::Integer(1))), ident("b")),
+            bin("+", ident("a"), bin("*", ident("b"), Node::new(NodeKind::Number(0.5)))),
+            bin("/", ident("a"), bin("%", ident("b"), ident("c"))),
+            bin("-", ident("a"), Node::new(NodeKind::Integer(-1))),
+            Node::new(NodeKind::Function { name: "empty".into(), args: vec![], body: vec![] }),
+            Node::new(NodeKind::Function {
+                name: "add".into(),
+                args: vec!["a".into(), "b".into()],
+                body: vec![bin("+", ident("a"), ident("b")), bin("*", ident("a"), Node::new(NodeKind::Integer(2)))],
+            }),
+        ];
+        for node in &cases {
+            assert_roundtrip(node);
+        }
+    }
+
+    #[test]
+    fn parsed_programs_print_back_to_the_same_tree() {
+        for src in ["1 + 2 * 3", "(1 + 2) * 3", "a - (b - c)", "-x * 2.5", "fn f(a, b) { a * (b + 1); a % b }"] {
+            for node in AstParser::parse(src).unwrap() {
+                assert_roundtrip(&node);
+            }
+        }
+    }
+
+    #[test]
+    fn structurally_eq_ignores_meta() {
+        let mut a = bin("+", ident("a"), ident("b"));
+        a.meta.insert(FEATURE_GENERICS.into(), "true".into());
+        assert!(a.structurally_eq(&bin("+", ident("a"), ident("b"))));
+        assert!(!a.structurally_eq(&bin("+", ident("b"), ident("a"))));
+        assert!(!Node::new(NodeKind::Integer(1)).structurally_eq(&Node::new(NodeKind::Number(1.0))));
+    }
+
+    #[test]
+    fn semantic_cache_reuses_alpha_equivalent_nodes() {
+        let llm = MockLLM::with_responses([
+            ("Rewrite in idiomatic", "sum := __ic_v0 + __ic_v1"),
+            ("Security check", "[]"),
+        ]);
+        let compiler = Compiler::new(llm.clone()).with_semantic_cache();
+        let ab = compiler.compile_node_result(&bin("+", ident("a"), ident("b")), "go", Some("1.21"));
+        let xy = compiler.compile_node_result(&bin("+", ident("x"), ident("y")), "go", Some("1.21"));
+
+        let cache = compiler.cache.as_ref().unwrap();
+        assert_eq!((cache.hits(), cache.misses()), (1, 1));
+        assert_eq!(cache.len(), 1);
+        assert_eq!(ab.refined_code, "sum := a + b");
+        assert_eq!(xy.refined_code, "sum := x + y");
+        // refine + security 한 번씩만
+        assert_eq!(llm.calls().len(), 2);
+    }
+
+    fn policy(strategy: BackoffStrategy) -> RetryPolicy {
+        RetryPolicy {
+            strategy,
+            max_retries: 10,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(1_000),
+        }
+    }
+
+    fn delays_ms(p: &RetryPolicy, attempts: u32) -> Vec<u128> {
+        (0..attempts).map(|n| p.delay(n).as_millis()).collect()
+    }
+
+    #[test]
+    fn backoff_sequences_are_capped_at_max_delay() {
+        assert_eq!(delays_ms(&policy(BackoffStrategy::Exponential), 6), [100, 200, 400, 800, 1_000, 1_000]);
+        assert_eq!(delays_ms(&policy(BackoffStrategy::Fixed), 4), [100, 100, 100, 100]);
+        assert_eq!(delays_ms(&policy(BackoffStrategy::Fibonacci), 7), [100, 100, 200, 300, 500, 800, 1_000]);
+        // 아주 큰 attempt 도 overflow 없이 상한
+        assert_eq!(policy(BackoffStrategy::Exponential).delay(200), Duration::from_millis(1_000));
+        assert_eq!(policy(BackoffStrategy::Fibonacci).delay(200), Duration::from_millis(1_000));
+    }
+
+    #[test]
+    fn jitter_stays_within_the_exponential_envelope() {
+        let p = policy(BackoffStrategy::ExponentialWithJitter);
+        for attempt in 0..8 {
+            let envelope = policy(BackoffStrategy::Exponential).delay(attempt);
+            assert_eq!(p.base_delay_for(attempt), envelope);
+            for _ in 0..20 {
+                assert!(p.delay(attempt) <= envelope, "attempt {}: {:?} > {:?}", attempt, p.delay(attempt), envelope);
+            }
+        }
+    }
+
+    #[test]
+    fn backoff_strategy_names() {
+        assert_eq!(BackoffStrategy::parse("exponential-with-jitter"), Some(BackoffStrategy::ExponentialWithJitter));
+        assert_eq!(BackoffStrategy::parse(" FIB "), Some(BackoffStrategy::Fibonacci));
+        assert_eq!(BackoffStrategy::parse("linear"), None);
+    }
+
+    // 출력 트리의 파일 경로 (manifest 제외)
+    fn outputs(fs: &MemoryFs, root: &str) -> Vec<String> {
+        fs.files()
+            .into_keys()
+            .filter(|p| p.starts_with(root) && !p.ends_with(MANIFEST_FILE))
+            .map(|p| p.to_string_lossy().replace('\\', "/"))
+            .collect()
+    }
+
+    fn go_llm() -> MockLLM {
+        MockLLM::with_responses(Vec::<(String, String)>::new()).with_fallback("package main\n\nfunc main() {}\n")
+    }
+
+    #[test]
+    fn project_walk_mirrors_tree_and_skips_build_dirs() {
+        let fs = MemoryFs::new()
+            .with_file("/src/main.py", "print(1)\n")
+            .with_file("/src/pkg/util.py", "def f():\n    return 1\n")
+            .with_file("/src/node_modules/dep/index.js", "module.exports = 1\n")
+            .with_file("/src/target/gen.py", "print(2)\n")
+            .with_file("/src/.git/hooks/pre-commit.py", "print(3)\n");
+        let llm = go_llm();
+        let report =
+            transpile_project_in(&fs, &llm, Path::new("/src"), Path::new("/out"), "go", &TranspileOptions::default())
+                .unwrap();
+
+        assert_eq!(outputs(&fs, "/out"), ["/out/main.go", "/out/pkg/util.go"]);
+        assert_eq!(report.converted, 2);
+        assert_eq!(llm.calls().len(), 2);
+        // 원본은 그대로
+        assert_eq!(fs.read_to_string(Path::new("/src/main.py")).unwrap(), "print(1)\n");
+    }
+
+    #[test]
+    fn project_walk_keeps_existing_outputs() {
+        let fs = MemoryFs::new()
+            .with_file("/src/main.py", "print(1)\n")
+            .with_file("/src/other.py", "print(2)\n")
+            .with_file("/out/main.go", "// hand edited\n");
+        let llm = go_llm();
+        let report =
+            transpile_project_in(&fs, &llm, Path::new("/src"), Path::new("/out"), "go", &TranspileOptions::default())
+                .unwrap();
+
+        assert_eq!(fs.read_to_string(Path::new("/out/main.go")).unwrap(), "// hand edited\n");
+        assert!(fs.exists(Path::new("/out/other.go")));
+        assert_eq!((report.converted, report.skipped), (1, 1));
+        assert_eq!(llm.calls().len(), 1);
+
+        // overwrite 면 다시 변환
+        let opts = TranspileOptions { on_conflict: ConflictPolicy::Overwrite, ..Default::default() };
+        transpile_project_in(&fs, &llm, Path::new("/src"), Path::new("/out"), "go", &opts).unwrap();
+        assert_ne!(fs.read_to_string(Path::new("/out/main.go")).unwrap(), "// hand edited\n");
+    }
+
+    #[test]
+    fn output_filename_replaces_only_the_last_extension() {
+        let cases = [
+            ("math.py", "go", "math.go"),
+            ("utils.rs", "go", "utils.go"),
+            ("foo.test.ts", "go", "foo.test.go"),
+            ("Makefile", "go", "Makefile.go"),
+            ("src/lib/engine.cpp", "rust", "engine.rs"),
+        ];
+        for (path, lang, expected) in cases {
+            assert_eq!(output_filename(Path::new(path), lang), expected, "{} -> {}", path, lang);
+        }
+    }
+
+    fn func(name: &str, args: &[&str], body: Vec<Node>) -> Node {
+        Node::new(NodeKind::Function { name: name.into(), args: args.iter().map(|a| a.to_string()).collect(), body })
+    }
+
+    #[test]
+    fn function_nodes_generate_typed_base_code() {
+        let add = func("add", &["a", "b"], vec![bin("+", ident("a"), ident("b"))]);
+        assert_eq!(BaseGenerator.generate(&add, Language::Go), "func add(a int, b int) int {\n\treturn (a + b)\n}");
+        assert_eq!(BaseGenerator.generate(&add, Language::Cpp), "auto add(auto a, auto b) {\n    return (a + b);\n}");
+        assert_eq!(BaseGenerator.generate(&add, Language::Swift), "func add(_ a: Int, _ b: Int) -> Int {\n    return (a + b)\n}");
+
+        // 소수 리터럴이 있으면 실수 타입
+        let scale = func("scale", &["x"], vec![bin("*", ident("x"), Node::new(NodeKind::Number(0.5)))]);
+        assert_eq!(BaseGenerator.generate(&scale, Language::Go), "func scale(x float64) float64 {\n\treturn (x * 0.5)\n}");
+        assert_eq!(BaseGenerator.generate(&scale, Language::Cpp), "auto scale(auto x) {\n    return (x * 0.5);\n}");
+        assert_eq!(
+            BaseGenerator.generate(&scale, Language::Swift),
+            "func scale(_ x: Double) -> Double {\n    return (x * 0.5)\n}"
+        );
+    }
+
+    #[test]
+    fn function_bodies_discard_all_but_the_last_expression() {
+        let two = func(
+            "f",
+            &["a"],
+            vec![
+                bin("+", ident("a"), Node::new(NodeKind::Integer(1))),
+                bin("*", ident("a"), Node::new(NodeKind::Integer(2))),
+            ],
+        );
+        assert_eq!(BaseGenerator.generate(&two, Language::Go), "func f(a int) int {\n\t_ = (a + 1)\n\treturn (a * 2)\n}");
+        assert_eq!(BaseGenerator.generate(&two, Language::Cpp), "auto f(auto a) {\n    (a + 1);\n    return (a * 2);\n}");
+        assert_eq!(
+            BaseGenerator.generate(&two, Language::Swift),
+            "func f(_ a: Int) -> Int {\n    _ = (a + 1)\n    return (a * 2)\n}"
+        );
+
+        let noop = func("noop", &[], vec![]);
+        assert_eq!(BaseGenerator.generate(&noop, Language::Go), "func noop() {}");
+        assert_eq!(BaseGenerator.generate(&noop, Language::Cpp), "auto noop() {}");
+        assert_eq!(BaseGenerator.generate(&noop, Language::Swift), "func noop() {}");
+
+        // 산술이 없으면 타입을 정할 근거가 없음
+        let id = func("id", &["a"], vec![ident("a")]);
+        assert_eq!(BaseGenerator.generate(&id, Language::Go), "func id(a any) any {\n\treturn a\n}");
+        assert_eq!(BaseGenerator.generate(&id, Language::Swift), "func id(_ a: Any) -> Any {\n    return a\n}");
+    }
+
+    #[test]
+    fn fix_imports_keeps_code_on_error_and_strips_fences() {
+        let code = "fn main() {}\n";
+        let failing = MockLLM::with_responses(Vec::<(String, String)>::new()).with_fallback("(API ERROR: 500)");
+        assert_eq!(fix_imports(&failing, code, "rust"), (code.to_string(), ImportFix::Llm { changed: false }));
+        let empty = MockLLM::with_responses(Vec::<(String, String)>::new()).with_fallback("  \n");
+        assert_eq!(fix_imports(&empty, code, "rust"), (code.to_string(), ImportFix::Llm { changed: false }));
+
+        let fenced = MockLLM::with_responses(Vec::<(String, String)>::new())
+            .with_fallback("```rust\nuse std::fs;\nfn main() {}\n```");
+        let (fixed, how) = fix_imports(&fenced, code, "rust");
+        assert_eq!(fixed, "use std::fs;\nfn main() {}\n");
+        assert_eq!(how, ImportFix::Llm { changed: true });
+
+        let same = MockLLM::with_responses(Vec::<(String, String)>::new()).with_fallback(format!("```\n{}```", code));
+        assert_eq!(fix_imports(&same, code, "rust").1, ImportFix::Llm { changed: false });
+    }
+
+    #[test]
+    fn sha256_hex_matches_known_vectors() {
+        assert_eq!(sha256_hex(b""), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        assert_eq!(sha256_hex(b"abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+    }
+
+    #[test]
+    fn cache_key_includes_provider_identity() {
+        let ollama = |model: &str| OllamaLLM::with_model("http://127.0.0.1:11434", model);
+        let a = CachingLLM::with_dir(ollama("llama3"), "cache");
+        let b = CachingLLM::with_dir(ollama("qwen2.5-coder"), "cache");
+        let parts = ["", "system", "prompt"];
+        assert_ne!(a.path_for(&parts), b.path_for(&parts));
+        assert_eq!(a.path_for(&parts), CachingLLM::with_dir(ollama("llama3"), "cache").path_for(&parts));
+
+        // Box / Arc 로 감싸도 같은 key
+        let boxed: Box<dyn LLM> = Box::new(ollama("llama3"));
+        assert_eq!(CachingLLM::with_dir(boxed, "cache").path_for(&parts), a.path_for(&parts));
+        let limited = RateLimitedLLM::new(ollama("llama3"));
+        assert_eq!(limited.cache_identity(), ollama("llama3").cache_identity());
+    }
+
+    #[test]
+    fn static_security_rules_scan_the_refined_code() {
+        let llm = MockLLM::with_responses([
+            ("Rewrite in idiomatic rust", "fn add(a: i64, b: i64) -> i64 {\n    unsafe { a.unchecked_add(b) }\n}"),
+            ("Rewrite in idiomatic cpp", "char buf[8];\nstrcpy(buf, a);"),
+            ("Security check", "[]"),
+        ]);
+        let compiler = Compiler::new(llm.clone());
+        let node = bin("+", ident("a"), ident("b"));
+
+        let rust = compiler.compile_node_result(&node, "rust", None);
+        assert_eq!(
+            rust.security_findings.iter().map(|f| f.message.as_str()).collect::<Vec<_>>(),
+            ["unsafe code bypasses the borrow checker"]
+        );
+        let cpp = compiler.compile_node_result(&node, "cpp", None);
+        assert_eq!(cpp.security_findings.len(), 1);
+        assert_eq!(cpp.security_findings[0].severity, Severity::High);
+
+        // 같은 노드라도 base 코드 (a + b) 에는 걸리는 규칙이 없음
+        let offline = Compiler::new(llm.clone()).with_offline(true).compile_node_result(&node, "cpp", None);
+        assert!(offline.ai_skipped && offline.security_findings.is_empty());
+        assert!(offline.render(RefineView::Full, false).contains("Security (offline rules only):\n(no findings)"));
+
+        let calls = llm.calls().len();
+        let offline = SecurityAI::new(llm.clone()).with_offline(true);
+        let batch = offline.analyze_all(&[(&node, "strcpy(buf, a);"), (&node, "a + b")], "cpp");
+        assert_eq!(batch.iter().map(Vec::len).collect::<Vec<_>>(), [1, 0]);
+        assert_eq!(llm.calls().len(), calls);
+    }
+
+    #[test]
+    fn transpile_str_streaming_passes_the_raw_response_through() {
+        let llm = MockLLM::with_responses(Vec::<(String, String)>::new()).with_fallback("```go\nx := 1\n```");
+        let mut seen = String::new();
+        let out = transpile_str_streaming(&TranspileOptions::default(), &llm, "x = 1\n", "go", None, Some(&mut |t: &str| {
+            seen.push_str(t)
+        }))
+        .unwrap();
+        assert_eq!(seen, "```go\nx := 1\n```");
+        assert_eq!(out.code, "x := 1\n");
+        assert!(out.fences_stripped);
+    }
+
+    // chunk 를 나눠 넣어도 화면에 보이는 결과가 split_reasoning 의 답과 같아야 함
+    #[test]
+    fn reasoning_filter_hides_think_spans_split_across_chunks() {
+        let stream = |chunks: &[&str]| {
+            let mut filter = ReasoningFilter::default();
+            let mut shown: String = chunks.iter().map(|c| filter.feed(c)).collect();
+            shown.push_str(&filter.finish());
+            shown
+        };
+        let full = "<think>plan the loop</think>\n\nfor i in 0..n { a < b; }";
+        let answer = split_reasoning(full).0;
+        assert_eq!(stream(&[full]), answer);
+        let by_char: Vec<String> = full.chars().map(String::from).collect();
+        assert_eq!(stream(&by_char.iter().map(String::as_str).collect::<Vec<_>>()), answer);
+        assert_eq!(stream(&["<thin", "king>x</thi", "nking>ok"]), "ok");
+        // 태그가 아닌 < 와 닫히지 않은 태그는 그대로
+        assert_eq!(stream(&["a <", "= b"]), "a <= b");
+        assert_eq!(stream(&["<think>never closed"]), "<think>never closed");
+    }
+
+    #[test]
+    fn ollama_requests_time_out_on_a_hung_server() {
+        // 연결은 받지만 응답하지 않는 서버
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let llm = OllamaLLM::with_model(url, "llama3").with_timeout(Duration::from_millis(300));
+        let started = Instant::now();
+        let response = llm.predict("hello");
+        assert_eq!(response, LlmError::Timeout(Duration::from_millis(300)).to_string());
+        assert!(started.elapsed() < Duration::from_secs(5));
+        let stats = llm.provider_stats();
+        assert_eq!(stats.values().map(|s| (s.requests, s.errors)).collect::<Vec<_>>(), [(1, 1)]);
+        drop(listener);
+    }
+
+    // 요청 하나를 받아서 body 를 JSON 으로 응답하는 서버. 주소를 돌려줌
+    fn serve_json_once(body: &'static str) -> String {
+        use std::io::Read;
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let (mut conn, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 4096];
+            // 헤더 끝과 Content-Length 만큼의 본문까지 읽음
+            loop {
+                let n = conn.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..n]);
+                let text = String::from_utf8_lossy(&request);
+                if let Some(end) = text.find("\r\n\r\n") {
+                    let length = text[..end]
+                        .lines()
+                        .find_map(|l| l.to_ascii_lowercase().strip_prefix("content-length:").map(|v| v.trim().to_string()))
+                        .and_then(|v| v.parse::<usize>().ok())
+                        .unwrap_or(0);
+                    if request.len() >= end + 4 + length {
+                        break;
+                    }
+                }
+                if n == 0 {
+                    break;
+                }
+            }
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            conn.write_all(response.as_bytes()).unwrap();
+        });
+        url
+    }
+
+    #[test]
+    fn ollama_records_provider_stats() {
+        let url = serve_json_once(
+            r#"{"message":{"role":"assistant","content":"x := 1"},"done_reason":"stop","prompt_eval_count":12,"eval_count":5}"#,
+        );
+        let llm = OllamaLLM::with_model(url.clone(), "llama3");
+        assert_eq!(llm.predict("hello"), "x := 1");
+
+        let stats = llm.provider_stats();
+        let s = &stats[&format!("{}/api/chat (llama3)", url)];
+        assert_eq!((s.requests, s.prompt_tokens, s.completion_tokens, s.errors), (1, 12, 5, 0));
+    }
+
+    #[test]
+    fn transpile_str_detects_the_source_language_from_the_path() {
+        let llm = MockLLM::with_responses(Vec::<(String, String)>::new()).with_fallback("x := 1");
+        let options = TranspileOptions::default();
+        transpile_str(&options, &llm, "x = 1\n", "go", Some(Path::new("x.py"))).unwrap();
+        transpile_str(&options, &llm, "x = 1\n", "go", None).unwrap();
+        let calls = llm.calls();
+        assert!(calls[0].contains("Python"), "{}", calls[0]);
+        assert!(!calls[1].contains("Python"), "{}", calls[1]);
+    }
+
+    #[test]
+    fn every_language_gets_its_own_base_code() {
+        let decls: Vec<String> = Language::ALL.iter().map(|&l| BaseGenerator.generate(&ident("x"), l)).collect();
+        assert_eq!(
+            decls,
+            ["let x;", "auto x;", "int x;", "x = None", "var x any", "let x: any;", "let x;", "var x: Any", "Object x;"]
+        );
+
+        let add = func("add", &["a", "b"], vec![bin("+", ident("a"), ident("b"))]);
+        assert_eq!(BaseGenerator.generate(&add, Language::Rust), "fn add(a: i64, b: i64) -> i64 {\n    (a + b)\n}");
+        assert_eq!(BaseGenerator.generate(&add, Language::Python), "def add(a, b):\n    return (a + b)");
+        assert_eq!(BaseGenerator.generate(&add, Language::C), "long add(long a, long b) {\n    return (a + b);\n}");
+        assert_eq!(
+            BaseGenerator.generate(&add, Language::TypeScript),
+            "function add(a: number, b: number): number {\n    return (a + b);\n}"
+        );
+        assert_eq!(base_code(&add, "klingon"), "/* unknown language 'klingon' */");
+    }
+
+    #[test]
+    fn token_budget_wait_releases_the_lock_and_stops_on_abort() {
+        let budget = Arc::new(Mutex::new(TokenBudget::new(10)));
+        assert!(TokenBudget::acquire_shared(&budget, 10, None));
+
+        let abort = AbortToken::new();
+        let waiter = {
+            let (budget, abort) = (budget.clone(), abort.clone());
+            std::thread::spawn(move || TokenBudget::acquire_shared(&budget, 5, Some(&abort)))
+        };
+        std::thread::sleep(Duration::from_millis(100));
+        // 기다리는 동안에도 다른 worker 가 lock 을 잡을 수 있어야 함
+        assert!(budget.try_lock().is_ok());
+        let started = Instant::now();
+        abort.cancel();
+        assert!(!waiter.join().unwrap());
+        assert!(started.elapsed() < Duration::from_secs(1));
+        // 취소된 요청은 예산을 쓰지 않음
+        assert_eq!(budget.lock().unwrap().window.len(), 1);
+    }
+
+    #[test]
+    fn built_in_rule_patterns_all_compile() {
+        for rule in TEXT_RULES.iter().chain(LANGUAGE_RULES.iter().map(|(_, r)| r)) {
+            // id 가 겹치면 다른 규칙의 regex 를 쓰게 됨
+            assert_eq!(rule_regex(rule).map(Regex::as_str), Some(rule.pattern), "{}", rule.id);
+        }
+        assert_eq!(scan_language_rules("strcpy(buf, src);\nputs(buf);", "cpp").len(), 1);
+        assert_eq!(detect_language(Path::new(""), "#include <vector>\nint main() {}"), Some("C++".into()));
+    }
 }